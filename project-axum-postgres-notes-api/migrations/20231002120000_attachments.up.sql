@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS attachments (
+        id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4 ()),
+        note_id UUID NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+        filename VARCHAR(255) NOT NULL,
+        content_type VARCHAR(255) NOT NULL,
+        size BIGINT NOT NULL,
+        sha256 CHAR(64) NOT NULL,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW ()
+    );
+
+CREATE INDEX IF NOT EXISTS attachments_note_id_idx ON attachments (note_id);
+
+CREATE INDEX IF NOT EXISTS attachments_sha256_idx ON attachments (sha256);
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS note_links (
+        source_id UUID NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+        target_title VARCHAR(255) NOT NULL,
+        PRIMARY KEY (source_id, target_title)
+    );
+
+CREATE INDEX IF NOT EXISTS note_links_target_title_idx ON note_links (target_title);
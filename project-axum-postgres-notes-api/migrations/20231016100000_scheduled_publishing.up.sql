@@ -0,0 +1,12 @@
+-- Add up migration script here
+ALTER TABLE notes
+ADD COLUMN IF NOT EXISTS publish_at TIMESTAMP WITH TIME ZONE,
+ADD COLUMN IF NOT EXISTS unpublish_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS notes_publish_at_idx ON notes (publish_at)
+WHERE
+    publish_at IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS notes_unpublish_at_idx ON notes (unpublish_at)
+WHERE
+    unpublish_at IS NOT NULL;
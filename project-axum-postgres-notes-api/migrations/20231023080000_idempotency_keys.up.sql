@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS idempotency_keys (
+        key VARCHAR(255) PRIMARY KEY NOT NULL,
+        request_hash CHAR(64) NOT NULL,
+        status_code SMALLINT,
+        response_body BYTEA,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW (),
+            expires_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL
+    );
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
@@ -0,0 +1,21 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS note_audit (
+        id BIGSERIAL PRIMARY KEY NOT NULL,
+        -- No foreign key: entries must outlive the notes they describe.
+        note_id UUID NOT NULL,
+        action VARCHAR(20) NOT NULL,
+        actor VARCHAR(255),
+        client_ip VARCHAR(45),
+        request_id VARCHAR(255),
+        diff JSONB NOT NULL,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW ()
+    );
+
+CREATE INDEX IF NOT EXISTS note_audit_note_id_idx ON note_audit (note_id, created_at);
+
+CREATE INDEX IF NOT EXISTS note_audit_actor_idx ON note_audit (actor, created_at);
+
+CREATE INDEX IF NOT EXISTS note_audit_created_at_idx ON note_audit (created_at);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS notes_content_trgm_idx;
+
+DROP INDEX IF EXISTS notes_title_trgm_idx;
+
+DROP EXTENSION IF EXISTS pg_trgm;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS notes_title_trgm_idx ON notes USING GIN (title gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS notes_content_trgm_idx ON notes USING GIN (content gin_trgm_ops);
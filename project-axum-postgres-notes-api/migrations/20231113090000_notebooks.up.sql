@@ -0,0 +1,26 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS notebooks (
+        id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4 ()),
+        parent_id UUID REFERENCES notebooks (id),
+        name VARCHAR(255) NOT NULL,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW (),
+            updated_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW ()
+    );
+
+CREATE INDEX IF NOT EXISTS notebooks_parent_id_idx ON notebooks (parent_id);
+
+-- Sibling names are unique; top-level notebooks count as siblings.
+CREATE UNIQUE INDEX IF NOT EXISTS notebooks_parent_name_idx ON notebooks (
+    COALESCE(parent_id, '00000000-0000-0000-0000-000000000000'),
+    name
+);
+
+ALTER TABLE notes
+ADD COLUMN IF NOT EXISTS notebook_id UUID REFERENCES notebooks (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS notes_notebook_id_idx ON notes (notebook_id);
@@ -0,0 +1,45 @@
+-- Add up migration script here
+CREATE TABLE
+    IF NOT EXISTS webhooks (
+        id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4 ()),
+        url TEXT NOT NULL,
+        secret VARCHAR(255) NOT NULL,
+        events TEXT[] NOT NULL,
+        active BOOLEAN NOT NULL DEFAULT TRUE,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW (),
+            updated_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW ()
+    );
+
+-- The outbox: rows are written in the same transaction as the note change and
+-- kept afterwards as the delivery log.
+CREATE TABLE
+    IF NOT EXISTS webhook_deliveries (
+        id BIGSERIAL PRIMARY KEY NOT NULL,
+        webhook_id UUID NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+        event VARCHAR(40) NOT NULL,
+        payload JSONB NOT NULL,
+        -- pending, delivered or dead
+        status VARCHAR(20) NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW (),
+            last_status_code SMALLINT,
+            last_error TEXT,
+            created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT NOW (),
+            delivered_at TIMESTAMP
+        WITH
+            TIME ZONE
+    );
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, created_at);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+WHERE
+    status = 'pending';
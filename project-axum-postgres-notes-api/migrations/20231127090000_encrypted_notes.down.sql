@@ -0,0 +1,9 @@
+-- Add down migration script here
+ALTER TABLE notes
+DROP CONSTRAINT IF EXISTS notes_encrypted_content_check;
+
+ALTER TABLE notes
+DROP COLUMN IF EXISTS content_ciphertext,
+DROP COLUMN IF EXISTS content_nonce,
+DROP COLUMN IF EXISTS content_salt,
+DROP COLUMN IF EXISTS encrypted;
@@ -0,0 +1,24 @@
+-- Add up migration script here
+ALTER TABLE notes
+ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN IF NOT EXISTS content_salt BYTEA,
+ADD COLUMN IF NOT EXISTS content_nonce BYTEA,
+ADD COLUMN IF NOT EXISTS content_ciphertext BYTEA;
+
+-- Encrypted notes keep nothing readable in `content`.
+ALTER TABLE notes
+ADD CONSTRAINT notes_encrypted_content_check CHECK (
+    (
+        encrypted
+        AND content = ''
+        AND content_salt IS NOT NULL
+        AND content_nonce IS NOT NULL
+        AND content_ciphertext IS NOT NULL
+    )
+    OR (
+        NOT encrypted
+        AND content_salt IS NULL
+        AND content_nonce IS NULL
+        AND content_ciphertext IS NULL
+    )
+);
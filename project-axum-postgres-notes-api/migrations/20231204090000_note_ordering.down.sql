@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS notes_order_idx;
+
+ALTER TABLE notes
+DROP COLUMN IF EXISTS position,
+DROP COLUMN IF EXISTS pinned;
@@ -0,0 +1,26 @@
+-- Add up migration script here
+ALTER TABLE notes
+ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE,
+-- Fractional index: notes sort by comparing keys byte by byte.
+ADD COLUMN IF NOT EXISTS position VARCHAR(255) COLLATE "C";
+
+-- Existing notes keep their creation order. Fixed-width keys compare like the
+-- numbers they spell, and the trailing digit keeps them from ending in '0'.
+UPDATE notes
+SET
+    position = numbered.position
+FROM
+    (
+        SELECT
+            id,
+            'a' || LPAD(ROW_NUMBER() OVER (ORDER BY created_at, id)::TEXT, 10, '0') || 'V' AS position
+        FROM
+            notes
+    ) AS numbered
+WHERE
+    notes.id = numbered.id;
+
+ALTER TABLE notes
+ALTER COLUMN position SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS notes_order_idx ON notes (pinned DESC, position, id);
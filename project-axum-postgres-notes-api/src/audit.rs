@@ -0,0 +1,222 @@
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use serde_json::{Map, Value};
+use sqlx::{Postgres, Transaction};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::model::NoteModel;
+use crate::AppState;
+
+/// Fields left out of diffs because every mutation touches them.
+const IGNORED_FIELDS: [&str; 1] = ["updatedAt"];
+/// Logged in place of the content of a note that is or was encrypted.
+const REDACTED: &str = "[redacted]";
+
+/// The authenticated user behind a request.
+///
+/// An authentication layer inserts this into the request extensions; without
+/// one, audit entries are recorded with no actor.
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+/// Who made a change and where it came from.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub actor: Option<String>,
+    pub client_ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl AuditContext {
+    /// Context for changes made by the server itself, e.g. the scheduler.
+    pub fn system(actor: &str) -> Self {
+        AuditContext {
+            actor: Some(actor.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded_for = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_string())
+            .filter(|_| state.config.trust_forwarded_for);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        Ok(AuditContext {
+            actor: parts.extensions.get::<Actor>().map(|a| a.0.clone()),
+            client_ip: forwarded_for.or(peer),
+            request_id: parts
+                .headers
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, async_graphql::Enum)]
+#[graphql(name = "NoteAction")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Publish,
+    Unpublish,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Publish => "publish",
+            Action::Unpublish => "unpublish",
+        }
+    }
+
+    /// Classifies an update, calling out changes to `published`.
+    pub fn for_update(old: &NoteModel, new: &NoteModel) -> Self {
+        match (old.published, new.published) {
+            (old, Some(true)) if old != Some(true) => Action::Publish,
+            (Some(true), new) if new != Some(true) => Action::Unpublish,
+            _ => Action::Update,
+        }
+    }
+}
+
+/// Records a change to a note. Updates that change nothing are skipped.
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    ctx: &AuditContext,
+    action: Action,
+    old: Option<&NoteModel>,
+    new: Option<&NoteModel>,
+) -> Result<(), sqlx::Error> {
+    let Some(note_id) = new.or(old).map(|note| note.id) else {
+        return Ok(());
+    };
+    // The log only tells that the content of an encrypted note changed, and
+    // never holds what the note stores sealed, before or after.
+    let encrypted = old.into_iter().chain(new).any(|note| note.encrypted);
+    let snapshot = |note: Option<&NoteModel>| {
+        let mut value = note.map_or(Value::Null, |note| serde_json::to_value(note).unwrap());
+        if let Some(fields) = value.as_object_mut().filter(|_| encrypted) {
+            fields.remove("content");
+        }
+        value
+    };
+    let mut diff = diff(&snapshot(old), &snapshot(new));
+    let sealing =
+        |note: Option<&NoteModel>| note.map(|note| (note.encrypted, note.content_salt.clone()));
+    if encrypted && sealing(old) != sealing(new) {
+        diff["content"] = redacted();
+    }
+    if diff.as_object().is_none_or(Map::is_empty) {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO note_audit (note_id, action, actor, client_ip, request_id, diff) VALUES ($1, $2, $3, $4, $5, $6)",
+        note_id,
+        action.as_str(),
+        ctx.actor,
+        ctx.client_ip,
+        ctx.request_id,
+        diff
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Redacts the content in every entry about the note, for when it gets
+/// encrypted.
+pub async fn redact_content(
+    tx: &mut Transaction<'_, Postgres>,
+    note_id: uuid::Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE note_audit SET diff = jsonb_set(diff, '{content}', $2) WHERE note_id = $1 AND diff ? 'content'",
+        note_id,
+        redacted()
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+fn redacted() -> Value {
+    serde_json::json!({ "old": REDACTED, "new": REDACTED })
+}
+
+/// Builds `{"field": {"old": ..., "new": ...}}` for every field that differs
+/// between two serialized notes. A missing side is treated as all-`null`.
+pub fn diff(old: &Value, new: &Value) -> Value {
+    let empty = Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for field in old.keys().chain(new.keys()) {
+        if IGNORED_FIELDS.contains(&field.as_str()) || changes.contains_key(field) {
+            continue;
+        }
+        let before = old.get(field).unwrap_or(&Value::Null);
+        let after = new.get(field).unwrap_or(&Value::Null);
+        if before != after {
+            changes.insert(
+                field.clone(),
+                serde_json::json!({ "old": before, "new": after }),
+            );
+        }
+    }
+    Value::Object(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_lists_changed_fields_only() {
+        let old = json!({"title": "a", "content": "b", "updatedAt": "t1"});
+        let new = json!({"title": "a", "content": "c", "updatedAt": "t2"});
+        assert_eq!(
+            diff(&old, &new),
+            json!({"content": {"old": "b", "new": "c"}})
+        );
+    }
+
+    #[test]
+    fn diff_against_nothing() {
+        let note = json!({"title": "a", "category": null});
+        assert_eq!(
+            diff(&Value::Null, &note),
+            json!({"title": {"old": null, "new": "a"}})
+        );
+        assert_eq!(
+            diff(&note, &Value::Null),
+            json!({"title": {"old": "a", "new": null}})
+        );
+    }
+}
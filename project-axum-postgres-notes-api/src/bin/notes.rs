@@ -0,0 +1,468 @@
+//! Command-line client for the notes API.
+//!
+//! Talks to `/api/v2` of a running server. The base URL and token are taken
+//! from `--url`/`--token`, then `NOTES_URL`/`NOTES_TOKEN`, then the config
+//! file at `$NOTES_CONFIG` or `~/.config/notes/config.toml`:
+//!
+//! ```toml
+//! base_url = "http://localhost:8000"
+//! token = "secret"
+//! ```
+
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+const DEFAULT_BASE_URL: &str = "http://localhost:8000";
+const TITLE_WIDTH: usize = 40;
+
+#[derive(Parser)]
+#[command(name = "notes", about = "Command-line client for the notes API")]
+struct Cli {
+    /// Base URL of the API server.
+    #[arg(long, env = "NOTES_URL", global = true)]
+    url: Option<String>,
+    /// Bearer token sent with every request.
+    #[arg(long, env = "NOTES_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
+    #[arg(short, long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// List notes, one page at a time.
+    List {
+        #[arg(long, default_value_t = 1)]
+        page: usize,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        #[arg(long)]
+        category: Option<String>,
+        #[arg(long)]
+        published: Option<bool>,
+    },
+    /// Show a note including its content.
+    Show { id: String },
+    /// Create a note. The content is read from stdin unless `--content` is given.
+    Create {
+        title: String,
+        #[arg(long)]
+        content: Option<String>,
+        #[arg(long)]
+        category: Option<String>,
+    },
+    /// Edit a note's content in `$EDITOR`.
+    Edit { id: String },
+    /// Delete a note.
+    Delete { id: String },
+    /// Publish a note now, or at a later time with `--at`.
+    Publish {
+        id: String,
+        /// RFC 3339 timestamp, e.g. 2024-01-31T09:00:00Z.
+        #[arg(long)]
+        at: Option<DateTime<Utc>>,
+    },
+    /// Search note titles and contents.
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct FileConfig {
+    base_url: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Note {
+    id: String,
+    title: String,
+    content: String,
+    category: Option<String>,
+    published: Option<bool>,
+    updated_at: Option<DateTime<Utc>>,
+    publish_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageMeta {
+    page: usize,
+    total: i64,
+    total_pages: i64,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    data: T,
+    meta: Option<PageMeta>,
+}
+
+struct Api {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Api {
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v2{}", self.base_url.trim_end_matches('/'), path)
+    }
+
+    /// Sends the request and returns the parsed body, or `None` for
+    /// `204 No Content`. Error envelopes become their `message`.
+    fn send(&self, request: RequestBuilder) -> Result<Option<Value>, String> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let text = response.text().map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            let message = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| format!("{} {}", status, text.trim()));
+            return Err(message);
+        }
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("unexpected response: {}", e))
+    }
+
+    fn get_note(&self, id: &str) -> Result<Value, String> {
+        self.send(self.client.get(self.url(&format!("/notes/{}", id))))?
+            .ok_or_else(|| "empty response".to_string())
+    }
+
+    fn patch_note(&self, id: &str, body: Value) -> Result<Value, String> {
+        let request = self
+            .client
+            .patch(self.url(&format!("/notes/{}", id)))
+            .json(&body);
+        self.send(request)?
+            .ok_or_else(|| "empty response".to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let file = load_config()?;
+    let (base_url, token) = resolve(cli.url, cli.token, file);
+    let api = Api {
+        client: Client::new(),
+        base_url,
+        token,
+    };
+    let output = cli.output;
+
+    match cli.command {
+        Cmd::List {
+            page,
+            limit,
+            category,
+            published,
+        } => {
+            let mut query = vec![("page", page.to_string()), ("limit", limit.to_string())];
+            if let Some(category) = category {
+                query.push(("category", category));
+            }
+            if let Some(published) = published {
+                query.push(("published", published.to_string()));
+            }
+            let body = api.send(api.client.get(api.url("/notes")).query(&query))?;
+            print_notes(output, body)
+        }
+        Cmd::Show { id } => print_note(output, Some(api.get_note(&id)?), true),
+        Cmd::Create {
+            title,
+            content,
+            category,
+        } => {
+            let content = match content {
+                Some(content) => content,
+                None => {
+                    let mut content = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut content)
+                        .map_err(|e| e.to_string())?;
+                    content
+                }
+            };
+            let body = json!({ "title": title, "content": content, "category": category });
+            let request = api.client.post(api.url("/notes")).json(&body);
+            print_note(output, api.send(request)?, false)
+        }
+        Cmd::Edit { id } => {
+            let note = api.get_note(&id)?;
+            let content = note["data"]["content"].as_str().unwrap_or_default();
+            let edited = edit_in_editor(content)?;
+            if edited == content {
+                eprintln!("No changes.");
+                return Ok(());
+            }
+            let note = api.patch_note(&id, json!({ "content": edited }))?;
+            print_note(output, Some(note), false)
+        }
+        Cmd::Delete { id } => {
+            api.send(api.client.delete(api.url(&format!("/notes/{}", id))))?;
+            if output == Output::Table {
+                eprintln!("Deleted {}.", id);
+            }
+            Ok(())
+        }
+        Cmd::Publish { id, at } => {
+            let body = match at {
+                Some(at) => json!({ "publishAt": at }),
+                None => json!({ "published": true }),
+            };
+            print_note(output, Some(api.patch_note(&id, body)?), false)
+        }
+        Cmd::Search { query, limit } => {
+            let request = api
+                .client
+                .get(api.url("/notes/search"))
+                .query(&[("q", query), ("limit", limit.to_string())]);
+            print_notes(output, api.send(request)?)
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("NOTES_CONFIG") {
+        return Some(path.into());
+    }
+    let config_dir = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+    Some(config_dir.join("notes").join("config.toml"))
+}
+
+/// Reads the config file. A missing file is the same as an empty one.
+fn load_config() -> Result<FileConfig, String> {
+    let Some(path) = config_path() else {
+        return Ok(FileConfig::default());
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FileConfig::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Flags and environment variables win over the config file.
+fn resolve(
+    url: Option<String>,
+    token: Option<String>,
+    file: FileConfig,
+) -> (String, Option<String>) {
+    (
+        url.or(file.base_url)
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+        token.or(file.token),
+    )
+}
+
+fn edit_in_editor(content: &str) -> Result<String, String> {
+    // The note may have been decrypted, so it goes in a new directory only we
+    // can enter; nobody else can read it or plant a symlink where it is written.
+    let dir = std::env::temp_dir().join(format!("notes-{}", uuid::Uuid::new_v4().simple()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| e.to_string())?;
+    let edited = edit_file(&dir.join("note.md"), content);
+    let _ = std::fs::remove_dir_all(&dir);
+    edited
+}
+
+fn edit_file(path: &Path, content: &str) -> Result<String, String> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| e.to_string())?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Run through the shell so that editors configured with flags work.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+        Ok(status) => Err(format!("{} exited with {}", editor, status)),
+        Err(e) => Err(format!("failed to run {}: {}", editor, e)),
+    }
+}
+
+fn print_notes(output: Output, body: Option<Value>) -> Result<(), String> {
+    let body = body.unwrap_or(Value::Null);
+    if output == Output::Json {
+        println!("{}", serde_json::to_string_pretty(&body).unwrap());
+        return Ok(());
+    }
+    let envelope: Envelope<Vec<Note>> =
+        serde_json::from_value(body).map_err(|e| format!("unexpected response: {}", e))?;
+    print!("{}", format_table(&envelope.data));
+    if let Some(meta) = envelope.meta {
+        println!(
+            "Page {} of {} ({} notes)",
+            meta.page,
+            meta.total_pages.max(1),
+            meta.total
+        );
+    }
+    Ok(())
+}
+
+fn print_note(output: Output, body: Option<Value>, with_content: bool) -> Result<(), String> {
+    let body = body.unwrap_or(Value::Null);
+    if output == Output::Json {
+        println!("{}", serde_json::to_string_pretty(&body).unwrap());
+        return Ok(());
+    }
+    let Envelope { data: note, .. }: Envelope<Note> =
+        serde_json::from_value(body).map_err(|e| format!("unexpected response: {}", e))?;
+    println!("ID:         {}", note.id);
+    println!("Title:      {}", note.title);
+    println!("Category:   {}", note.category.unwrap_or_default());
+    println!("Published:  {}", note.published.unwrap_or(false));
+    if let Some(publish_at) = note.publish_at {
+        println!("Publish at: {}", publish_at.to_rfc3339());
+    }
+    println!(
+        "Updated:    {}",
+        note.updated_at.map(|t| t.to_rfc3339()).unwrap_or_default()
+    );
+    if with_content {
+        println!("\n{}", note.content);
+    }
+    Ok(())
+}
+
+fn format_table(notes: &[Note]) -> String {
+    let mut rows = vec![[
+        "ID".to_string(),
+        "TITLE".to_string(),
+        "CATEGORY".to_string(),
+        "PUBLISHED".to_string(),
+        "UPDATED".to_string(),
+    ]];
+    for note in notes {
+        rows.push([
+            note.id.clone(),
+            truncate(&note.title, TITLE_WIDTH),
+            note.category.clone().unwrap_or_default(),
+            note.published.unwrap_or(false).to_string(),
+            note.updated_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+        ]);
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        return value.to_string();
+    }
+    let mut truncated: String = value.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_win_over_config_file() {
+        let file: FileConfig =
+            toml::from_str("base_url = \"http://notes:8000\"\ntoken = \"file\"").unwrap();
+        assert_eq!(
+            resolve(None, Some("flag".to_string()), file),
+            ("http://notes:8000".to_string(), Some("flag".to_string()))
+        );
+        assert_eq!(
+            resolve(None, None, FileConfig::default()),
+            (DEFAULT_BASE_URL.to_string(), None)
+        );
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let notes: Vec<Note> = serde_json::from_value(json!([
+            {"id": "1", "title": "Short", "content": "", "category": "", "published": true,
+             "updatedAt": "2023-10-01T12:00:00Z"},
+            {"id": "2", "title": "A considerably longer title that will not fit the column",
+             "content": "", "category": "work", "published": false, "updatedAt": null}
+        ]))
+        .unwrap();
+        let table = format_table(&notes);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ID  TITLE"));
+        assert!(lines[2].contains("A considerably longer title that will n…"));
+        assert_eq!(lines[0].find("PUBLISHED"), lines[1].find("true"));
+    }
+}
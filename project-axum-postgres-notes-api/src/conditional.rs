@@ -0,0 +1,174 @@
+use axum::http::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use axum::http::{HeaderMap, HeaderName};
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::model::NoteModel;
+
+/// Cache validators for a note representation, derived from `updated_at`.
+///
+/// The ETag is weak because the bytes on the wire depend on the negotiated
+/// compression, while the validator only tracks the notes themselves.
+#[derive(Debug, PartialEq)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    pub fn for_notes(notes: &[NoteModel]) -> Self {
+        let mut hasher = Sha256::new();
+        for note in notes {
+            hasher.update(note.id.as_bytes());
+            let updated_at = note.updated_at.or(note.created_at);
+            hasher.update(updated_at.map_or(0, |t| t.timestamp_micros()).to_be_bytes());
+        }
+        let digest = hex::encode(hasher.finalize());
+
+        Validators {
+            etag: format!("W/\"{}\"", &digest[..32]),
+            last_modified: notes
+                .iter()
+                .filter_map(|note| note.updated_at.or(note.created_at))
+                .max(),
+        }
+    }
+
+    /// Validators for one page of a paginated list. The ETag also covers
+    /// `total`, which changes when notes on other pages are added or removed.
+    /// `Last-Modified` is left out because such a removal does not move it.
+    pub fn for_page(notes: &[NoteModel], total: i64) -> Self {
+        let etag = Self::for_notes(notes).etag;
+        let digest = hex::encode(Sha256::digest(format!("{}/{}", etag, total)));
+        Validators {
+            etag: format!("W/\"{}\"", &digest[..32]),
+            last_modified: None,
+        }
+    }
+
+    /// Evaluates `If-None-Match` and, only when it is absent,
+    /// `If-Modified-Since`, as RFC 9110 prescribes for GET requests.
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || weak_tag(tag) == weak_tag(&self.etag));
+        }
+
+        let (Some(last_modified), Some(if_modified_since)) =
+            (self.last_modified, headers.get(IF_MODIFIED_SINCE))
+        else {
+            return false;
+        };
+        let Some(if_modified_since) = if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+        else {
+            return false;
+        };
+        // HTTP dates only carry whole seconds.
+        last_modified.timestamp() <= if_modified_since.timestamp()
+    }
+
+    pub fn headers(&self) -> Vec<(HeaderName, String)> {
+        let mut headers = vec![(ETAG, self.etag.clone())];
+        if let Some(last_modified) = self.last_modified {
+            headers.push((LAST_MODIFIED, http_date(last_modified)));
+        }
+        headers
+    }
+}
+
+fn weak_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    Utc.timestamp_opt(time.timestamp(), 0)
+        .unwrap()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn note(updated_at: &str) -> NoteModel {
+        NoteModel {
+            id: uuid::Uuid::nil(),
+            title: "title".to_string(),
+            content: "content".to_string(),
+            category: None,
+            published: Some(false),
+            created_at: None,
+            updated_at: Some(updated_at.parse().unwrap()),
+            publish_at: None,
+            unpublish_at: None,
+            notebook_id: None,
+            encrypted: false,
+            content_salt: None,
+            content_nonce: None,
+            content_ciphertext: None,
+            pinned: false,
+            position: "V".to_string(),
+        }
+    }
+
+    #[test]
+    fn etag_changes_with_updated_at() {
+        let before = Validators::for_notes(&[note("2023-09-14T10:00:00Z")]);
+        let after = Validators::for_notes(&[note("2023-09-14T10:00:01Z")]);
+        assert_ne!(before.etag, after.etag);
+        assert_eq!(
+            before,
+            Validators::for_notes(&[note("2023-09-14T10:00:00Z")])
+        );
+    }
+
+    #[test]
+    fn if_none_match() {
+        let validators = Validators::for_notes(&[note("2023-09-14T10:00:00Z")]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"other\", {}", validators.etag)).unwrap(),
+        );
+        assert!(validators.is_not_modified(&headers));
+
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        // A failed ETag match wins over a matching date.
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 14 Sep 2023 10:00:00 GMT"),
+        );
+        assert!(!validators.is_not_modified(&headers));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let validators = Validators::for_notes(&[note("2023-09-14T10:00:00.500Z")]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 14 Sep 2023 10:00:00 GMT"),
+        );
+        assert!(validators.is_not_modified(&headers));
+
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 14 Sep 2023 09:59:59 GMT"),
+        );
+        assert!(!validators.is_not_modified(&headers));
+        assert_eq!(
+            validators.headers()[1].1,
+            "Thu, 14 Sep 2023 10:00:00 GMT".to_string()
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_url: String,
+    pub attachments_dir: PathBuf,
+    /// Maximum size of a single uploaded file, in bytes.
+    pub attachments_max_size: usize,
+    /// Accepted MIME types, e.g. `image/*` or `application/pdf`. Empty means any.
+    pub attachments_allowed_types: Vec<String>,
+    /// Upper bound on how long the publishing scheduler sleeps between scans.
+    pub scheduler_interval: Duration,
+    /// How long responses to `Idempotency-Key` requests are kept for replay.
+    pub idempotency_ttl: Duration,
+    /// Take the client IP from `X-Forwarded-For`; only safe behind a proxy.
+    pub trust_forwarded_for: bool,
+    /// Attempts after which a webhook delivery is given up as dead.
+    pub webhook_max_attempts: i32,
+    /// How long a webhook receiver gets to respond.
+    pub webhook_timeout: Duration,
+    /// Port for plain HTTP, which serves the API or only redirects to HTTPS.
+    pub http_port: u16,
+    /// HTTPS is served only when a certificate and key are configured.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain; reloaded whenever it changes on disk.
+    pub cert_path: PathBuf,
+    /// PEM private key; reloaded along with the certificate.
+    pub key_path: PathBuf,
+    pub https_port: u16,
+    /// Answer plain HTTP with redirects instead of serving the API on it.
+    pub redirect_http: bool,
+    /// `max-age` of the `Strict-Transport-Security` header.
+    pub hsts_max_age: Duration,
+}
+
+impl Config {
+    pub fn init() -> Config {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let attachments_dir = std::env::var("ATTACHMENTS_DIR")
+            .unwrap_or_else(|_| "./data/attachments".to_string())
+            .into();
+        let attachments_max_size = std::env::var("ATTACHMENTS_MAX_SIZE")
+            .map(|v| v.parse().expect("ATTACHMENTS_MAX_SIZE must be a number"))
+            .unwrap_or(10 * 1024 * 1024);
+        let attachments_allowed_types = std::env::var("ATTACHMENTS_ALLOWED_TYPES")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let scheduler_interval = std::env::var("SCHEDULER_INTERVAL_SECS")
+            .map(|v| v.parse().expect("SCHEDULER_INTERVAL_SECS must be a number"))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let idempotency_ttl = std::env::var("IDEMPOTENCY_TTL_SECS")
+            .map(|v| v.parse().expect("IDEMPOTENCY_TTL_SECS must be a number"))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(24 * 60 * 60));
+        let trust_forwarded_for = std::env::var("TRUST_FORWARDED_FOR")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let webhook_max_attempts = std::env::var("WEBHOOK_MAX_ATTEMPTS")
+            .map(|v| v.parse().expect("WEBHOOK_MAX_ATTEMPTS must be a number"))
+            .unwrap_or(10);
+        let webhook_timeout = std::env::var("WEBHOOK_TIMEOUT_SECS")
+            .map(|v| v.parse().expect("WEBHOOK_TIMEOUT_SECS must be a number"))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+        let http_port = std::env::var("PORT")
+            .map(|v| v.parse().expect("PORT must be a port number"))
+            .unwrap_or(8000);
+        let tls = match (
+            std::env::var("TLS_CERT_PATH"),
+            std::env::var("TLS_KEY_PATH"),
+        ) {
+            (Ok(cert_path), Ok(key_path)) => Some(TlsConfig {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                https_port: std::env::var("HTTPS_PORT")
+                    .map(|v| v.parse().expect("HTTPS_PORT must be a port number"))
+                    .unwrap_or(8443),
+                redirect_http: std::env::var("HTTP_REDIRECT_TO_HTTPS")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                hsts_max_age: std::env::var("HSTS_MAX_AGE_SECS")
+                    .map(|v| v.parse().expect("HSTS_MAX_AGE_SECS must be a number"))
+                    .map(Duration::from_secs)
+                    .unwrap_or(Duration::from_secs(365 * 24 * 60 * 60)),
+            }),
+            (Err(_), Err(_)) => None,
+            _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+        };
+
+        Config {
+            database_url,
+            attachments_dir,
+            attachments_max_size,
+            attachments_allowed_types,
+            scheduler_interval,
+            idempotency_ttl,
+            trust_forwarded_for,
+            webhook_max_attempts,
+            webhook_timeout,
+            http_port,
+            tls,
+        }
+    }
+}
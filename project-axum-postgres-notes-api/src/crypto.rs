@@ -0,0 +1,171 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::HeaderName;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+
+use crate::model::NoteModel;
+
+// Content of encrypted notes is sealed with AES-256-GCM under a key derived
+// from the client's password with Argon2id, using a fresh salt and nonce every
+// time it is written. The note id is bound in as associated data, so sealed
+// content copied onto another note fails to open. Neither the password nor the
+// key is stored; a lost password means lost content.
+
+pub const NOTE_PASSWORD: HeaderName = HeaderName::from_static("x-note-password");
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// The password from `X-Note-Password`, if the request sent a non-empty one.
+#[derive(Debug, Clone, Default)]
+pub struct NotePassword(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for NotePassword {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(NotePassword(
+            parts
+                .headers
+                .get(NOTE_PASSWORD)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+                .map(str::to_string),
+        ))
+    }
+}
+
+impl NotePassword {
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+/// Sealed content as stored in the `content_*` columns.
+#[derive(Debug, Clone)]
+pub struct Sealed {
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl Sealed {
+    pub fn of(note: &NoteModel) -> Option<Sealed> {
+        Some(Sealed {
+            salt: note.content_salt.clone()?,
+            nonce: note.content_nonce.clone()?,
+            ciphertext: note.content_ciphertext.clone()?,
+        })
+    }
+}
+
+/// Key derivation is deliberately slow, so sealing and opening run on the
+/// blocking thread pool.
+pub async fn seal(password: &str, note_id: uuid::Uuid, plaintext: &str) -> Sealed {
+    let password = password.to_string();
+    let plaintext = plaintext.to_string();
+    tokio::task::spawn_blocking(move || seal_blocking(&password, note_id, &plaintext))
+        .await
+        .expect("Sealing note content panicked")
+}
+
+/// `None` when the password is wrong or the note is not encrypted.
+pub async fn open(password: &str, note: &NoteModel) -> Option<String> {
+    let password = password.to_string();
+    let note_id = note.id;
+    let sealed = Sealed::of(note)?;
+    tokio::task::spawn_blocking(move || open_blocking(&password, note_id, &sealed))
+        .await
+        .expect("Opening note content panicked")
+}
+
+fn seal_blocking(password: &str, note_id: uuid::Uuid, plaintext: &str) -> Sealed {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = vec![0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher(password, &salt)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: note_id.as_bytes(),
+            },
+        )
+        .expect("AES-GCM encryption cannot fail for note-sized input");
+    Sealed {
+        salt,
+        nonce,
+        ciphertext,
+    }
+}
+
+fn open_blocking(password: &str, note_id: uuid::Uuid, sealed: &Sealed) -> Option<String> {
+    if sealed.nonce.len() != NONCE_LEN {
+        return None;
+    }
+    let plaintext = cipher(password, &sealed.salt)
+        .decrypt(
+            Nonce::from_slice(&sealed.nonce),
+            Payload {
+                msg: &sealed.ciphertext,
+                aad: note_id.as_bytes(),
+            },
+        )
+        .ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+fn cipher(password: &str, salt: &[u8]) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .expect("Argon2 accepts any password and a 16-byte salt");
+    Aes256Gcm::new(&key.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sealed_content_opens_with_the_password_only() {
+        let id = uuid::Uuid::new_v4();
+        let sealed = seal_blocking("hunter22", id, "secret plans");
+        assert!(!sealed
+            .ciphertext
+            .windows(b"secret".len())
+            .any(|window| window == b"secret"));
+        assert_eq!(
+            open_blocking("hunter22", id, &sealed).as_deref(),
+            Some("secret plans")
+        );
+        assert_eq!(open_blocking("hunter2", id, &sealed), None);
+    }
+
+    #[test]
+    fn sealed_content_is_bound_to_its_note() {
+        let sealed = seal_blocking("hunter22", uuid::Uuid::new_v4(), "secret plans");
+        assert_eq!(
+            open_blocking("hunter22", uuid::Uuid::new_v4(), &sealed),
+            None
+        );
+    }
+
+    #[test]
+    fn every_seal_uses_fresh_randomness() {
+        let id = uuid::Uuid::new_v4();
+        let first = seal_blocking("hunter22", id, "secret plans");
+        let second = seal_blocking("hunter22", id, "secret plans");
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first.nonce, second.nonce);
+        assert_ne!(first.ciphertext, second.ciphertext);
+    }
+}
@@ -0,0 +1,46 @@
+use tokio::sync::broadcast;
+
+use crate::audit::Action;
+use crate::model::NoteModel;
+
+/// Events buffered per subscriber; a subscriber that falls further behind
+/// skips the oldest ones.
+const CAPACITY: usize = 256;
+
+/// A committed change to a note.
+#[derive(Debug, Clone, async_graphql::SimpleObject)]
+#[graphql(name = "NoteChange")]
+pub struct NoteEvent {
+    pub action: Action,
+    /// The note after the change, or as it was before it was deleted.
+    pub note: NoteModel,
+}
+
+/// Fans note changes out to live subscribers, e.g. GraphQL subscriptions.
+///
+/// Events live only in memory: subscribers see changes made by this instance
+/// after they subscribed, and nothing is replayed.
+#[derive(Clone)]
+pub struct NoteEvents {
+    sender: broadcast::Sender<NoteEvent>,
+}
+
+impl Default for NoteEvents {
+    fn default() -> Self {
+        NoteEvents {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl NoteEvents {
+    /// Call only once the change is committed.
+    pub fn publish(&self, action: Action, note: NoteModel) {
+        // Sending fails only when nobody is listening, which is fine.
+        let _ = self.sender.send(NoteEvent { action, note });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NoteEvent> {
+        self.sender.subscribe()
+    }
+}
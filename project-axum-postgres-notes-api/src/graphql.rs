@@ -0,0 +1,325 @@
+use async_graphql::http::{GraphiQLSource, WebSocketProtocols, WsMessage};
+use async_graphql::{
+    Context, Data, ErrorExtensions, InputObject, MaybeUndefined, Object, Schema, SimpleObject,
+    Subscription,
+};
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::header::SEC_WEBSOCKET_PROTOCOL;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures_util::{future, SinkExt, Stream, StreamExt};
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::audit::AuditContext;
+use crate::events::NoteEvent;
+use crate::model::NoteModel;
+use crate::response::{ApiError, PageMeta};
+use crate::schema::{CreateNoteSchema, NoteFilter, UpdateNoteSchema};
+use crate::service;
+use crate::AppState;
+
+pub type NotesSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Deeper queries are rejected before any resolver runs.
+const MAX_DEPTH: usize = 8;
+const MAX_LIMIT: usize = 100;
+
+pub fn build_schema() -> NotesSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// Resolvers read the application state and the caller's audit context from
+/// the per-request data, so the schema itself can be built once at startup.
+fn request_data(data: Arc<AppState>, ctx: AuditContext) -> Data {
+    let mut request_data = Data::default();
+    request_data.insert(data);
+    request_data.insert(ctx);
+    request_data
+}
+
+impl From<ApiError> for async_graphql::Error {
+    fn from(e: ApiError) -> Self {
+        async_graphql::Error::new(e.message).extend_with(|_, extensions| {
+            extensions.set("code", e.code.as_u16());
+        })
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct NotePage {
+    pub notes: Vec<NoteModel>,
+    pub meta: PageMeta,
+}
+
+/// Partial update; omitted fields are kept, and `null` clears a schedule or
+/// takes the note out of its notebook.
+#[derive(InputObject)]
+pub struct UpdateNoteInput {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub category: Option<String>,
+    pub published: Option<bool>,
+    pub encrypted: Option<bool>,
+    pub pinned: Option<bool>,
+    pub publish_at: MaybeUndefined<DateTime<Utc>>,
+    pub unpublish_at: MaybeUndefined<DateTime<Utc>>,
+    pub notebook_id: MaybeUndefined<uuid::Uuid>,
+}
+
+impl From<UpdateNoteInput> for UpdateNoteSchema {
+    fn from(input: UpdateNoteInput) -> Self {
+        UpdateNoteSchema {
+            title: input.title,
+            content: input.content,
+            category: input.category,
+            published: input.published,
+            encrypted: input.encrypted,
+            pinned: input.pinned,
+            publish_at: input.publish_at.into(),
+            unpublish_at: input.unpublish_at.into(),
+            notebook_id: input.notebook_id.into(),
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The content of an encrypted note is only filled in given its password.
+    async fn note(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+        password: Option<String>,
+    ) -> async_graphql::Result<Option<NoteModel>> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        match service::get_note(data, id).await {
+            Ok(note) => Ok(Some(service::unlock(note, password.as_deref()).await)),
+            Err(e) if e.code == StatusCode::NOT_FOUND => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn notes(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<NoteFilter>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> async_graphql::Result<NotePage> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        let filter = filter.unwrap_or_default();
+        let page = page.max(1);
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let notes = service::list_notes(data, &filter, page, limit)
+            .await
+            .map_err(ApiError::from)?;
+        let total = service::count_notes(data, &filter)
+            .await
+            .map_err(ApiError::from)?;
+        Ok(NotePage {
+            notes,
+            meta: PageMeta::new(page, limit, total),
+        })
+    }
+
+    async fn search_notes(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        #[graphql(default = 10)] limit: usize,
+    ) -> async_graphql::Result<Vec<NoteModel>> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        Ok(
+            service::search_notes(data, &query, limit.clamp(1, MAX_LIMIT))
+                .await
+                .map_err(ApiError::from)?,
+        )
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_note(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateNoteSchema,
+        password: Option<String>,
+    ) -> async_graphql::Result<NoteModel> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        let audit = ctx.data::<AuditContext>()?;
+        Ok(service::create_note(data, audit, input, password.as_deref()).await?)
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+        input: UpdateNoteInput,
+        #[graphql(default)] rewrite_links: bool,
+        password: Option<String>,
+    ) -> async_graphql::Result<NoteModel> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        let audit = ctx.data::<AuditContext>()?;
+        let password = password.as_deref();
+        Ok(service::update_note(data, audit, id, input.into(), rewrite_links, password).await?)
+    }
+
+    /// Returns the note as it was before deletion.
+    async fn delete_note(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> async_graphql::Result<NoteModel> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        let audit = ctx.data::<AuditContext>()?;
+        Ok(service::delete_note(data, audit, id).await?)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Changes committed after subscribing, optionally for a single note.
+    /// Events a slow subscriber could not keep up with are skipped.
+    async fn note_changed(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<uuid::Uuid>,
+    ) -> async_graphql::Result<impl Stream<Item = NoteEvent>> {
+        let data = ctx.data::<Arc<AppState>>()?;
+        Ok(
+            BroadcastStream::new(data.events.subscribe()).filter_map(move |event| {
+                future::ready(
+                    event
+                        .ok()
+                        .filter(|event| id.is_none_or(|id| event.note.id == id)),
+                )
+            }),
+        )
+    }
+}
+
+pub async fn graphql_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let request = request.data(data.clone()).data(ctx);
+    Json(data.graphql.execute(request).await)
+}
+
+pub async fn graphiql_handler() -> impl IntoResponse {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/api/graphql")
+            .subscription_endpoint("/api/graphql/ws")
+            .finish(),
+    )
+}
+
+pub async fn graphql_ws_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(',')
+                .find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok())
+        });
+    let Some(protocol) = protocol else {
+        return ApiError::fail(
+            StatusCode::BAD_REQUEST,
+            "Sec-WebSocket-Protocol must be graphql-transport-ws or graphql-ws!",
+        )
+        .into_response();
+    };
+
+    ws.protocols([protocol.sec_websocket_protocol()])
+        .on_upgrade(move |socket| serve_ws(socket, data, ctx, protocol))
+}
+
+async fn serve_ws(
+    socket: WebSocket,
+    data: Arc<AppState>,
+    ctx: AuditContext,
+    protocol: WebSocketProtocols,
+) {
+    let (mut sink, stream) = socket.split();
+    let incoming = stream
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(text.into_bytes()),
+                Ok(Message::Binary(bytes)) => Some(bytes),
+                _ => None,
+            })
+        });
+
+    let mut outgoing =
+        async_graphql::http::WebSocket::new(data.graphql.clone(), incoming, protocol)
+            .connection_data(request_data(data.clone(), ctx));
+    while let Some(message) = outgoing.next().await {
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        };
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn update_input_keeps_null_apart_from_missing() {
+        let input = UpdateNoteInput {
+            title: None,
+            content: None,
+            category: None,
+            published: None,
+            encrypted: None,
+            pinned: None,
+            publish_at: MaybeUndefined::Null,
+            unpublish_at: MaybeUndefined::Undefined,
+            notebook_id: MaybeUndefined::Undefined,
+        };
+        let schema: UpdateNoteSchema = input.into();
+        assert_eq!(schema.publish_at, Some(None));
+        assert_eq!(schema.unpublish_at, None);
+    }
+
+    #[test]
+    fn sdl_exposes_operations() {
+        let sdl = build_schema().sdl();
+        for field in [
+            "note(id: UUID!, password: String)",
+            "searchNotes(",
+            "createNote(input: CreateNoteInput!, password: String)",
+            "deleteNote(id: UUID!)",
+            "noteChanged(id: UUID)",
+        ] {
+            assert!(sdl.contains(field), "missing {}", field);
+        }
+    }
+}
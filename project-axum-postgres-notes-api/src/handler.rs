@@ -1,13 +1,37 @@
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::body::StreamBody;
+use axum::extract::{Multipart, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Response};
 use axum::Json;
 use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
 
-use crate::model::NoteModel;
-use crate::schema::{CreateNoteSchema, FilterOptions, UpdateNoteSchema};
+use crate::audit::AuditContext;
+use crate::conditional::Validators;
+use crate::crypto::NotePassword;
+use crate::idempotency;
+use crate::model::{AttachmentModel, AuditModel, GraphEdge, GraphNode, NoteModel};
+use crate::notebook;
+use crate::response::{
+    to_json_bytes, ApiError, ApiResponse, DeliveryData, DeliveryListData, DuplicateData,
+    NewWebhook, NoteData, NoteListResponse, NotebookData, NotebookTreeData, RelatedData,
+    WebhookData, WebhookListData,
+};
+use crate::schema::{
+    AuditFilterOptions, CheckDuplicateSchema, CreateNoteSchema, CreateNotebookSchema,
+    CreateWebhookSchema, DeleteNotebookOptions, DeliveryFilterOptions, FilterOptions,
+    MoveNoteSchema, NoteFilter, RelatedOptions, RotateKeySchema, UpdateNoteSchema,
+    UpdateNotebookSchema, UpdateOptions, UpdateWebhookSchema,
+};
+use crate::service::{self, collect_blob, ensure_note_exists, lock_blob};
+use crate::similarity;
+use crate::webhook;
 use crate::AppState;
 
+// Handlers for `/api/v1` (also served unversioned under `/api`). Response
+// shapes here are frozen; changes to them belong in `handler_v2`.
+
 pub async fn health_check_handler() -> impl IntoResponse {
     const MESSAGE: &str = "Simple CRUD API with Rust, SQLX, Postgres,and Axum";
     let json_response = serde_json::json!({
@@ -19,216 +43,790 @@ pub async fn health_check_handler() -> impl IntoResponse {
 
 pub async fn create_one_handler(
     State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    password: NotePassword,
+    headers: HeaderMap,
     Json(body): Json<CreateNoteSchema>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let query_result = sqlx::query_as!(
-        NoteModel,
-        "INSERT INTO notes (title, content, category) VALUES ($1, $2, $3) RETURNING *",
-        body.title.to_string(),
-        body.content.to_string(),
-        body.category.to_owned().unwrap_or("".to_string())
+) -> Result<Response, ApiError> {
+    let request_hash = idempotency::fingerprint(&("v1", &body));
+    idempotency::respond_once(&data, &headers, &request_hash, || async {
+        let result = service::create_note(&data, &ctx, body, password.as_deref()).await;
+        to_json_bytes(result.map(|note| (StatusCode::CREATED, ApiResponse::new(NoteData { note }))))
+    })
+    .await
+}
+
+pub async fn read_all_handler(
+    State(data): State<Arc<AppState>>,
+    opts: Option<Query<FilterOptions>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let fetch_failed = |_| ApiError {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        status: "fail",
+        message: "Something went wrong while fetching all notes!".to_string(),
+    };
+    let filter = NoteFilter::default();
+    let notes = service::list_notes(
+        &data,
+        &filter,
+        opts.page.unwrap_or(1),
+        opts.limit.unwrap_or(10),
     )
-    .fetch_one(&data.db)
-    .await;
+    .await
+    .map_err(fetch_failed)?;
+    let total = service::count_notes(&data, &filter)
+        .await
+        .map_err(fetch_failed)?;
 
-    match query_result {
-        Ok(note) => {
-            let json_response = serde_json::json!(
-                {
-                    "status": "success",
-                    "data": serde_json::json!(
-                        {
-                            "note": note
-                        }
-                    )
+    let validators = Validators::for_page(&notes, total);
+    if validators.is_not_modified(&headers) {
+        return Ok(not_modified(&validators));
+    }
+    Ok((
+        AppendHeaders(validators.headers()),
+        Json(NoteListResponse::new(notes)),
+    )
+        .into_response())
+}
+
+pub async fn read_one_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    password: NotePassword,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let note = service::get_note(&data, id)
+        .await
+        .map_err(|_| ApiError::note_not_found(id))?;
+
+    if note.encrypted && password.0.is_some() {
+        // Content read with a password must not be served from any cache.
+        let note = service::unlock(note, password.as_deref()).await;
+        return Ok((
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(ApiResponse::new(NoteData { note })),
+        )
+            .into_response());
+    }
+
+    let validators = Validators::for_notes(std::slice::from_ref(&note));
+    if validators.is_not_modified(&headers) {
+        return Ok(not_modified(&validators));
+    }
+    Ok((
+        AppendHeaders(validators.headers()),
+        Json(ApiResponse::new(NoteData { note })),
+    )
+        .into_response())
+}
+
+pub async fn update_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+    opts: Option<Query<UpdateOptions>>,
+    password: NotePassword,
+    Json(body): Json<UpdateNoteSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let note = service::update_note(
+        &data,
+        &ctx,
+        id,
+        body,
+        opts.rewrite_links.unwrap_or(false),
+        password.as_deref(),
+    )
+    .await?;
+    Ok(Json(ApiResponse::new(NoteData { note })))
+}
+
+pub async fn move_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<MoveNoteSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let note = service::move_note(&data, &ctx, id, body).await?;
+    Ok(Json(ApiResponse::new(NoteData { note })))
+}
+
+pub async fn rotate_key_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+    password: NotePassword,
+    Json(body): Json<RotateKeySchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let note =
+        service::rotate_key(&data, &ctx, id, password.as_deref(), &body.new_password).await?;
+    Ok(Json(ApiResponse::new(NoteData { note })))
+}
+
+pub async fn delete_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    service::delete_note(&data, &ctx, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn upload_attachment_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_note_exists(&data, id).await?;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ApiError::fail(e.status(), e.body_text()))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let filename = field
+            .file_name()
+            .map(sanitize_filename)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "attachment".to_string());
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_lowercase();
+        if !mime_allowed(&data.config.attachments_allowed_types, &content_type) {
+            return Err(ApiError::fail(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Attachments of type {} are not allowed!", content_type),
+            ));
+        }
+
+        let mut writer = data.blobs.writer().await?;
+        loop {
+            let chunk = match field.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    writer.abort().await?;
+                    return Err(ApiError::fail(e.status(), e.body_text()));
                 }
-            );
-            Ok((StatusCode::CREATED, Json(json_response)))
+            };
+            if writer.size() as usize + chunk.len() > data.config.attachments_max_size {
+                writer.abort().await?;
+                return Err(ApiError::fail(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Attachments may not be larger than {} bytes!",
+                        data.config.attachments_max_size
+                    ),
+                ));
+            }
+            writer.write(&chunk).await?;
         }
-        Err(e) => {
-            if e.to_string()
-                .contains("duplicate key value violates unique constraint")
-            {
-                let error_response = serde_json::json!(
-                    {
-                        "status": "fail",
-                        "message": "Note with that title already exists!"
-                    }
-                );
-                println!("❌ Returning error response...");
-                return Err((StatusCode::CONFLICT, Json(error_response)));
+        let staged = writer.finish().await?;
+
+        // The advisory lock keeps a concurrent delete from collecting the blob
+        // between moving it into place and referencing it.
+        let mut tx = data.db.begin().await?;
+        lock_blob(&mut tx, &staged.sha256).await?;
+        let sha256 = staged.sha256.clone();
+        let size = staged.size;
+        data.blobs.commit(staged).await?;
+        let inserted = sqlx::query_as!(
+            AttachmentModel,
+            "INSERT INTO attachments (note_id, filename, content_type, size, sha256) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            id,
+            filename,
+            content_type,
+            size,
+            sha256
+        )
+        .fetch_one(&mut *tx)
+        .await;
+        let stored = match inserted {
+            Ok(attachment) => tx.commit().await.map(|_| attachment),
+            Err(e) => {
+                let _ = tx.rollback().await;
+                Err(e)
+            }
+        };
+        let attachment = match stored {
+            Ok(attachment) => attachment,
+            Err(e) => {
+                // The blob is in place already, and nothing may reference it.
+                collect_blob(&data, &sha256).await?;
+                return Err(e.into());
             }
+        };
 
-            println!("❌ Returning error response...");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!(
+        let json_response = serde_json::json!(
+            {
+                "status": "success",
+                "data": serde_json::json!(
                     {
-                        "status": "error",
-                        "message": format!("{:?}", e)
+                        "attachment": attachment
                     }
-                )),
-            ))
-        }
+                )
+            }
+        );
+        return Ok((StatusCode::CREATED, Json(json_response)));
     }
+
+    Err(ApiError::fail(
+        StatusCode::BAD_REQUEST,
+        "Missing multipart field 'file'!",
+    ))
 }
 
-pub async fn read_all_handler(
+pub async fn read_attachments_handler(
     State(data): State<Arc<AppState>>,
-    opts: Option<Query<FilterOptions>>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let Query(opts) = opts.unwrap_or_default();
-    let page = opts.page.unwrap_or(1);
-    let limit = opts.limit.unwrap_or(10);
-    let offset = (page - 1) * limit;
-    let query_result = sqlx::query_as!(
-        NoteModel,
-        "SELECT * FROM notes ORDER by id LIMIT $1 OFFSET $2",
-        limit as i32,
-        offset as i32
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let attachments = sqlx::query_as!(
+        AttachmentModel,
+        "SELECT * FROM attachments WHERE note_id = $1 ORDER BY created_at",
+        id
     )
     .fetch_all(&data.db)
-    .await;
+    .await?;
 
-    if query_result.is_err() {
-        let error_response = serde_json::json!(
-            {
-                "status": "fail",
-                "message": "Something went wrong while fetching all notes!"
+    let json_response = serde_json::json!(
+        {
+            "status": "success",
+            "results": attachments.len(),
+            "attachments": attachments
+        }
+    );
+    Ok(Json(json_response))
+}
+
+pub async fn download_attachment_handler(
+    State(data): State<Arc<AppState>>,
+    Path((id, attachment_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let attachment = sqlx::query_as!(
+        AttachmentModel,
+        "SELECT * FROM attachments WHERE id = $1 AND note_id = $2",
+        attachment_id,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| {
+        ApiError::fail(
+            StatusCode::NOT_FOUND,
+            format!("Attachment with id {} not found!", attachment_id),
+        )
+    })?;
+
+    let size = attachment.size as u64;
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => match parse_range(value, size) {
+            Ok(range) => range,
+            Err(()) => {
+                return Ok((
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                )
+                    .into_response());
             }
+        },
+        None => None,
+    };
+
+    let mut file = tokio::fs::File::open(data.blobs.path(attachment.sha256.trim())).await?;
+    let (status, start, end) = match range {
+        Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+        None => (StatusCode::OK, 0, size.saturating_sub(1)),
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let body = StreamBody::new(ReaderStream::new(file.take(length)));
+
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, attachment.content_type.clone()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(&attachment.filename),
+            ),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::ETAG, format!("\"{}\"", attachment.sha256.trim())),
+        ],
+        body,
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, size).parse().unwrap(),
         );
-        println!("❌ Returning error response...");
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
     }
+    Ok(response)
+}
+
+pub async fn delete_attachment_handler(
+    State(data): State<Arc<AppState>>,
+    Path((id, attachment_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let sha256 = sqlx::query_scalar!(
+        "DELETE FROM attachments WHERE id = $1 AND note_id = $2 RETURNING sha256",
+        attachment_id,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| {
+        ApiError::fail(
+            StatusCode::NOT_FOUND,
+            format!("Attachment with id {} not found!", attachment_id),
+        )
+    })?;
+
+    collect_blob(&data, &sha256).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn read_links_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let targets = sqlx::query_scalar!(
+        "SELECT target_title FROM note_links WHERE source_id = $1 ORDER BY target_title",
+        id
+    )
+    .fetch_all(&data.db)
+    .await?;
+    if targets.is_empty() {
+        ensure_note_exists(&data, id).await?;
+    }
+
+    let notes = sqlx::query_as!(
+        NoteModel,
+        "SELECT * FROM notes WHERE title = ANY($1) ORDER BY title",
+        &targets[..]
+    )
+    .fetch_all(&data.db)
+    .await?;
+    let unresolved: Vec<&String> = targets
+        .iter()
+        .filter(|title| !notes.iter().any(|note| &note.title == *title))
+        .collect();
 
-    let notes = query_result.unwrap();
     let json_response = serde_json::json!(
         {
             "status": "success",
             "results": notes.len(),
-            "notes": notes
+            "notes": notes,
+            "unresolved": unresolved
         }
     );
     Ok(Json(json_response))
 }
 
-pub async fn read_one_handler(
+pub async fn read_backlinks_handler(
     State(data): State<Arc<AppState>>,
     Path(id): Path<uuid::Uuid>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let query_result = sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", id)
-        .fetch_one(&data.db)
-        .await;
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_note_exists(&data, id).await?;
 
-    match query_result {
-        Ok(note) => {
-            let note_response = serde_json::json!(
-                {
-                    "status": "success",
-                    "data": serde_json::json!(
-                        {
-                            "note": note
-                        }
-                    )
-                }
-            );
-            Ok(Json(note_response))
+    let notes = sqlx::query_as!(
+        NoteModel,
+        r#"SELECT source.* FROM notes target
+        JOIN note_links l ON l.target_title = target.title
+        JOIN notes source ON source.id = l.source_id
+        WHERE target.id = $1
+        ORDER BY source.title"#,
+        id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let json_response = serde_json::json!(
+        {
+            "status": "success",
+            "results": notes.len(),
+            "notes": notes
         }
-        Err(_) => {
-            let error_response = serde_json::json!(
+    );
+    Ok(Json(json_response))
+}
+
+pub async fn read_graph_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let nodes = sqlx::query_as!(
+        GraphNode,
+        "SELECT id, title, category FROM notes ORDER BY title"
+    )
+    .fetch_all(&data.db)
+    .await?;
+    let edges = sqlx::query_as!(
+        GraphEdge,
+        r#"SELECT l.source_id AS source, target.id AS target FROM note_links l
+        JOIN notes target ON target.title = l.target_title"#
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let json_response = serde_json::json!(
+        {
+            "status": "success",
+            "data": serde_json::json!(
                 {
-                    "status": "fail",
-                    "message": format!("Note with id {} not found!", id)
+                    "nodes": nodes,
+                    "edges": edges
                 }
-            );
-            println!("❌ Returning error response...");
-            Err((StatusCode::NOT_FOUND, Json(error_response)))
+            )
         }
+    );
+    Ok(Json(json_response))
+}
+
+pub async fn read_related_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    opts: Option<Query<RelatedOptions>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let limit = opts.limit.unwrap_or(5).clamp(1, 50);
+    let notes = similarity::related_notes(&data.db, id, limit).await?;
+    if notes.is_empty() {
+        ensure_note_exists(&data, id).await?;
     }
+
+    Ok(Json(ApiResponse::new(RelatedData { notes })))
 }
 
-pub async fn update_one_handler(
+pub async fn check_duplicate_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<CheckDuplicateSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let matches = similarity::similar_notes(
+        &data.db,
+        &body.title,
+        body.content.as_deref(),
+        body.exclude_id,
+        5,
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::new(DuplicateData {
+        duplicate: similarity::is_duplicate(&matches),
+        matches,
+    })))
+}
+
+pub async fn create_notebook_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<CreateNotebookSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let notebook = notebook::create(&data, body).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::new(NotebookData {
+            notebook,
+            breadcrumbs: None,
+        })),
+    ))
+}
+
+pub async fn read_notebooks_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let notebooks = notebook::tree(&data.db, None).await?;
+
+    Ok(Json(ApiResponse::new(NotebookTreeData { notebooks })))
+}
+
+pub async fn read_notebook_handler(
     State(data): State<Arc<AppState>>,
     Path(id): Path<uuid::Uuid>,
-    Json(body): Json<UpdateNoteSchema>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let query_result = sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", id)
-        .fetch_one(&data.db)
-        .await;
+) -> Result<impl IntoResponse, ApiError> {
+    let notebook = notebook::get(&data, id).await?;
+    let breadcrumbs = notebook::breadcrumbs(&data.db, id).await?;
 
-    if query_result.is_err() {
-        let error_response = serde_json::json!(
-            {
-                "status": "fail",
-                "message": format!("Note with id {} not found!", id)
-            }
-        );
-        println!("❌ Returning error response...");
-        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    Ok(Json(ApiResponse::new(NotebookData {
+        notebook,
+        breadcrumbs: Some(breadcrumbs),
+    })))
+}
+
+pub async fn read_notebook_tree_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let notebooks = notebook::tree(&data.db, Some(id)).await?;
+    if notebooks.is_empty() {
+        notebook::get(&data, id).await?;
     }
 
-    let now = chrono::Utc::now();
-    let note = query_result.unwrap();
-    let query_result = sqlx::query_as!(
-        NoteModel,
-        "UPDATE notes SET title = $1, content = $2, category = $3, published = $4, updated_at = $5 WHERE id = $6 RETURNING *",
-        body.title.to_owned().unwrap_or(note.title),
-        body.content.to_owned().unwrap_or(note.content),
-        body.category.to_owned().unwrap_or(note.category.unwrap()),
-        body.published.unwrap_or(note.published.unwrap()),
-        now,
-        id
-    ).fetch_one(&data.db).await;
+    Ok(Json(ApiResponse::new(NotebookTreeData { notebooks })))
+}
 
-    match query_result {
-        Ok(note) => {
-            let note_response = serde_json::json!(
-                {
-                    "status": "success",
-                    "data": serde_json::json!(
-                        {
-                            "note": note
-                        }
-                    )
-                }
-            );
-            Ok(Json(note_response))
+pub async fn update_notebook_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<UpdateNotebookSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let notebook = notebook::update(&data, id, body).await?;
+
+    Ok(Json(ApiResponse::new(NotebookData {
+        notebook,
+        breadcrumbs: None,
+    })))
+}
+
+pub async fn delete_notebook_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+    opts: Option<Query<DeleteNotebookOptions>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    notebook::delete(&data, &ctx, id, opts.mode).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn create_webhook_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<CreateWebhookSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let webhook = webhook::create(&data, body).await?;
+    // The secret is only ever shown here.
+    let webhook = NewWebhook {
+        secret: webhook.secret.clone(),
+        webhook,
+    };
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::new(WebhookData { webhook })),
+    ))
+}
+
+pub async fn read_webhooks_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let webhooks = webhook::list(&data).await?;
+
+    Ok(Json(ApiResponse::new(WebhookListData { webhooks })))
+}
+
+pub async fn read_webhook_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let webhook = webhook::get(&data, id).await?;
+
+    Ok(Json(ApiResponse::new(WebhookData { webhook })))
+}
+
+pub async fn update_webhook_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<UpdateWebhookSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let webhook = webhook::update(&data, id, body).await?;
+
+    Ok(Json(ApiResponse::new(WebhookData { webhook })))
+}
+
+pub async fn delete_webhook_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    webhook::delete(&data, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn read_deliveries_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    opts: Option<Query<DeliveryFilterOptions>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let deliveries = webhook::deliveries(&data, id, opts).await?;
+
+    Ok(Json(ApiResponse::new(DeliveryListData { deliveries })))
+}
+
+pub async fn redeliver_handler(
+    State(data): State<Arc<AppState>>,
+    Path((id, delivery_id)): Path<(uuid::Uuid, i64)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let delivery = webhook::redeliver(&data, id, delivery_id).await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::new(DeliveryData { delivery })),
+    ))
+}
+
+pub async fn read_audit_handler(
+    State(data): State<Arc<AppState>>,
+    opts: Option<Query<AuditFilterOptions>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let page = opts.page.unwrap_or(1).max(1);
+    let limit = opts.limit.unwrap_or(50).clamp(1, 100);
+    let offset = i64::try_from((page - 1).saturating_mul(limit)).unwrap_or(i64::MAX);
+
+    let entries = sqlx::query_as!(
+        AuditModel,
+        r#"SELECT * FROM note_audit
+        WHERE ($1::UUID IS NULL OR note_id = $1)
+            AND ($2::VARCHAR IS NULL OR actor = $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR created_at >= $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR created_at < $4)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $5 OFFSET $6"#,
+        opts.note_id,
+        opts.actor,
+        opts.from,
+        opts.to,
+        limit as i64,
+        offset
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let json_response = serde_json::json!(
+        {
+            "status": "success",
+            "results": entries.len(),
+            "entries": entries
         }
-        Err(e) => {
-            println!("❌ Returning error response...");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!(
-                    {
-                        "status": "error",
-                        "message": format!("{:?}", e)
-                    }
-                )),
-            ))
+    );
+    Ok(Json(json_response))
+}
+
+fn not_modified(validators: &Validators) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        AppendHeaders(validators.headers()),
+    )
+        .into_response()
+}
+
+/// Checks a MIME type against an allow list that may contain `type/*` wildcards.
+fn mime_allowed(allowed: &[String], content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(prefix) => essence.split('/').next() == Some(prefix),
+                None => pattern == essence,
+            })
+}
+
+/// Parses a single `bytes=` range into inclusive `(start, end)` offsets.
+///
+/// Returns `Ok(None)` for ranges the server is allowed to ignore (multiple
+/// ranges or other units) and `Err(())` for unsatisfiable ones.
+fn parse_range(value: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| ())?;
+            if suffix == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (start, "") => (start.parse().map_err(|_| ())?, size.saturating_sub(1)),
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end: u64 = end.parse().map_err(|_| ())?;
+            (start, end.min(size.saturating_sub(1)))
         }
+    };
+    if start >= size || start > end {
+        return Err(());
     }
+    Ok(Some((start, end)))
 }
 
-pub async fn delete_one_handler(
-    State(data): State<Arc<AppState>>,
-    Path(id): Path<uuid::Uuid>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let rows_affected = sqlx::query!("DELETE FROM notes WHERE id = $1", id)
-        .execute(&data.db)
-        .await
-        .unwrap()
-        .rows_affected();
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    name.chars().filter(|c| !c.is_control()).take(255).collect()
+}
 
-    if rows_affected == 0 {
-        let error_response = serde_json::json!(
-            {
-                "status": "fail",
-                "message": format!("Note with id {} not found!", id)
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
             }
-        );
-        println!("❌ Returning error response...");
-        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn range_forms() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=500-5000", 1000), Ok(Some((500, 999))));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
     }
 
-    Ok(StatusCode::NO_CONTENT)
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=5-1", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=abc", 1000), Err(()));
+    }
+
+    #[test]
+    fn mime_allow_list() {
+        let allowed = vec!["image/*".to_string(), "application/pdf".to_string()];
+        assert!(mime_allowed(&allowed, "image/png"));
+        assert!(mime_allowed(&allowed, "application/pdf; charset=binary"));
+        assert!(!mime_allowed(&allowed, "application/zip"));
+        assert!(mime_allowed(&[], "application/zip"));
+    }
+
+    #[test]
+    fn disposition_escapes_filename() {
+        assert_eq!(
+            content_disposition("résumé \"v2\".pdf"),
+            "attachment; filename=\"r_sum_ _v2_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%22v2%22.pdf"
+        );
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+    }
 }
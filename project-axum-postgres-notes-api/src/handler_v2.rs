@@ -0,0 +1,134 @@
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Response};
+use axum::Json;
+use std::sync::Arc;
+
+use crate::audit::AuditContext;
+use crate::conditional::Validators;
+use crate::crypto::NotePassword;
+use crate::idempotency;
+use crate::response::{to_json_bytes, ApiError, ApiResponse, PageMeta};
+use crate::schema::{
+    CreateNoteSchema, FilterOptions, NoteFilter, SearchOptions, UpdateNoteSchema, UpdateOptions,
+};
+use crate::service;
+use crate::AppState;
+
+// Handlers for `/api/v2`. Every success body is an `ApiResponse` whose `data`
+// is the resource itself; lists carry pagination in `meta`.
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+
+pub async fn create_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    password: NotePassword,
+    headers: HeaderMap,
+    Json(body): Json<CreateNoteSchema>,
+) -> Result<Response, ApiError> {
+    let request_hash = idempotency::fingerprint(&("v2", &body));
+    idempotency::respond_once(&data, &headers, &request_hash, || async {
+        let result = service::create_note(&data, &ctx, body, password.as_deref()).await;
+        to_json_bytes(result.map(|note| (StatusCode::CREATED, ApiResponse::new(note))))
+    })
+    .await
+}
+
+pub async fn read_all_handler(
+    State(data): State<Arc<AppState>>,
+    opts: Option<Query<FilterOptions>>,
+    filter: Option<Query<NoteFilter>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let Query(filter) = filter.unwrap_or_default();
+    let page = opts.page.unwrap_or(1).max(1);
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let notes = service::list_notes(&data, &filter, page, limit).await?;
+    let total = service::count_notes(&data, &filter).await?;
+
+    let validators = Validators::for_page(&notes, total);
+    if validators.is_not_modified(&headers) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            AppendHeaders(validators.headers()),
+        )
+            .into_response());
+    }
+    let json_response = ApiResponse::new(notes).with_meta(PageMeta::new(page, limit, total));
+    Ok((AppendHeaders(validators.headers()), Json(json_response)).into_response())
+}
+
+pub async fn search_handler(
+    State(data): State<Arc<AppState>>,
+    Query(opts): Query<SearchOptions>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let notes = service::search_notes(&data, &opts.q, limit).await?;
+    Ok(Json(ApiResponse::new(notes)))
+}
+
+pub async fn read_one_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+    password: NotePassword,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let note = service::get_note(&data, id).await?;
+
+    if note.encrypted && password.0.is_some() {
+        // Content read with a password must not be served from any cache.
+        let note = service::unlock(note, password.as_deref()).await;
+        return Ok((
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(ApiResponse::new(note)),
+        )
+            .into_response());
+    }
+
+    let validators = Validators::for_notes(std::slice::from_ref(&note));
+    if validators.is_not_modified(&headers) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            AppendHeaders(validators.headers()),
+        )
+            .into_response());
+    }
+    Ok((
+        AppendHeaders(validators.headers()),
+        Json(ApiResponse::new(note)),
+    )
+        .into_response())
+}
+
+pub async fn update_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+    opts: Option<Query<UpdateOptions>>,
+    password: NotePassword,
+    Json(body): Json<UpdateNoteSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let note = service::update_note(
+        &data,
+        &ctx,
+        id,
+        body,
+        opts.rewrite_links.unwrap_or(false),
+        password.as_deref(),
+    )
+    .await?;
+    Ok(Json(ApiResponse::new(note)))
+}
+
+pub async fn delete_one_handler(
+    State(data): State<Arc<AppState>>,
+    ctx: AuditContext,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    service::delete_note(&data, &ctx, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,223 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::response::ApiError;
+use crate::AppState;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// How long an unfinished claim blocks retries before it is considered
+/// abandoned, e.g. because the instance handling it crashed.
+const CLAIM_TIMEOUT_SECS: f64 = 60.0;
+
+/// Outcome of claiming an `Idempotency-Key` for a request.
+#[derive(Debug)]
+pub enum Claim {
+    /// First use of the key; the caller must `complete` or `release` it.
+    Fresh,
+    /// The key was already answered; replay the stored response.
+    Replay { status: u16, body: Vec<u8> },
+    /// The key was used for a request with a different body.
+    Mismatch,
+    /// Another request with the same key is still running.
+    InProgress,
+}
+
+/// Reads and validates the `Idempotency-Key` header, if present.
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, &'static str> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(Some(key.to_string())),
+        _ => Err("Idempotency-Key must be 1 to 255 visible ASCII characters!"),
+    }
+}
+
+/// Hashes the parsed request body, so formatting differences between retries
+/// do not count as a different request.
+pub fn fingerprint<T: Serialize>(body: &T) -> String {
+    hex::encode(Sha256::digest(serde_json::to_vec(body).unwrap()))
+}
+
+/// Runs `handle` at most once per `Idempotency-Key`, replaying the stored
+/// response to retries. Requests without the header are simply handled.
+///
+/// `handle` returns the serialized response so that exactly the bytes sent the
+/// first time can be stored.
+pub async fn respond_once<F, Fut>(
+    data: &AppState,
+    headers: &HeaderMap,
+    request_hash: &str,
+    handle: F,
+) -> Result<Response, ApiError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = (StatusCode, Vec<u8>)>,
+{
+    let key = key_from_headers(headers)
+        .map_err(|message| ApiError::fail(StatusCode::BAD_REQUEST, message))?;
+    let Some(key) = key else {
+        let (status, body) = handle().await;
+        return Ok(json_bytes_response(status, body, false));
+    };
+
+    match claim(&data.db, &key, request_hash, data.config.idempotency_ttl).await? {
+        Claim::Fresh => {}
+        Claim::Replay { status, body } => {
+            let status = StatusCode::from_u16(status).map_err(ApiError::internal)?;
+            return Ok(json_bytes_response(status, body, true));
+        }
+        Claim::Mismatch => {
+            return Err(ApiError::fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request body!",
+            ));
+        }
+        Claim::InProgress => {
+            return Err(ApiError::fail(
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still being processed!",
+            ));
+        }
+    }
+
+    let (status, body) = handle().await;
+    if status.is_server_error() {
+        // Let the client retry a failure that was not its fault.
+        release(&data.db, &key).await?;
+    } else {
+        complete(&data.db, &key, status.as_u16(), &body).await?;
+    }
+    Ok(json_bytes_response(status, body, false))
+}
+
+fn json_bytes_response(status: StatusCode, body: Vec<u8>, replayed: bool) -> Response {
+    let mut response = (status, [(header::CONTENT_TYPE, "application/json")], body).into_response();
+    if replayed {
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+pub async fn claim(
+    db: &Pool<Postgres>,
+    key: &str,
+    request_hash: &str,
+    ttl: Duration,
+) -> Result<Claim, sqlx::Error> {
+    sqlx::query!("DELETE FROM idempotency_keys WHERE expires_at <= NOW()")
+        .execute(db)
+        .await?;
+
+    let inserted = sqlx::query_scalar!(
+        r#"INSERT INTO idempotency_keys (key, request_hash, expires_at)
+        VALUES ($1, $2, NOW() + make_interval(secs => $3))
+        ON CONFLICT (key) DO NOTHING
+        RETURNING key"#,
+        key,
+        request_hash,
+        ttl.as_secs_f64()
+    )
+    .fetch_optional(db)
+    .await?;
+    if inserted.is_some() {
+        return Ok(Claim::Fresh);
+    }
+
+    let reclaimed = sqlx::query_scalar!(
+        r#"UPDATE idempotency_keys SET created_at = NOW()
+        WHERE key = $1 AND request_hash = $2 AND status_code IS NULL
+            AND created_at < NOW() - make_interval(secs => $3)
+        RETURNING key"#,
+        key,
+        request_hash,
+        CLAIM_TIMEOUT_SECS
+    )
+    .fetch_optional(db)
+    .await?;
+    if reclaimed.is_some() {
+        return Ok(Claim::Fresh);
+    }
+
+    let stored = sqlx::query!(
+        "SELECT request_hash, status_code, response_body FROM idempotency_keys WHERE key = $1",
+        key
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(match stored {
+        None => Claim::InProgress,
+        Some(row) if row.request_hash != request_hash => Claim::Mismatch,
+        Some(row) => match (row.status_code, row.response_body) {
+            (Some(status), Some(body)) => Claim::Replay {
+                status: status as u16,
+                body,
+            },
+            _ => Claim::InProgress,
+        },
+    })
+}
+
+/// Stores the response for a claimed key so that retries can replay it.
+pub async fn complete(
+    db: &Pool<Postgres>,
+    key: &str,
+    status: u16,
+    body: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE idempotency_keys SET status_code = $1, response_body = $2 WHERE key = $3",
+        status as i16,
+        body,
+        key
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Gives up a claimed key so that the request can be retried from scratch.
+pub async fn release(db: &Pool<Postgres>, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE key = $1 AND status_code IS NULL",
+        key
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn fingerprint_ignores_formatting() {
+        let a: serde_json::Value = serde_json::from_str(r#"{"title":"a","content":"b"}"#).unwrap();
+        let b: serde_json::Value =
+            serde_json::from_str("{ \"content\": \"b\",\n \"title\": \"a\" }").unwrap();
+        let c: serde_json::Value = serde_json::from_str(r#"{"title":"a","content":"c"}"#).unwrap();
+        assert_eq!(fingerprint(&a), fingerprint(&b));
+        assert_ne!(fingerprint(&a), fingerprint(&c));
+    }
+
+    #[test]
+    fn validates_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(key_from_headers(&headers), Ok(None));
+        headers.insert(IDEMPOTENCY_KEY, HeaderValue::from_static("abc-123"));
+        assert_eq!(key_from_headers(&headers), Ok(Some("abc-123".to_string())));
+        headers.insert(IDEMPOTENCY_KEY, HeaderValue::from_static(""));
+        assert!(key_from_headers(&headers).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use sqlx::{Postgres, Transaction};
+
+/// Longest title a `[[...]]` reference can point at, matching `notes.title`.
+const MAX_TITLE_LEN: usize = 255;
+
+/// Returns the distinct `[[Note Title]]` references in `content`, in order of
+/// first appearance.
+pub fn parse_links(content: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+    for (_, title) in references(content) {
+        if !titles.iter().any(|t| t == title) {
+            titles.push(title.to_string());
+        }
+    }
+    titles
+}
+
+/// Rewrites every `[[old]]` reference in `content` to `[[new]]`.
+pub fn rewrite_links(content: &str, old: &str, new: &str) -> String {
+    let mut rewritten = String::with_capacity(content.len());
+    let mut last = 0;
+    for (range, title) in references(content) {
+        if title == old {
+            rewritten.push_str(&content[last..range.start]);
+            rewritten.push_str("[[");
+            rewritten.push_str(new);
+            rewritten.push_str("]]");
+            last = range.end;
+        }
+    }
+    rewritten.push_str(&content[last..]);
+    rewritten
+}
+
+/// Yields the byte range of each well-formed `[[...]]` reference together with
+/// its trimmed title.
+fn references(content: &str) -> impl Iterator<Item = (std::ops::Range<usize>, &str)> {
+    let mut pos = 0;
+    std::iter::from_fn(move || loop {
+        let start = pos + content[pos..].find("[[")?;
+        let inner_start = start + 2;
+        let Some(len) = content[inner_start..].find("]]") else {
+            pos = content.len();
+            return None;
+        };
+        let inner = &content[inner_start..inner_start + len];
+        if inner.contains(['[', ']', '\n']) {
+            // Not a reference; resume right after this `[[` so that nested or
+            // stray brackets do not hide a valid reference further on.
+            pos = start + 1;
+            continue;
+        }
+        pos = inner_start + len + 2;
+        let title = inner.trim();
+        if !title.is_empty() && title.len() <= MAX_TITLE_LEN {
+            return Some((start..pos, title));
+        }
+    })
+}
+
+/// Replaces the stored outgoing links of a note with the references found in
+/// its `content`.
+pub async fn sync_links(
+    tx: &mut Transaction<'_, Postgres>,
+    note_id: uuid::Uuid,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    let titles = parse_links(content);
+    sqlx::query!("DELETE FROM note_links WHERE source_id = $1", note_id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO note_links (source_id, target_title) SELECT $1, UNNEST($2::VARCHAR[])",
+        note_id,
+        &titles[..]
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_references() {
+        let content = "See [[Meeting notes]] and [[ Roadmap ]], again [[Meeting notes]].";
+        assert_eq!(parse_links(content), vec!["Meeting notes", "Roadmap"]);
+    }
+
+    #[test]
+    fn ignores_malformed_references() {
+        assert!(parse_links("[[]] [[ ]] [[unclosed").is_empty());
+        assert_eq!(parse_links("[[a\nb]] [[[Inner]]]"), vec!["Inner"]);
+        assert_eq!(parse_links("[[x] [[Valid]]"), vec!["Valid"]);
+    }
+
+    #[test]
+    fn rewrites_only_matching_references() {
+        let content = "[[Old]], [[ Old ]], [[Older]] and [[Other]]";
+        assert_eq!(
+            rewrite_links(content, "Old", "New"),
+            "[[New]], [[New]], [[Older]] and [[Other]]"
+        );
+        assert_eq!(rewrite_links("no links", "Old", "New"), "no links");
+    }
+}
@@ -1,29 +1,76 @@
-use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
-use axum::http::{HeaderValue, Method};
+use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, VARY};
+use axum::http::{Extensions, HeaderMap, HeaderValue, Method, StatusCode, Version};
 use dotenv::dotenv;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
 
+use crate::config::Config;
+use crate::crypto::NOTE_PASSWORD;
+use crate::events::NoteEvents;
+use crate::graphql::NotesSchema;
+use crate::idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED};
+use crate::ordering::Rebalancer;
 use crate::route::create_router;
+use crate::scheduler::Scheduler;
+use crate::storage::BlobStore;
+use crate::webhook::WebhookDispatcher;
 
+mod audit;
+mod conditional;
+mod config;
+mod crypto;
+mod events;
+mod graphql;
 mod handler;
+mod handler_v2;
+mod idempotency;
+mod links;
 mod model;
+mod notebook;
+mod ordering;
+mod response;
 mod route;
+mod scheduler;
 mod schema;
+mod service;
+mod similarity;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod webhook;
 
 pub struct AppState {
     db: Pool<Postgres>,
+    config: Config,
+    blobs: BlobStore,
+    scheduler: Scheduler,
+    rebalancer: Rebalancer,
+    events: NoteEvents,
+    webhooks: WebhookDispatcher,
+    graphql: NotesSchema,
+}
+
+/// Responses that advertise byte ranges (attachment downloads) are served as
+/// stored, since compressing them would break `Range` offsets.
+fn not_ranged(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    !headers.contains_key(axum::http::header::ACCEPT_RANGES)
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let config = Config::init();
     let pool = match PgPoolOptions::new()
         .max_connections(10)
-        .connect(&database_url)
+        .connect(&config.database_url)
         .await
     {
         Ok(pool) => {
@@ -36,7 +83,31 @@ async fn main() {
         }
     };
 
-    let app_state = Arc::new(AppState { db: pool.clone() });
+    let blobs = match BlobStore::new(config.attachments_dir.clone()).await {
+        Ok(blobs) => blobs,
+        Err(err) => {
+            println!("❌ Failed to open the attachment store: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let http_addr = SocketAddr::from(([0, 0, 0, 0], config.http_port));
+    let tls = config.tls.clone();
+    let events = NoteEvents::default();
+    let scheduler = Scheduler::spawn(pool.clone(), config.scheduler_interval, events.clone());
+    let rebalancer = Rebalancer::spawn(pool.clone());
+    let webhooks = WebhookDispatcher::spawn(pool.clone(), &config, &events);
+
+    let app_state = Arc::new(AppState {
+        db: pool.clone(),
+        config,
+        blobs,
+        scheduler,
+        rebalancer,
+        events,
+        webhooks,
+        graphql: graphql::build_schema(),
+    });
     // let app = Router::new()
     //     .route("/api/healthchecker", get(handler::health_check_handler))
     //     .with_state(app_state);
@@ -44,12 +115,37 @@ async fn main() {
         .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
-    let app = create_router(app_state).layer(cors);
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            IDEMPOTENCY_KEY,
+            NOTE_PASSWORD,
+        ])
+        .expose_headers([IDEMPOTENT_REPLAYED]);
+    let compression =
+        CompressionLayer::new().compress_when(DefaultPredicate::new().and(not_ranged));
+    let app = create_router(app_state)
+        .layer(cors)
+        .layer(compression)
+        .layer(SetResponseHeaderLayer::appending(
+            VARY,
+            HeaderValue::from_static("accept-encoding"),
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
     println!("🚀 Server started successfully");
 
-    axum::Server::bind(&"0.0.0.0:8000".parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    match tls {
+        Some(tls) => {
+            if let Err(err) = tls::serve(app, http_addr, tls).await {
+                println!("❌ Failed to serve HTTPS: {:?}", err);
+                std::process::exit(1);
+            }
+        }
+        None => axum::Server::bind(&http_addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap(),
+    }
 }
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-#[derive(Debug, FromRow, Deserialize, Serialize)]
+#[derive(Debug, Clone, FromRow, Deserialize, Serialize, async_graphql::SimpleObject)]
+#[graphql(name = "Note")]
 pub struct NoteModel {
     pub id: uuid::Uuid,
     pub title: String,
@@ -12,4 +13,156 @@ pub struct NoteModel {
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
     #[serde(rename = "updatedAt")]
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "publishAt")]
+    pub publish_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "unpublishAt")]
+    pub unpublish_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "notebookId")]
+    pub notebook_id: Option<uuid::Uuid>,
+    /// When set, `content` is empty and the text is only stored sealed.
+    pub encrypted: bool,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub content_salt: Option<Vec<u8>>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub content_nonce: Option<Vec<u8>>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub content_ciphertext: Option<Vec<u8>>,
+    /// Pinned notes are listed first.
+    pub pinned: bool,
+    /// Sort key within the pinned and unpinned groups; see `ordering`.
+    pub position: String,
+}
+
+impl NoteModel {
+    /// The note without its content, as encrypted notes are returned.
+    pub fn redacted(&self) -> NoteModel {
+        NoteModel {
+            content: String::new(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, FromRow, Deserialize, Serialize)]
+pub struct AttachmentModel {
+    pub id: uuid::Uuid,
+    #[serde(rename = "noteId")]
+    pub note_id: uuid::Uuid,
+    pub filename: String,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct NotebookModel {
+    pub id: uuid::Uuid,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<uuid::Uuid>,
+    pub name: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A notebook within a listed tree. `noteCount` covers the notebook itself,
+/// `totalNotes` also its descendants.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct NotebookNode {
+    pub id: uuid::Uuid,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<uuid::Uuid>,
+    pub name: String,
+    pub depth: i32,
+    #[serde(rename = "noteCount")]
+    pub note_count: i64,
+    #[serde(rename = "totalNotes")]
+    pub total_notes: i64,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct Breadcrumb {
+    pub id: uuid::Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct GraphNode {
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub category: Option<String>,
+}
+
+/// A note scored by trigram similarity, from 0 (unrelated) to 1 (identical).
+#[derive(Debug, FromRow, Serialize)]
+pub struct SimilarNoteModel {
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub category: Option<String>,
+    pub score: f64,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct GraphEdge {
+    pub source: uuid::Uuid,
+    pub target: uuid::Uuid,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct WebhookModel {
+    pub id: uuid::Uuid,
+    pub url: String,
+    /// Shown only once, when the webhook is registered.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct WebhookDeliveryModel {
+    pub id: i64,
+    #[serde(rename = "webhookId")]
+    pub webhook_id: uuid::Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    #[serde(rename = "nextAttemptAt")]
+    pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "lastStatusCode")]
+    pub last_status_code: Option<i16>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "deliveredAt")]
+    pub delivered_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct AuditModel {
+    pub id: i64,
+    #[serde(rename = "noteId")]
+    pub note_id: uuid::Uuid,
+    pub action: String,
+    pub actor: Option<String>,
+    #[serde(rename = "clientIp")]
+    pub client_ip: Option<String>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+    pub diff: serde_json::Value,
+    #[serde(rename = "createdAt")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
 }
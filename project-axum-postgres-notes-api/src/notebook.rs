@@ -0,0 +1,521 @@
+use axum::http::StatusCode;
+use sqlx::{PgExecutor, Pool, Postgres, Transaction};
+use std::collections::HashMap;
+
+use crate::audit::{self, Action, AuditContext};
+use crate::model::{Breadcrumb, NoteModel, NotebookModel, NotebookNode};
+use crate::response::ApiError;
+use crate::schema::{CreateNotebookSchema, DeleteMode, UpdateNotebookSchema};
+use crate::service::collect_blob;
+use crate::webhook;
+use crate::AppState;
+
+// Notebooks form a forest through `parent_id`. Every change that can alter
+// the shape of the tree takes a table lock first, so that two concurrent
+// moves cannot each pass the cycle check and together create a loop.
+
+pub async fn create(
+    data: &AppState,
+    body: CreateNotebookSchema,
+) -> Result<NotebookModel, ApiError> {
+    let name = validate_name(&body.name)?;
+    sqlx::query_as!(
+        NotebookModel,
+        "INSERT INTO notebooks (name, parent_id) VALUES ($1, $2) RETURNING *",
+        name,
+        body.parent_id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(write_error)
+}
+
+pub async fn get(data: &AppState, id: uuid::Uuid) -> Result<NotebookModel, ApiError> {
+    sqlx::query_as!(NotebookModel, "SELECT * FROM notebooks WHERE id = $1", id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| not_found(id))
+}
+
+/// Lists the subtree under `root`, or every notebook when `root` is `None`,
+/// depth first with siblings ordered by name.
+pub async fn tree(
+    db: impl PgExecutor<'_>,
+    root: Option<uuid::Uuid>,
+) -> Result<Vec<NotebookNode>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        NotebookNode,
+        r#"WITH RECURSIVE tree AS (
+            SELECT id, parent_id, name, 0 AS depth, ARRAY[name::TEXT] AS path
+            FROM notebooks
+            WHERE ($1::UUID IS NULL AND parent_id IS NULL) OR id = $1
+            UNION ALL
+            SELECT child.id, child.parent_id, child.name, tree.depth + 1, tree.path || child.name::TEXT
+            FROM notebooks child
+            JOIN tree ON child.parent_id = tree.id
+        )
+        SELECT tree.id AS "id!", tree.parent_id, tree.name AS "name!", tree.depth AS "depth!",
+            (SELECT COUNT(*) FROM notes WHERE notes.notebook_id = tree.id) AS "note_count!",
+            0::BIGINT AS "total_notes!"
+        FROM tree
+        ORDER BY tree.path"#,
+        root
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(with_totals(rows))
+}
+
+/// The path from the top level down to the notebook, inclusive.
+pub async fn breadcrumbs(
+    db: &Pool<Postgres>,
+    id: uuid::Uuid,
+) -> Result<Vec<Breadcrumb>, sqlx::Error> {
+    sqlx::query_as!(
+        Breadcrumb,
+        r#"WITH RECURSIVE crumbs AS (
+            SELECT id, parent_id, name, 0 AS depth FROM notebooks WHERE id = $1
+            UNION ALL
+            SELECT parent.id, parent.parent_id, parent.name, crumbs.depth + 1
+            FROM notebooks parent
+            JOIN crumbs ON parent.id = crumbs.parent_id
+        )
+        SELECT id AS "id!", name AS "name!" FROM crumbs ORDER BY depth DESC"#,
+        id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Renames and/or moves a notebook.
+pub async fn update(
+    data: &AppState,
+    id: uuid::Uuid,
+    body: UpdateNotebookSchema,
+) -> Result<NotebookModel, ApiError> {
+    let mut tx = data.db.begin().await?;
+    if body.parent_id.is_some() {
+        lock_tree(&mut tx).await?;
+    }
+    let notebook = sqlx::query_as!(
+        NotebookModel,
+        "SELECT * FROM notebooks WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| not_found(id))?;
+
+    if let Some(Some(parent_id)) = body.parent_id {
+        if parent_id == id || is_descendant(&mut tx, parent_id, id).await? {
+            return Err(ApiError::fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "A notebook cannot be moved into itself or one of its descendants!",
+            ));
+        }
+    }
+    let name = match &body.name {
+        Some(name) => validate_name(name)?,
+        None => notebook.name,
+    };
+
+    let notebook = sqlx::query_as!(
+        NotebookModel,
+        "UPDATE notebooks SET name = $1, parent_id = $2, updated_at = NOW() WHERE id = $3 RETURNING *",
+        name,
+        body.parent_id.unwrap_or(notebook.parent_id),
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(write_error)?;
+    tx.commit().await?;
+    Ok(notebook)
+}
+
+/// Deletes a notebook. Without a `mode`, only empty notebooks can be deleted.
+pub async fn delete(
+    data: &AppState,
+    ctx: &AuditContext,
+    id: uuid::Uuid,
+    mode: Option<DeleteMode>,
+) -> Result<(), ApiError> {
+    let mut tx = data.db.begin().await?;
+    lock_tree(&mut tx).await?;
+    let notebook = sqlx::query_as!(
+        NotebookModel,
+        "SELECT * FROM notebooks WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| not_found(id))?;
+    let subtree: Vec<uuid::Uuid> = tree(&mut *tx, Some(id))
+        .await?
+        .into_iter()
+        .map(|node| node.id)
+        .collect();
+    let has_notes = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM notes WHERE notebook_id = $1)",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+        == Some(true);
+
+    let (changed, deleted, blobs, removed) = match mode {
+        None if subtree.len() > 1 || has_notes => {
+            return Err(ApiError::fail(
+                StatusCode::CONFLICT,
+                "Notebook is not empty; delete it with mode=cascade or mode=reparent!",
+            ));
+        }
+        None | Some(DeleteMode::Reparent) => {
+            // The children now hang off the parent, so only this one goes.
+            let changed = reparent(&mut tx, ctx, &notebook).await?;
+            (changed, Vec::new(), Vec::new(), vec![id])
+        }
+        Some(DeleteMode::Cascade) => {
+            let (deleted, blobs) = delete_notes(&mut tx, ctx, &subtree).await?;
+            (Vec::new(), deleted, blobs, subtree)
+        }
+    };
+    sqlx::query!("DELETE FROM notebooks WHERE id = ANY($1)", &removed[..])
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    for (action, note) in changed {
+        data.events.publish(action, note);
+    }
+    for note in deleted {
+        data.events.publish(Action::Delete, note);
+    }
+    for sha256 in blobs {
+        collect_blob(data, &sha256).await?;
+    }
+    Ok(())
+}
+
+/// Hands the notebook's children and notes over to its parent.
+async fn reparent(
+    tx: &mut Transaction<'_, Postgres>,
+    ctx: &AuditContext,
+    notebook: &NotebookModel,
+) -> Result<Vec<(Action, NoteModel)>, ApiError> {
+    sqlx::query!(
+        "UPDATE notebooks SET parent_id = $1, updated_at = NOW() WHERE parent_id = $2",
+        notebook.parent_id,
+        notebook.id
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(write_error)?;
+
+    let old_notes = sqlx::query_as!(
+        NoteModel,
+        "SELECT * FROM notes WHERE notebook_id = $1 ORDER BY id FOR UPDATE",
+        notebook.id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let new_notes = sqlx::query_as!(
+        NoteModel,
+        "UPDATE notes SET notebook_id = $1, updated_at = NOW() WHERE notebook_id = $2 RETURNING *",
+        notebook.parent_id,
+        notebook.id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let mut changed = Vec::with_capacity(new_notes.len());
+    for new in new_notes {
+        let old = old_notes.iter().find(|note| note.id == new.id);
+        audit::record(tx, ctx, Action::Update, old, Some(&new)).await?;
+        webhook::enqueue(tx, Action::Update, &new).await?;
+        changed.push((Action::Update, new));
+    }
+    Ok(changed)
+}
+
+/// Deletes every note in the given notebooks, returning them along with the
+/// attachment blobs that may have become unreferenced.
+async fn delete_notes(
+    tx: &mut Transaction<'_, Postgres>,
+    ctx: &AuditContext,
+    notebooks: &[uuid::Uuid],
+) -> Result<(Vec<NoteModel>, Vec<String>), ApiError> {
+    let blobs = sqlx::query_scalar!(
+        r#"SELECT DISTINCT a.sha256 FROM attachments a
+        JOIN notes n ON n.id = a.note_id
+        WHERE n.notebook_id = ANY($1)"#,
+        notebooks
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let deleted = sqlx::query_as!(
+        NoteModel,
+        "DELETE FROM notes WHERE notebook_id = ANY($1) RETURNING *",
+        notebooks
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    for note in &deleted {
+        audit::record(tx, ctx, Action::Delete, Some(note), None).await?;
+        webhook::enqueue(tx, Action::Delete, note).await?;
+    }
+    Ok((deleted, blobs))
+}
+
+async fn lock_tree(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("LOCK TABLE notebooks IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+/// Whether `candidate` lies below `ancestor`, found by walking up from
+/// `candidate`, which is cheaper than walking down a wide subtree.
+async fn is_descendant(
+    tx: &mut Transaction<'_, Postgres>,
+    candidate: uuid::Uuid,
+    ancestor: uuid::Uuid,
+) -> Result<bool, sqlx::Error> {
+    let found = sqlx::query_scalar!(
+        r#"WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM notebooks WHERE id = $1
+            UNION ALL
+            SELECT parent.id, parent.parent_id
+            FROM notebooks parent
+            JOIN ancestors ON parent.id = ancestors.parent_id
+        )
+        SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = $2) AS "found!""#,
+        candidate,
+        ancestor
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(found)
+}
+
+/// Fills in `total_notes` for rows listed depth first, where every
+/// descendant follows its ancestors.
+fn with_totals(mut rows: Vec<NotebookNode>) -> Vec<NotebookNode> {
+    let positions: HashMap<uuid::Uuid, usize> = rows
+        .iter()
+        .enumerate()
+        .map(|(position, row)| (row.id, position))
+        .collect();
+    for row in rows.iter_mut() {
+        row.total_notes = row.note_count;
+    }
+    for position in (0..rows.len()).rev() {
+        let parent = rows[position]
+            .parent_id
+            .and_then(|parent_id| positions.get(&parent_id));
+        if let Some(&parent) = parent {
+            rows[parent].total_notes += rows[position].total_notes;
+        }
+    }
+    rows
+}
+
+fn validate_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Notebook name must not be empty!",
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn not_found(id: uuid::Uuid) -> ApiError {
+    ApiError::fail(
+        StatusCode::NOT_FOUND,
+        format!("Notebook with id {} not found!", id),
+    )
+}
+
+/// Maps constraint violations on `notebooks` to client errors.
+fn write_error(e: sqlx::Error) -> ApiError {
+    let message = e.to_string();
+    if message.contains("notebooks_parent_name_idx") {
+        return ApiError::fail(
+            StatusCode::CONFLICT,
+            "A notebook with that name already exists here!",
+        );
+    }
+    if message.contains("notebooks_parent_id_fkey") {
+        return ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Parent notebook not found!",
+        );
+    }
+    ApiError::internal(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing;
+
+    fn node(id: u128, parent: Option<u128>, note_count: i64) -> NotebookNode {
+        NotebookNode {
+            id: uuid::Uuid::from_u128(id),
+            parent_id: parent.map(uuid::Uuid::from_u128),
+            name: id.to_string(),
+            depth: 0,
+            note_count,
+            total_notes: 0,
+        }
+    }
+
+    #[test]
+    fn totals_include_descendants() {
+        // 1
+        // ├── 2
+        // │   └── 3
+        // └── 4
+        let rows = with_totals(vec![
+            node(1, None, 1),
+            node(2, Some(1), 2),
+            node(3, Some(2), 4),
+            node(4, Some(1), 8),
+        ]);
+        let totals: Vec<i64> = rows.iter().map(|row| row.total_notes).collect();
+        assert_eq!(totals, vec![15, 6, 4, 8]);
+    }
+
+    #[test]
+    fn subtree_root_parent_is_ignored() {
+        // Listing a subtree leaves the root's parent out of the rows.
+        let rows = with_totals(vec![node(2, Some(1), 2), node(3, Some(2), 4)]);
+        assert_eq!(rows[0].total_notes, 6);
+    }
+
+    async fn notebook(
+        data: &AppState,
+        name: &str,
+        parent: Option<&NotebookModel>,
+    ) -> NotebookModel {
+        let body = CreateNotebookSchema {
+            name: name.to_string(),
+            parent_id: parent.map(|parent| parent.id),
+        };
+        create(data, body).await.unwrap()
+    }
+
+    async fn note_in(data: &AppState, notebook: &NotebookModel) -> uuid::Uuid {
+        sqlx::query_scalar!(
+            "INSERT INTO notes (title, content, position, notebook_id) VALUES ($1, '', 'm', $2) RETURNING id",
+            format!("In {}", notebook.name),
+            notebook.id
+        )
+        .fetch_one(&data.db)
+        .await
+        .unwrap()
+    }
+
+    async fn notebook_of(data: &AppState, note: uuid::Uuid) -> Option<Option<uuid::Uuid>> {
+        sqlx::query_scalar!("SELECT notebook_id FROM notes WHERE id = $1", note)
+            .fetch_optional(&data.db)
+            .await
+            .unwrap()
+    }
+
+    async fn move_to(
+        data: &AppState,
+        notebook: &NotebookModel,
+        parent: Option<&NotebookModel>,
+    ) -> Result<NotebookModel, ApiError> {
+        let body = UpdateNotebookSchema {
+            name: None,
+            parent_id: Some(parent.map(|parent| parent.id)),
+        };
+        update(data, notebook.id, body).await
+    }
+
+    #[tokio::test]
+    async fn reparenting_deletes_only_the_notebook() {
+        let data = testing::state().await;
+        let work = notebook(&data, "Work", None).await;
+        let projects = notebook(&data, "Projects", Some(&work)).await;
+        let launch = notebook(&data, "Launch", Some(&projects)).await;
+        let in_work = note_in(&data, &work).await;
+        let in_launch = note_in(&data, &launch).await;
+
+        let ctx = AuditContext::default();
+        delete(&data, &ctx, work.id, Some(DeleteMode::Reparent))
+            .await
+            .unwrap();
+
+        assert!(get(&data, work.id).await.is_err());
+        assert_eq!(get(&data, projects.id).await.unwrap().parent_id, None);
+        assert_eq!(
+            get(&data, launch.id).await.unwrap().parent_id,
+            Some(projects.id)
+        );
+        assert_eq!(notebook_of(&data, in_work).await, Some(None));
+        assert_eq!(notebook_of(&data, in_launch).await, Some(Some(launch.id)));
+    }
+
+    #[tokio::test]
+    async fn cascading_deletes_the_subtree_and_its_notes() {
+        let data = testing::state().await;
+        let work = notebook(&data, "Work", None).await;
+        let projects = notebook(&data, "Projects", Some(&work)).await;
+        let launch = notebook(&data, "Launch", Some(&projects)).await;
+        let home = notebook(&data, "Home", None).await;
+        let in_launch = note_in(&data, &launch).await;
+        let in_home = note_in(&data, &home).await;
+
+        let ctx = AuditContext::default();
+        delete(&data, &ctx, work.id, Some(DeleteMode::Cascade))
+            .await
+            .unwrap();
+
+        for gone in [&work, &projects, &launch] {
+            assert!(get(&data, gone.id).await.is_err());
+        }
+        assert_eq!(notebook_of(&data, in_launch).await, None);
+        assert_eq!(notebook_of(&data, in_home).await, Some(Some(home.id)));
+    }
+
+    #[tokio::test]
+    async fn moves_carry_the_subtree_along() {
+        let data = testing::state().await;
+        let work = notebook(&data, "Work", None).await;
+        let projects = notebook(&data, "Projects", Some(&work)).await;
+        let launch = notebook(&data, "Launch", Some(&projects)).await;
+        let archive = notebook(&data, "Archive", None).await;
+
+        move_to(&data, &projects, Some(&archive)).await.unwrap();
+        let path: Vec<String> = breadcrumbs(&data.db, launch.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|crumb| crumb.name)
+            .collect();
+        assert_eq!(path, ["Archive", "Projects", "Launch"]);
+
+        move_to(&data, &projects, None).await.unwrap();
+        assert_eq!(get(&data, projects.id).await.unwrap().parent_id, None);
+        assert_eq!(tree(&data.db, Some(work.id)).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn moves_into_their_own_subtree_are_rejected() {
+        let data = testing::state().await;
+        let work = notebook(&data, "Work", None).await;
+        let projects = notebook(&data, "Projects", Some(&work)).await;
+        let launch = notebook(&data, "Launch", Some(&projects)).await;
+
+        for target in [&work, &projects, &launch] {
+            let err = move_to(&data, &work, Some(target)).await.unwrap_err();
+            assert_eq!(err.code, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(get(&data, work.id).await.unwrap().parent_id, None);
+    }
+}
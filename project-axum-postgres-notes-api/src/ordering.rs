@@ -0,0 +1,225 @@
+use sqlx::{Pool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Notes are ordered by fractional-index keys: strings of base-62 digits that
+// compare byte by byte (the `position` column uses the "C" collation), read as
+// fractions after the point. There is always room for a key between two
+// others, so a move rewrites only the moved note. Keys never end in the lowest
+// digit, which keeps room in front of every key too.
+
+const DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+/// Keys longer than this get the whole order respaced.
+pub const MAX_KEY_LEN: usize = 32;
+/// How often the rebalancer looks at key lengths when nothing wakes it.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// A key that sorts after `low` and before `high`; `None` stands for the start
+/// or the end of the order. Requires `low < high`.
+pub fn key_between(low: Option<&str>, high: Option<&str>) -> String {
+    midpoint(low.unwrap_or("").as_bytes(), high.map(str::as_bytes))
+}
+
+fn midpoint(low: &[u8], high: Option<&[u8]>) -> String {
+    if let Some(high) = high {
+        // Copy the shared prefix, reading a missing digit of `low` as zero.
+        let shared = high
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &digit)| low.get(i).copied().unwrap_or(DIGITS[0]) == digit)
+            .count();
+        if shared > 0 {
+            let prefix = String::from_utf8_lossy(&high[..shared]);
+            let rest = midpoint(low.get(shared..).unwrap_or_default(), Some(&high[shared..]));
+            return format!("{}{}", prefix, rest);
+        }
+    }
+
+    let low_digit = low.first().map_or(0, |&digit| value(digit));
+    let high_digit = high.map_or(DIGITS.len(), |high| value(high[0]));
+    if high_digit - low_digit > 1 {
+        char::from(DIGITS[(low_digit + high_digit) / 2]).to_string()
+    } else if let Some(high) = high.filter(|high| high.len() > 1) {
+        // `high` continues past its first digit, so that digit alone is lower.
+        char::from(high[0]).to_string()
+    } else {
+        let rest = midpoint(low.get(1..).unwrap_or_default(), None);
+        format!("{}{}", char::from(DIGITS[low_digit]), rest)
+    }
+}
+
+/// `count` ascending keys spaced evenly, as short as that allows.
+pub fn spread(count: usize) -> Vec<String> {
+    let base = DIGITS.len() as u128;
+    let mut width = 1;
+    while base.pow(width) < 2 * (count as u128 + 1) {
+        width += 1;
+    }
+    let span = base.pow(width);
+    (1..=count as u128)
+        .map(|i| encode(i * span / (count as u128 + 1), width))
+        .collect()
+}
+
+fn encode(mut number: u128, width: u32) -> String {
+    let base = DIGITS.len() as u128;
+    let mut digits = vec![DIGITS[0]; width as usize];
+    for digit in digits.iter_mut().rev() {
+        *digit = DIGITS[(number % base) as usize];
+        number /= base;
+    }
+    while digits.last() == Some(&DIGITS[0]) {
+        digits.pop();
+    }
+    String::from_utf8(digits).unwrap()
+}
+
+fn value(digit: u8) -> usize {
+    DIGITS
+        .iter()
+        .position(|&d| d == digit)
+        .expect("position keys only contain base-62 digits")
+}
+
+/// Serializes everything that hands out positions, so that two writers never
+/// pick the same key.
+pub async fn lock_positions(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('notes.position'))")
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+/// Respaces position keys in the background once one grows past
+/// [`MAX_KEY_LEN`]. The order itself never changes, so nothing is audited or
+/// announced.
+#[derive(Clone)]
+pub struct Rebalancer {
+    wake: Arc<Notify>,
+}
+
+impl Rebalancer {
+    pub fn spawn(db: Pool<Postgres>) -> Rebalancer {
+        let wake = Arc::new(Notify::new());
+        tokio::spawn(run(db, wake.clone()));
+        Rebalancer { wake }
+    }
+
+    /// Makes the rebalancer check key lengths right away.
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+}
+
+async fn run(db: Pool<Postgres>, wake: Arc<Notify>) {
+    loop {
+        match rebalance_if_needed(&db).await {
+            Ok(0) => {}
+            Ok(count) => println!("↕️ Rebalanced the positions of {} note(s)", count),
+            Err(err) => println!("❌ Failed to rebalance note positions: {:?}", err),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
+
+/// Returns how many notes got a new key.
+pub async fn rebalance_if_needed(db: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    lock_positions(&mut tx).await?;
+    let longest = sqlx::query_scalar!("SELECT MAX(LENGTH(position)) FROM notes")
+        .fetch_one(&mut *tx)
+        .await?;
+    if longest.unwrap_or(0) as usize <= MAX_KEY_LEN {
+        return Ok(0);
+    }
+
+    let ids = sqlx::query_scalar!("SELECT id FROM notes ORDER BY position, id")
+        .fetch_all(&mut *tx)
+        .await?;
+    let positions = spread(ids.len());
+    sqlx::query!(
+        r#"UPDATE notes SET position = spread.position
+        FROM UNNEST($1::UUID[], $2::TEXT[]) AS spread(id, position)
+        WHERE notes.id = spread.id"#,
+        &ids[..],
+        &positions[..]
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_between(low: Option<&str>, high: Option<&str>) -> String {
+        let key = key_between(low, high);
+        assert!(
+            low.is_none_or(|low| low < key.as_str()),
+            "{:?} !< {}",
+            low,
+            key
+        );
+        assert!(
+            high.is_none_or(|high| key.as_str() < high),
+            "{} !< {:?}",
+            key,
+            high
+        );
+        assert!(!key.ends_with('0'), "{} ends in the lowest digit", key);
+        key
+    }
+
+    #[test]
+    fn keys_fit_between_neighbours() {
+        assert_eq!(key_between(None, None), "V");
+        assert_eq!(key_between(Some("V"), None), "k");
+        assert_eq!(key_between(None, Some("V")), "F");
+        assert_eq!(key_between(Some("V"), Some("W")), "VV");
+        assert_eq!(key_between(Some("z"), None), "zV");
+        assert_eq!(key_between(None, Some("1")), "0V");
+        assert_between(Some("a0000000001V"), Some("a0000000002V"));
+        assert_between(Some("V"), Some("V1"));
+        assert_between(Some("0V"), Some("1"));
+    }
+
+    #[test]
+    fn repeated_inserts_stay_ordered() {
+        // Always inserting right after the first key is the worst case for
+        // key growth.
+        let first = assert_between(None, None);
+        let mut high = assert_between(Some(&first), None);
+        for _ in 0..200 {
+            high = assert_between(Some(&first), Some(&high));
+        }
+        assert!(high.len() > MAX_KEY_LEN);
+
+        let mut low = first.clone();
+        for _ in 0..200 {
+            low = assert_between(None, Some(&low));
+        }
+    }
+
+    #[test]
+    fn spread_keys_are_short_and_ordered() {
+        assert!(spread(0).is_empty());
+        for count in [1, 2, 30, 31, 1000] {
+            let keys = spread(count);
+            assert_eq!(keys.len(), count);
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(keys
+                .iter()
+                .all(|key| !key.is_empty() && !key.ends_with('0')));
+            assert!(keys.iter().all(|key| key.len() <= 2));
+            // There is room next to every key.
+            assert_between(None, keys.first().map(String::as_str));
+            assert_between(keys.last().map(String::as_str), None);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+use crate::model::{
+    Breadcrumb, NoteModel, NotebookModel, NotebookNode, SimilarNoteModel, WebhookDeliveryModel,
+    WebhookModel,
+};
+
+/// Success envelope: `{"status": "success", "data": ..., "meta": ...}`.
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T> {
+    pub status: &'static str,
+    pub data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<PageMeta>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn new(data: T) -> Self {
+        ApiResponse {
+            status: "success",
+            data,
+            meta: None,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: PageMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+}
+
+#[derive(Debug, Serialize, async_graphql::SimpleObject)]
+pub struct PageMeta {
+    pub page: usize,
+    pub limit: usize,
+    pub total: i64,
+    #[serde(rename = "totalPages")]
+    pub total_pages: i64,
+}
+
+impl PageMeta {
+    pub fn new(page: usize, limit: usize, total: i64) -> Self {
+        let limit_i64 = limit.max(1) as i64;
+        PageMeta {
+            page,
+            limit,
+            total,
+            total_pages: (total + limit_i64 - 1) / limit_i64,
+        }
+    }
+}
+
+/// `{"note": ...}`, the `data` of single-note responses in v1.
+#[derive(Debug, Serialize)]
+pub struct NoteData {
+    pub note: NoteModel,
+}
+
+/// `{"notes": [...]}`, the notes most similar to a given one, best first.
+#[derive(Debug, Serialize)]
+pub struct RelatedData {
+    pub notes: Vec<SimilarNoteModel>,
+}
+
+/// `{"duplicate": bool, "matches": [...]}`, the result of a duplicate check.
+#[derive(Debug, Serialize)]
+pub struct DuplicateData {
+    pub duplicate: bool,
+    pub matches: Vec<SimilarNoteModel>,
+}
+
+/// `{"notebook": ...}`, plus the path from the root down to it when a single
+/// notebook is read.
+#[derive(Debug, Serialize)]
+pub struct NotebookData {
+    pub notebook: NotebookModel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breadcrumbs: Option<Vec<Breadcrumb>>,
+}
+
+/// `{"notebooks": [...]}`, a notebook tree flattened in depth-first order.
+#[derive(Debug, Serialize)]
+pub struct NotebookTreeData {
+    pub notebooks: Vec<NotebookNode>,
+}
+
+/// `{"webhook": ...}`.
+#[derive(Debug, Serialize)]
+pub struct WebhookData<W = WebhookModel> {
+    pub webhook: W,
+}
+
+/// A webhook as returned when it is registered, the only time its signing
+/// secret is shown.
+#[derive(Debug, Serialize)]
+pub struct NewWebhook {
+    #[serde(flatten)]
+    pub webhook: WebhookModel,
+    pub secret: String,
+}
+
+/// `{"webhooks": [...]}`.
+#[derive(Debug, Serialize)]
+pub struct WebhookListData {
+    pub webhooks: Vec<WebhookModel>,
+}
+
+/// `{"delivery": ...}`.
+#[derive(Debug, Serialize)]
+pub struct DeliveryData {
+    pub delivery: WebhookDeliveryModel,
+}
+
+/// `{"deliveries": [...]}`, newest first.
+#[derive(Debug, Serialize)]
+pub struct DeliveryListData {
+    pub deliveries: Vec<WebhookDeliveryModel>,
+}
+
+/// `{"status": "success", "results": n, "notes": [...]}`, the v1 list shape.
+#[derive(Debug, Serialize)]
+pub struct NoteListResponse {
+    pub status: &'static str,
+    pub results: usize,
+    pub notes: Vec<NoteModel>,
+}
+
+impl NoteListResponse {
+    pub fn new(notes: Vec<NoteModel>) -> Self {
+        NoteListResponse {
+            status: "success",
+            results: notes.len(),
+            notes,
+        }
+    }
+}
+
+/// Error envelope shared by every API version.
+///
+/// `status` is `"fail"` when the client is at fault and `"error"` when the
+/// server is.
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub code: StatusCode,
+    pub status: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn fail(code: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            status: "fail",
+            message: message.into(),
+        }
+    }
+
+    pub fn note_not_found(id: uuid::Uuid) -> Self {
+        ApiError::fail(
+            StatusCode::NOT_FOUND,
+            format!("Note with id {} not found!", id),
+        )
+    }
+
+    pub fn internal<E: std::fmt::Debug>(e: E) -> Self {
+        ApiError {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            status: "error",
+            message: format!("{:?}", e),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        println!("❌ Returning error response...");
+        (self.code, Json(self)).into_response()
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::internal(e)
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::internal(e)
+    }
+}
+
+/// Serializes a handler result up front, for responses that must be stored
+/// and replayed byte for byte.
+pub fn to_json_bytes<T: Serialize>(
+    result: Result<(StatusCode, T), ApiError>,
+) -> (StatusCode, Vec<u8>) {
+    match result {
+        Ok((code, body)) => match serde_json::to_vec(&body) {
+            Ok(bytes) => (code, bytes),
+            Err(e) => to_json_bytes::<()>(Err(ApiError::internal(e))),
+        },
+        Err(error) => {
+            println!("❌ Returning error response...");
+            (error.code, serde_json::to_vec(&error).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_meta_rounds_up() {
+        assert_eq!(PageMeta::new(1, 10, 0).total_pages, 0);
+        assert_eq!(PageMeta::new(1, 10, 10).total_pages, 1);
+        assert_eq!(PageMeta::new(2, 10, 11).total_pages, 2);
+    }
+
+    #[test]
+    fn new_webhook_shows_its_secret() {
+        let webhook = WebhookModel {
+            id: uuid::Uuid::new_v4(),
+            url: "https://example.com/hook".to_string(),
+            secret: "s3cret".to_string(),
+            events: vec!["note.created".to_string()],
+            active: true,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let listed = serde_json::to_value(&webhook).unwrap();
+        assert!(listed.get("secret").is_none());
+
+        let created = serde_json::to_value(NewWebhook {
+            secret: webhook.secret.clone(),
+            webhook,
+        })
+        .unwrap();
+        assert_eq!(created["secret"], "s3cret");
+        assert_eq!(created["url"], "https://example.com/hook");
+    }
+
+    #[test]
+    fn error_envelope() {
+        let error = ApiError::fail(StatusCode::CONFLICT, "Note with that title already exists!");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({"status": "fail", "message": "Note with that title already exists!"})
+        );
+    }
+}
@@ -1,20 +1,134 @@
-use axum::routing::{delete, get, patch, post};
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{get, post};
 use axum::Router;
 use std::sync::Arc;
 
+use crate::graphql::{graphiql_handler, graphql_handler, graphql_ws_handler};
 use crate::handler::{
-    create_one_handler, delete_one_handler, health_check_handler, read_all_handler,
-    read_one_handler, update_one_handler,
+    check_duplicate_handler, create_notebook_handler, create_one_handler, create_webhook_handler,
+    delete_attachment_handler, delete_notebook_handler, delete_one_handler, delete_webhook_handler,
+    download_attachment_handler, health_check_handler, move_one_handler, read_all_handler,
+    read_attachments_handler, read_audit_handler, read_backlinks_handler, read_deliveries_handler,
+    read_graph_handler, read_links_handler, read_notebook_handler, read_notebook_tree_handler,
+    read_notebooks_handler, read_one_handler, read_related_handler, read_webhook_handler,
+    read_webhooks_handler, redeliver_handler, rotate_key_handler, update_notebook_handler,
+    update_one_handler, update_webhook_handler, upload_attachment_handler,
 };
+use crate::handler_v2;
 use crate::AppState;
 
 pub fn create_router(app_state: Arc<AppState>) -> Router {
+    let v1 = v1_routes(&app_state);
+
     Router::new()
-        .route("/api/healthchecker", get(health_check_handler))
-        .route("/api/notes", post(create_one_handler))
-        .route("/api/notes", get(read_all_handler))
-        .route("/api/notes/:id", get(read_one_handler))
-        .route("/api/notes/:id", patch(update_one_handler))
-        .route("/api/notes/:id", delete(delete_one_handler))
+        // Unversioned paths predate `/api/v1` and stay as aliases for it.
+        .nest("/api", v1.clone())
+        .nest("/api/v1", v1)
+        .nest("/api/v2", v2_routes())
+        .route("/api/graphql", get(graphiql_handler).post(graphql_handler))
+        .route("/api/graphql/ws", get(graphql_ws_handler))
         .with_state(app_state)
 }
+
+fn v1_routes(app_state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    // Leave room for the multipart framing around the file itself.
+    let upload_limit = app_state.config.attachments_max_size + 64 * 1024;
+
+    Router::new()
+        .route("/healthchecker", get(health_check_handler))
+        .route("/audit", get(read_audit_handler))
+        .route("/notes", post(create_one_handler).get(read_all_handler))
+        .route("/notes/graph", get(read_graph_handler))
+        .route(
+            "/notebooks",
+            post(create_notebook_handler).get(read_notebooks_handler),
+        )
+        .route(
+            "/notebooks/:id",
+            get(read_notebook_handler)
+                .patch(update_notebook_handler)
+                .delete(delete_notebook_handler),
+        )
+        .route("/notebooks/:id/tree", get(read_notebook_tree_handler))
+        .route(
+            "/webhooks",
+            post(create_webhook_handler).get(read_webhooks_handler),
+        )
+        .route(
+            "/webhooks/:id",
+            get(read_webhook_handler)
+                .patch(update_webhook_handler)
+                .delete(delete_webhook_handler),
+        )
+        .route("/webhooks/:id/deliveries", get(read_deliveries_handler))
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(redeliver_handler),
+        )
+        .route("/notes/check-duplicate", post(check_duplicate_handler))
+        .route(
+            "/notes/:id",
+            get(read_one_handler)
+                .patch(update_one_handler)
+                .delete(delete_one_handler),
+        )
+        .route("/notes/:id/move", post(move_one_handler))
+        .route("/notes/:id/rotate-key", post(rotate_key_handler))
+        .route("/notes/:id/links", get(read_links_handler))
+        .route("/notes/:id/backlinks", get(read_backlinks_handler))
+        .route("/notes/:id/related", get(read_related_handler))
+        .route(
+            "/notes/:id/attachments",
+            post(upload_attachment_handler)
+                .layer(DefaultBodyLimit::max(upload_limit))
+                .get(read_attachments_handler),
+        )
+        .route(
+            "/notes/:id/attachments/:attachment_id",
+            get(download_attachment_handler).delete(delete_attachment_handler),
+        )
+}
+
+fn v2_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthchecker", get(health_check_handler))
+        .route(
+            "/notes",
+            post(handler_v2::create_one_handler).get(handler_v2::read_all_handler),
+        )
+        .route("/notes/search", get(handler_v2::search_handler))
+        .route("/notes/check-duplicate", post(check_duplicate_handler))
+        .route(
+            "/notes/:id",
+            get(handler_v2::read_one_handler)
+                .patch(handler_v2::update_one_handler)
+                .delete(handler_v2::delete_one_handler),
+        )
+        .route("/notes/:id/related", get(read_related_handler))
+        .route(
+            "/notebooks",
+            post(create_notebook_handler).get(read_notebooks_handler),
+        )
+        .route(
+            "/notebooks/:id",
+            get(read_notebook_handler)
+                .patch(update_notebook_handler)
+                .delete(delete_notebook_handler),
+        )
+        .route("/notebooks/:id/tree", get(read_notebook_tree_handler))
+        .route(
+            "/webhooks",
+            post(create_webhook_handler).get(read_webhooks_handler),
+        )
+        .route(
+            "/webhooks/:id",
+            get(read_webhook_handler)
+                .patch(update_webhook_handler)
+                .delete(delete_webhook_handler),
+        )
+        .route("/webhooks/:id/deliveries", get(read_deliveries_handler))
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(redeliver_handler),
+        )
+}
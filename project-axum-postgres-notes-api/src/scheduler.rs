@@ -0,0 +1,195 @@
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::audit::{self, Action, AuditContext};
+use crate::events::NoteEvents;
+use crate::model::NoteModel;
+use crate::webhook;
+
+/// Number of notes flipped per transaction.
+const BATCH_SIZE: i64 = 100;
+/// Keeps the loop from spinning while another instance holds a due row.
+const MIN_SLEEP: Duration = Duration::from_millis(250);
+
+/// Flips `published` when a note's `publish_at` or `unpublish_at` comes due.
+///
+/// All state lives in the `notes` table, so a restart simply picks up whatever
+/// became overdue while the process was down. Due rows are claimed with
+/// `FOR UPDATE SKIP LOCKED`, which lets several instances run side by side
+/// without applying the same transition twice.
+#[derive(Clone)]
+pub struct Scheduler {
+    wake: Arc<Notify>,
+}
+
+impl Scheduler {
+    pub fn spawn(db: Pool<Postgres>, poll_interval: Duration, events: NoteEvents) -> Scheduler {
+        let wake = Arc::new(Notify::new());
+        tokio::spawn(run(db, poll_interval, events, wake.clone()));
+        Scheduler { wake }
+    }
+
+    /// Makes the scheduler re-read the next due time, e.g. after a handler has
+    /// set a schedule that is earlier than the one it is sleeping towards.
+    pub fn reschedule(&self) {
+        self.wake.notify_one();
+    }
+}
+
+async fn run(db: Pool<Postgres>, poll_interval: Duration, events: NoteEvents, wake: Arc<Notify>) {
+    loop {
+        match apply_due_transitions(&db, &events).await {
+            Ok(0) => {}
+            Ok(count) => println!("⏰ Applied {} scheduled publishing change(s)", count),
+            Err(err) => println!("❌ Failed to apply scheduled publishing changes: {:?}", err),
+        }
+
+        let sleep_for = match next_due_in(&db).await {
+            Ok(Some(due_in)) => due_in.min(poll_interval),
+            Ok(None) => poll_interval,
+            Err(err) => {
+                println!("❌ Failed to look up the next scheduled change: {:?}", err);
+                poll_interval
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(sleep_for.max(MIN_SLEEP)) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
+
+/// Applies every overdue transition and returns how many notes changed.
+///
+/// When both timestamps have passed, whichever came last wins.
+pub async fn apply_due_transitions(
+    db: &Pool<Postgres>,
+    events: &NoteEvents,
+) -> Result<usize, sqlx::Error> {
+    let ctx = AuditContext::system("scheduler");
+    let mut total = 0;
+    loop {
+        let mut tx = db.begin().await?;
+        let due = sqlx::query_as!(
+            NoteModel,
+            r#"SELECT * FROM notes
+            WHERE publish_at <= NOW() OR unpublish_at <= NOW()
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED"#,
+            BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let ids: Vec<uuid::Uuid> = due.iter().map(|note| note.id).collect();
+        let changed = sqlx::query_as!(
+            NoteModel,
+            r#"UPDATE notes SET
+                published = NOT COALESCE(
+                    unpublish_at <= NOW()
+                    AND (
+                        publish_at IS NULL
+                        OR publish_at > NOW()
+                        OR unpublish_at >= publish_at
+                    ),
+                    FALSE
+                ),
+                publish_at = CASE WHEN publish_at <= NOW() THEN NULL ELSE publish_at END,
+                unpublish_at = CASE WHEN unpublish_at <= NOW() THEN NULL ELSE unpublish_at END,
+                updated_at = NOW()
+            WHERE id = ANY($1)
+            RETURNING *"#,
+            &ids[..]
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut actions = Vec::with_capacity(changed.len());
+        for new in &changed {
+            let old = due.iter().find(|note| note.id == new.id).unwrap();
+            let action = Action::for_update(old, new);
+            audit::record(&mut tx, &ctx, action, Some(old), Some(new)).await?;
+            webhook::enqueue(&mut tx, action, new).await?;
+            actions.push(action);
+        }
+        tx.commit().await?;
+        for (action, note) in actions.into_iter().zip(&changed) {
+            events.publish(action, note.clone());
+        }
+
+        total += changed.len();
+        if (due.len() as i64) < BATCH_SIZE {
+            return Ok(total);
+        }
+    }
+}
+
+async fn next_due_in(db: &Pool<Postgres>) -> Result<Option<Duration>, sqlx::Error> {
+    let next = sqlx::query_scalar!(
+        "SELECT MIN(LEAST(publish_at, unpublish_at)) FROM notes WHERE publish_at IS NOT NULL OR unpublish_at IS NOT NULL"
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(next.map(|next| {
+        (next - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn due_notes_are_published_by_the_scheduler() {
+        let db = testing::database().await;
+        let events = NoteEvents::default();
+        let mut published = events.subscribe();
+        let due = sqlx::query_scalar!(
+            "INSERT INTO notes (title, content, position, published, publish_at) VALUES ('Launch', 'Out now', 'm', FALSE, NOW() - INTERVAL '1 minute') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let later = sqlx::query_scalar!(
+            "INSERT INTO notes (title, content, position, published, publish_at) VALUES ('Sequel', 'Soon', 'n', FALSE, NOW() + INTERVAL '1 hour') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(apply_due_transitions(&db, &events).await.unwrap(), 1);
+
+        let note = sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", due)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(note.published, Some(true));
+        assert_eq!(note.publish_at, None);
+        let entries = sqlx::query!(
+            "SELECT action, actor, diff FROM note_audit WHERE note_id = $1",
+            due
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "publish");
+        assert_eq!(entries[0].actor.as_deref(), Some("scheduler"));
+        assert_eq!(entries[0].diff["published"]["new"], true);
+        let event = published.try_recv().unwrap();
+        assert_eq!((event.action, event.note.id), (Action::Publish, due));
+
+        let note = sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", later)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(note.published, Some(false));
+        assert!(note.publish_at.is_some());
+        assert!(published.try_recv().is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Default, Deserialize)]
 pub struct FilterOptions {
@@ -6,12 +7,29 @@ pub struct FilterOptions {
     pub limit: Option<usize>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SearchOptions {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+/// Narrows a note listing; unset fields match every note.
+#[derive(Debug, Default, Deserialize, async_graphql::InputObject)]
+pub struct NoteFilter {
+    pub category: Option<String>,
+    pub published: Option<bool>,
+    #[serde(rename = "notebookId")]
+    pub notebook_id: Option<uuid::Uuid>,
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct ParamOptions {
     pub id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, async_graphql::InputObject)]
+#[graphql(name = "CreateNoteInput")]
 pub struct CreateNoteSchema {
     pub title: String,
     pub content: String,
@@ -19,6 +37,17 @@ pub struct CreateNoteSchema {
     pub category: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub published: Option<bool>,
+    #[serde(rename = "publishAt", skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<DateTime<Utc>>,
+    #[serde(rename = "unpublishAt", skip_serializing_if = "Option::is_none")]
+    pub unpublish_at: Option<DateTime<Utc>>,
+    #[serde(rename = "notebookId", skip_serializing_if = "Option::is_none")]
+    pub notebook_id: Option<uuid::Uuid>,
+    /// Seals the content under the request's `X-Note-Password`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -27,4 +56,137 @@ pub struct UpdateNoteSchema {
     pub content: Option<String>,
     pub category: Option<String>,
     pub published: Option<bool>,
+    /// Turning encryption on or off, or changing the content of an encrypted
+    /// note, takes the note's `X-Note-Password`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
+    /// `None` keeps the current schedule, `Some(None)` (an explicit `null`) clears it.
+    #[serde(
+        rename = "publishAt",
+        default,
+        deserialize_with = "double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub publish_at: Option<Option<DateTime<Utc>>>,
+    #[serde(
+        rename = "unpublishAt",
+        default,
+        deserialize_with = "double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub unpublish_at: Option<Option<DateTime<Utc>>>,
+    /// `Some(None)` moves the note out of its notebook.
+    #[serde(
+        rename = "notebookId",
+        default,
+        deserialize_with = "double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub notebook_id: Option<Option<uuid::Uuid>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateOptions {
+    /// Rewrite `[[Old Title]]` references in other notes when the title changes.
+    pub rewrite_links: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RelatedOptions {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckDuplicateSchema {
+    pub title: String,
+    pub content: Option<String>,
+    /// The note being edited, which should not match itself.
+    #[serde(rename = "excludeId")]
+    pub exclude_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateNotebookSchema {
+    pub name: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateNotebookSchema {
+    pub name: Option<String>,
+    /// `Some(None)` moves the notebook to the top level.
+    #[serde(rename = "parentId", default, deserialize_with = "double_option")]
+    pub parent_id: Option<Option<uuid::Uuid>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+    /// Delete the notebook, its descendants and every note in them.
+    Cascade,
+    /// Hand children and notes over to the notebook's parent.
+    Reparent,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteNotebookOptions {
+    pub mode: Option<DeleteMode>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilterOptions {
+    pub note_id: Option<uuid::Uuid>,
+    pub actor: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Neighbours to place a note between: right after `after`, right before
+/// `before`, or between the two when both are given.
+#[derive(Debug, Deserialize)]
+pub struct MoveNoteSchema {
+    pub before: Option<uuid::Uuid>,
+    pub after: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateKeySchema {
+    #[serde(rename = "newPassword")]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookSchema {
+    pub url: String,
+    pub events: Vec<String>,
+    /// Generated when omitted.
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWebhookSchema {
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeliveryFilterOptions {
+    pub status: Option<String>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Distinguishes a field that is present but `null` from one that is missing.
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
 }
@@ -0,0 +1,682 @@
+use axum::http::StatusCode;
+use sqlx::{Postgres, Transaction};
+
+use crate::audit::{self, Action, AuditContext};
+use crate::crypto::{self, Sealed};
+use crate::links::{rewrite_links, sync_links};
+use crate::model::NoteModel;
+use crate::ordering::{self, key_between, lock_positions};
+use crate::response::ApiError;
+use crate::schema::{CreateNoteSchema, MoveNoteSchema, NoteFilter, UpdateNoteSchema};
+use crate::webhook;
+use crate::AppState;
+
+// Note operations shared by every API version and GraphQL. Handlers only
+// translate between their protocol and these functions, so side effects such
+// as link tracking, auditing, blob collection and change events cannot drift
+// apart between them.
+
+/// Encrypted notes are returned without their content.
+pub async fn create_note(
+    data: &AppState,
+    ctx: &AuditContext,
+    body: CreateNoteSchema,
+    password: Option<&str>,
+) -> Result<NoteModel, ApiError> {
+    // The id is chosen up front because sealed content is bound to it.
+    let id = uuid::Uuid::new_v4();
+    let (content, sealed) = if body.encrypted == Some(true) {
+        let password = password.ok_or_else(password_required)?;
+        (
+            String::new(),
+            Some(crypto::seal(password, id, &body.content).await),
+        )
+    } else {
+        (body.content.to_string(), None)
+    };
+
+    let mut tx = data.db.begin().await?;
+    // New notes go to the end of the order.
+    lock_positions(&mut tx).await?;
+    let last = sqlx::query_scalar!("SELECT MAX(position) FROM notes")
+        .fetch_one(&mut *tx)
+        .await?;
+    let position = key_between(last.as_deref(), None);
+    let note = sqlx::query_as!(
+        NoteModel,
+        "INSERT INTO notes (id, title, content, category, publish_at, unpublish_at, notebook_id, encrypted, content_salt, content_nonce, content_ciphertext, pinned, position) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING *",
+        id,
+        body.title.to_string(),
+        content,
+        body.category.to_owned().unwrap_or("".to_string()),
+        body.publish_at,
+        body.unpublish_at,
+        body.notebook_id,
+        sealed.is_some(),
+        sealed.as_ref().map(|sealed| sealed.salt.clone()),
+        sealed.as_ref().map(|sealed| sealed.nonce.clone()),
+        sealed.as_ref().map(|sealed| sealed.ciphertext.clone()),
+        body.pinned.unwrap_or(false),
+        position
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(write_error)?;
+
+    sync_links(&mut tx, note.id, &note.content).await?;
+    audit::record(&mut tx, ctx, Action::Create, None, Some(&note)).await?;
+    webhook::enqueue(&mut tx, Action::Create, &note).await?;
+    tx.commit().await?;
+    if note.publish_at.is_some() || note.unpublish_at.is_some() {
+        data.scheduler.reschedule();
+    }
+    data.events.publish(Action::Create, note.clone());
+    Ok(note)
+}
+
+pub async fn list_notes(
+    data: &AppState,
+    filter: &NoteFilter,
+    page: usize,
+    limit: usize,
+) -> Result<Vec<NoteModel>, sqlx::Error> {
+    let offset = (page.max(1) - 1) * limit;
+    sqlx::query_as!(
+        NoteModel,
+        r#"SELECT * FROM notes
+        WHERE ($1::VARCHAR IS NULL OR category = $1)
+            AND ($2::BOOLEAN IS NULL OR published = $2)
+            AND ($3::UUID IS NULL OR notebook_id = $3)
+        ORDER BY pinned DESC, position, id
+        LIMIT $4 OFFSET $5"#,
+        filter.category,
+        filter.published,
+        filter.notebook_id,
+        limit as i64,
+        offset as i64
+    )
+    .fetch_all(&data.db)
+    .await
+}
+
+pub async fn count_notes(data: &AppState, filter: &NoteFilter) -> Result<i64, sqlx::Error> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) FROM notes
+        WHERE ($1::VARCHAR IS NULL OR category = $1)
+            AND ($2::BOOLEAN IS NULL OR published = $2)
+            AND ($3::UUID IS NULL OR notebook_id = $3)"#,
+        filter.category,
+        filter.published,
+        filter.notebook_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    Ok(total.unwrap_or(0))
+}
+
+/// Case-insensitive substring search over titles and contents. Title matches
+/// rank first, then the most recently updated notes.
+pub async fn search_notes(
+    data: &AppState,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<NoteModel>, sqlx::Error> {
+    let pattern = format!("%{}%", escape_like(query));
+    sqlx::query_as!(
+        NoteModel,
+        r#"SELECT * FROM notes
+        WHERE title ILIKE $1 OR content ILIKE $1
+        ORDER BY title ILIKE $1 DESC, updated_at DESC
+        LIMIT $2"#,
+        pattern,
+        limit as i64
+    )
+    .fetch_all(&data.db)
+    .await
+}
+
+pub async fn get_note(data: &AppState, id: uuid::Uuid) -> Result<NoteModel, ApiError> {
+    sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| ApiError::note_not_found(id))
+}
+
+/// Encrypted notes are returned without their content.
+pub async fn update_note(
+    data: &AppState,
+    ctx: &AuditContext,
+    id: uuid::Uuid,
+    body: UpdateNoteSchema,
+    rewrite_references: bool,
+    password: Option<&str>,
+) -> Result<NoteModel, ApiError> {
+    let mut tx = data.db.begin().await?;
+    let old_note = sqlx::query_as!(
+        NoteModel,
+        "SELECT * FROM notes WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::note_not_found(id))?;
+
+    let encrypted = body.encrypted.unwrap_or(old_note.encrypted);
+    let (content, sealed) = match (old_note.encrypted, encrypted) {
+        (false, false) => (
+            body.content.to_owned().unwrap_or(old_note.content.clone()),
+            None,
+        ),
+        (true, true) if body.content.is_none() => (String::new(), Sealed::of(&old_note)),
+        (true, _) => {
+            let password = password.ok_or_else(wrong_password)?;
+            let plaintext = crypto::open(password, &old_note)
+                .await
+                .ok_or_else(wrong_password)?;
+            let content = body.content.to_owned().unwrap_or(plaintext);
+            if encrypted {
+                (
+                    String::new(),
+                    Some(crypto::seal(password, id, &content).await),
+                )
+            } else {
+                (content, None)
+            }
+        }
+        (false, true) => {
+            let password = password.ok_or_else(password_required)?;
+            let content = body.content.as_ref().unwrap_or(&old_note.content);
+            (
+                String::new(),
+                Some(crypto::seal(password, id, content).await),
+            )
+        }
+    };
+
+    let now = chrono::Utc::now();
+    let note = old_note.clone();
+    let mut note = sqlx::query_as!(
+        NoteModel,
+        "UPDATE notes SET title = $1, content = $2, category = $3, published = $4, updated_at = $5, publish_at = $6, unpublish_at = $7, notebook_id = $8, encrypted = $9, content_salt = $10, content_nonce = $11, content_ciphertext = $12, pinned = $13 WHERE id = $14 RETURNING *",
+        body.title.to_owned().unwrap_or(note.title),
+        content,
+        body.category.to_owned().unwrap_or(note.category.unwrap()),
+        body.published.unwrap_or(note.published.unwrap()),
+        now,
+        body.publish_at.unwrap_or(note.publish_at),
+        body.unpublish_at.unwrap_or(note.unpublish_at),
+        body.notebook_id.unwrap_or(note.notebook_id),
+        sealed.is_some(),
+        sealed.as_ref().map(|sealed| sealed.salt.clone()),
+        sealed.as_ref().map(|sealed| sealed.nonce.clone()),
+        sealed.as_ref().map(|sealed| sealed.ciphertext.clone()),
+        body.pinned.unwrap_or(note.pinned),
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(write_error)?;
+
+    if note.encrypted && !old_note.encrypted {
+        // What the note now only stores sealed must not linger in plaintext.
+        audit::redact_content(&mut tx, id).await?;
+        webhook::redact_content(&mut tx, id).await?;
+    }
+    audit::record(
+        &mut tx,
+        ctx,
+        Action::for_update(&old_note, &note),
+        Some(&old_note),
+        Some(&note),
+    )
+    .await?;
+    if body.content.is_some() || note.encrypted != old_note.encrypted {
+        sync_links(&mut tx, note.id, &note.content).await?;
+    }
+    let mut rewritten = Vec::new();
+    if rewrite_references && note.title != old_note.title {
+        rewritten = rewrite_backlinks(&mut tx, ctx, &old_note.title, &note.title, now).await?;
+        // The note may reference itself.
+        note = sqlx::query_as!(NoteModel, "SELECT * FROM notes WHERE id = $1", id)
+            .fetch_one(&mut *tx)
+            .await?;
+    }
+    let action = Action::for_update(&old_note, &note);
+    // Nor does decrypting a note send its plaintext out, to webhooks or to
+    // live subscribers.
+    let published = if old_note.encrypted {
+        note.redacted()
+    } else {
+        note.clone()
+    };
+    webhook::enqueue(&mut tx, action, &published).await?;
+    for source in rewritten.iter().filter(|source| source.id != id) {
+        webhook::enqueue(&mut tx, Action::Update, source).await?;
+    }
+    tx.commit().await?;
+    if body.publish_at.is_some() || body.unpublish_at.is_some() {
+        data.scheduler.reschedule();
+    }
+    data.events.publish(action, published);
+    for source in rewritten.into_iter().filter(|source| source.id != id) {
+        data.events.publish(Action::Update, source);
+    }
+    Ok(note)
+}
+
+/// Gives the note a position between the requested neighbours. Only the
+/// moved note is written; pinning is left as it is.
+pub async fn move_note(
+    data: &AppState,
+    ctx: &AuditContext,
+    id: uuid::Uuid,
+    body: MoveNoteSchema,
+) -> Result<NoteModel, ApiError> {
+    if body.before.is_none() && body.after.is_none() {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Give the id of a note to move before or after!",
+        ));
+    }
+    if body.before == Some(id) || body.after == Some(id) {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A note cannot be moved next to itself!",
+        ));
+    }
+
+    let mut tx = data.db.begin().await?;
+    lock_positions(&mut tx).await?;
+    let old_note = sqlx::query_as!(
+        NoteModel,
+        "SELECT * FROM notes WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::note_not_found(id))?;
+
+    let mut neighbours = Vec::new();
+    for neighbour in [body.after, body.before].into_iter().flatten() {
+        let position = sqlx::query_scalar!("SELECT position FROM notes WHERE id = $1", neighbour)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| {
+                ApiError::fail(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Note with id {} to move next to not found!", neighbour),
+                )
+            })?;
+        neighbours.push(position);
+    }
+    let (low, high) = match (body.after, body.before) {
+        (Some(_), Some(_)) => (Some(neighbours.remove(0)), Some(neighbours.remove(0))),
+        (Some(_), None) => {
+            let low = neighbours.remove(0);
+            let high = sqlx::query_scalar!(
+                "SELECT MIN(position) FROM notes WHERE position > $1 AND id <> $2",
+                low,
+                id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            (Some(low), high)
+        }
+        _ => {
+            let high = neighbours.remove(0);
+            let low = sqlx::query_scalar!(
+                "SELECT MAX(position) FROM notes WHERE position < $1 AND id <> $2",
+                high,
+                id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            (low, Some(high))
+        }
+    };
+    if let (Some(low), Some(high)) = (&low, &high) {
+        if low >= high {
+            return Err(ApiError::fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The `after` note must come before the `before` note!",
+            ));
+        }
+    }
+
+    let position = key_between(low.as_deref(), high.as_deref());
+    let note = sqlx::query_as!(
+        NoteModel,
+        "UPDATE notes SET position = $1, updated_at = NOW() WHERE id = $2 RETURNING *",
+        position,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    audit::record(&mut tx, ctx, Action::Update, Some(&old_note), Some(&note)).await?;
+    webhook::enqueue(&mut tx, Action::Update, &note).await?;
+    tx.commit().await?;
+    data.events.publish(Action::Update, note.clone());
+    if note.position.len() > ordering::MAX_KEY_LEN {
+        data.rebalancer.wake();
+    }
+    Ok(note)
+}
+
+/// Fills in the content of an encrypted note if the password opens it, and
+/// otherwise leaves the metadata-only note as it is.
+pub async fn unlock(mut note: NoteModel, password: Option<&str>) -> NoteModel {
+    if let Some(password) = password.filter(|_| note.encrypted) {
+        if let Some(content) = crypto::open(password, &note).await {
+            note.content = content;
+        }
+    }
+    note
+}
+
+/// Re-seals an encrypted note under a new password.
+pub async fn rotate_key(
+    data: &AppState,
+    ctx: &AuditContext,
+    id: uuid::Uuid,
+    password: Option<&str>,
+    new_password: &str,
+) -> Result<NoteModel, ApiError> {
+    if new_password.is_empty() {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The new password must not be empty!",
+        ));
+    }
+    let mut tx = data.db.begin().await?;
+    let note = sqlx::query_as!(
+        NoteModel,
+        "SELECT * FROM notes WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::note_not_found(id))?;
+    if !note.encrypted {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Note is not encrypted!",
+        ));
+    }
+
+    let password = password.ok_or_else(wrong_password)?;
+    let plaintext = crypto::open(password, &note)
+        .await
+        .ok_or_else(wrong_password)?;
+    let sealed = crypto::seal(new_password, id, &plaintext).await;
+    let old_note = note;
+    let note = sqlx::query_as!(
+        NoteModel,
+        "UPDATE notes SET content_salt = $1, content_nonce = $2, content_ciphertext = $3, updated_at = NOW() WHERE id = $4 RETURNING *",
+        sealed.salt,
+        sealed.nonce,
+        sealed.ciphertext,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    audit::record(&mut tx, ctx, Action::Update, Some(&old_note), Some(&note)).await?;
+    webhook::enqueue(&mut tx, Action::Update, &note).await?;
+    tx.commit().await?;
+    data.events.publish(Action::Update, note.clone());
+    Ok(note)
+}
+
+pub async fn delete_note(
+    data: &AppState,
+    ctx: &AuditContext,
+    id: uuid::Uuid,
+) -> Result<NoteModel, ApiError> {
+    let blobs = sqlx::query_scalar!(
+        "SELECT DISTINCT sha256 FROM attachments WHERE note_id = $1",
+        id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut tx = data.db.begin().await?;
+    let note = sqlx::query_as!(NoteModel, "DELETE FROM notes WHERE id = $1 RETURNING *", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    audit::record(&mut tx, ctx, Action::Delete, Some(&note), None).await?;
+    webhook::enqueue(&mut tx, Action::Delete, &note).await?;
+    tx.commit().await?;
+    data.events.publish(Action::Delete, note.clone());
+
+    for sha256 in blobs {
+        collect_blob(data, &sha256).await?;
+    }
+    Ok(note)
+}
+
+pub async fn ensure_note_exists(data: &AppState, id: uuid::Uuid) -> Result<(), ApiError> {
+    let note_exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM notes WHERE id = $1)", id)
+        .fetch_one(&data.db)
+        .await?;
+    if note_exists != Some(true) {
+        return Err(ApiError::note_not_found(id));
+    }
+    Ok(())
+}
+
+/// Removes the blob from disk once no attachment references it anymore.
+pub async fn collect_blob(data: &AppState, sha256: &str) -> Result<(), ApiError> {
+    let mut tx = data.db.begin().await?;
+    lock_blob(&mut tx, sha256).await?;
+    let references =
+        sqlx::query_scalar!("SELECT COUNT(*) FROM attachments WHERE sha256 = $1", sha256)
+            .fetch_one(&mut *tx)
+            .await?;
+    if references == Some(0) {
+        data.blobs.remove(sha256.trim()).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn lock_blob(
+    tx: &mut Transaction<'_, Postgres>,
+    sha256: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(sha256)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+/// Points every `[[old_title]]` reference at `new_title`, touching `updated_at`
+/// of each note whose content changes. Returns the rewritten notes.
+async fn rewrite_backlinks(
+    tx: &mut Transaction<'_, Postgres>,
+    ctx: &AuditContext,
+    old_title: &str,
+    new_title: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<NoteModel>, sqlx::Error> {
+    let sources = sqlx::query_as!(
+        NoteModel,
+        r#"SELECT notes.* FROM notes
+        JOIN note_links l ON l.source_id = notes.id
+        WHERE l.target_title = $1
+        FOR UPDATE OF notes"#,
+        old_title
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let mut rewritten = Vec::with_capacity(sources.len());
+    for source in sources {
+        let content = rewrite_links(&source.content, old_title, new_title);
+        let updated = sqlx::query_as!(
+            NoteModel,
+            "UPDATE notes SET content = $1, updated_at = $2 WHERE id = $3 RETURNING *",
+            content,
+            now,
+            source.id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        sync_links(tx, source.id, &content).await?;
+        audit::record(tx, ctx, Action::Update, Some(&source), Some(&updated)).await?;
+        rewritten.push(updated);
+    }
+    Ok(rewritten)
+}
+
+/// Escapes `%`, `_` and `\` so that user input matches literally in `LIKE`.
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn password_required() -> ApiError {
+    ApiError::fail(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "X-Note-Password is required to encrypt a note!",
+    )
+}
+
+fn wrong_password() -> ApiError {
+    ApiError::fail(
+        StatusCode::FORBIDDEN,
+        "Wrong or missing X-Note-Password for this note!",
+    )
+}
+
+/// Maps constraint violations on `notes` to client errors.
+fn write_error(e: sqlx::Error) -> ApiError {
+    let message = e.to_string();
+    if message.contains("duplicate key value violates unique constraint") {
+        return ApiError::fail(StatusCode::CONFLICT, "Note with that title already exists!");
+    }
+    if message.contains("notes_notebook_id_fkey") {
+        return ApiError::fail(StatusCode::UNPROCESSABLE_ENTITY, "Notebook not found!");
+    }
+    ApiError::internal(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    async fn update(
+        data: &AppState,
+        id: uuid::Uuid,
+        body: serde_json::Value,
+        password: Option<&str>,
+    ) -> NoteModel {
+        let body = serde_json::from_value(body).unwrap();
+        let ctx = AuditContext::default();
+        update_note(data, &ctx, id, body, false, password)
+            .await
+            .unwrap()
+    }
+
+    /// Every audit entry and webhook payload about the note, oldest first.
+    async fn records(data: &AppState, id: uuid::Uuid) -> (Vec<String>, Vec<String>) {
+        let audit = sqlx::query_scalar!(
+            "SELECT diff::TEXT AS \"diff!\" FROM note_audit WHERE note_id = $1 ORDER BY id",
+            id
+        )
+        .fetch_all(&data.db)
+        .await
+        .unwrap();
+        let deliveries = sqlx::query_scalar!(
+            "SELECT payload::TEXT AS \"payload!\" FROM webhook_deliveries WHERE payload #>> '{data,note,id}' = $1 ORDER BY id",
+            id.to_string()
+        )
+        .fetch_all(&data.db)
+        .await
+        .unwrap();
+        (audit, deliveries)
+    }
+
+    fn mentions(records: &(Vec<String>, Vec<String>), text: &str) -> bool {
+        records
+            .0
+            .iter()
+            .chain(&records.1)
+            .any(|record| record.contains(text))
+    }
+
+    #[tokio::test]
+    async fn plaintext_of_encrypted_notes_stays_out_of_audit_and_webhooks() {
+        let data = testing::state().await;
+        sqlx::query!(
+            "INSERT INTO webhooks (url, secret, events) VALUES ('http://127.0.0.1:9/hook', 'whsec_0123456789abcdef', ARRAY['note.created', 'note.updated'])"
+        )
+        .execute(&data.db)
+        .await
+        .unwrap();
+        let body =
+            serde_json::from_value(json!({"title": "Diary", "content": "first draft"})).unwrap();
+        let note = create_note(&data, &AuditContext::default(), body, None)
+            .await
+            .unwrap();
+
+        // Plain to plain: content is logged and sent as usual.
+        update(&data, note.id, json!({"content": "dear diary"}), None).await;
+        let logged = records(&data, note.id).await;
+        assert!(logged.0.last().unwrap().contains("dear diary"));
+        assert!(logged.1.last().unwrap().contains("dear diary"));
+
+        // Plain to encrypted: earlier plaintext is scrubbed as well.
+        update(&data, note.id, json!({"encrypted": true}), Some("hunter2")).await;
+        let logged = records(&data, note.id).await;
+        assert!(!mentions(&logged, "first draft") && !mentions(&logged, "dear diary"));
+        let last: serde_json::Value = serde_json::from_str(logged.0.last().unwrap()).unwrap();
+        assert_eq!(last["content"]["new"], "[redacted]");
+        assert_eq!(last["encrypted"], json!({"old": false, "new": true}));
+
+        // Encrypted to encrypted: the change shows, the content does not.
+        update(
+            &data,
+            note.id,
+            json!({"content": "secret plans"}),
+            Some("hunter2"),
+        )
+        .await;
+        let logged = records(&data, note.id).await;
+        assert_eq!(logged.0.len(), 4);
+        assert!(!mentions(&logged, "secret plans"));
+
+        let mut events = data.events.subscribe();
+        let ctx = AuditContext::default();
+        rotate_key(&data, &ctx, note.id, Some("hunter2"), "correct horse")
+            .await
+            .unwrap();
+        let logged = records(&data, note.id).await;
+        assert_eq!((logged.0.len(), logged.1.len()), (5, 5));
+        assert!(logged.0.last().unwrap().contains("[redacted]"));
+        assert_eq!(events.try_recv().unwrap().action, Action::Update);
+
+        // Encrypted to plain: the caller gets the content, nobody else.
+        let mut events = data.events.subscribe();
+        let note = update(
+            &data,
+            note.id,
+            json!({"encrypted": false}),
+            Some("correct horse"),
+        )
+        .await;
+        assert_eq!(note.content, "secret plans");
+        let logged = records(&data, note.id).await;
+        assert_eq!(logged.0.len(), 6);
+        assert!(!mentions(&logged, "secret plans"));
+        assert_eq!(events.try_recv().unwrap().note.content, "");
+    }
+}
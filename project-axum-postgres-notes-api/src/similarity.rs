@@ -0,0 +1,96 @@
+use sqlx::{Pool, Postgres};
+
+use crate::model::SimilarNoteModel;
+
+// Candidates are found with pg_trgm's `%` operator, which matches pairs above
+// `pg_trgm.similarity_threshold` (0.3 by default) and can use the trigram
+// indexes on `title` and `content`. Scores are rounded to four decimals.
+
+/// Weight of the title in a related-notes score; the content gets the rest.
+const TITLE_WEIGHT: f32 = 0.6;
+/// Score from which a match is reported as a likely duplicate.
+pub const DUPLICATE_THRESHOLD: f64 = 0.8;
+
+/// Notes most similar to the given one, best first.
+pub async fn related_notes(
+    db: &Pool<Postgres>,
+    id: uuid::Uuid,
+    limit: usize,
+) -> Result<Vec<SimilarNoteModel>, sqlx::Error> {
+    sqlx::query_as!(
+        SimilarNoteModel,
+        r#"SELECT n.id, n.title, n.category,
+            ROUND(
+                (similarity(n.title, t.title) * $2 + similarity(n.content, t.content) * (1 - $2))::NUMERIC,
+                4
+            )::FLOAT8 AS "score!"
+        FROM notes t
+        JOIN notes n ON n.id <> t.id
+        WHERE t.id = $1 AND (n.title % t.title OR n.content % t.content)
+        ORDER BY 4 DESC, n.title
+        LIMIT $3"#,
+        id,
+        TITLE_WEIGHT,
+        limit as i64
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Existing notes that a note with this title (and content, if given) would
+/// resemble. Each match scores by whichever of title or content is closer, so
+/// a renamed copy is caught as well as a retitled one.
+pub async fn similar_notes(
+    db: &Pool<Postgres>,
+    title: &str,
+    content: Option<&str>,
+    exclude_id: Option<uuid::Uuid>,
+    limit: usize,
+) -> Result<Vec<SimilarNoteModel>, sqlx::Error> {
+    sqlx::query_as!(
+        SimilarNoteModel,
+        r#"SELECT id, title, category,
+            ROUND(
+                GREATEST(similarity(title, $1), COALESCE(similarity(content, $2), 0))::NUMERIC,
+                4
+            )::FLOAT8 AS "score!"
+        FROM notes
+        WHERE (title % $1 OR ($2::TEXT IS NOT NULL AND content % $2))
+            AND ($3::UUID IS NULL OR id <> $3)
+        ORDER BY 4 DESC, title
+        LIMIT $4"#,
+        title,
+        content,
+        exclude_id,
+        limit as i64
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub fn is_duplicate(matches: &[SimilarNoteModel]) -> bool {
+    matches
+        .iter()
+        .any(|candidate| candidate.score >= DUPLICATE_THRESHOLD)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(score: f64) -> SimilarNoteModel {
+        SimilarNoteModel {
+            id: uuid::Uuid::nil(),
+            title: "Meeting notes".to_string(),
+            category: None,
+            score,
+        }
+    }
+
+    #[test]
+    fn duplicate_needs_a_close_match() {
+        assert!(!is_duplicate(&[]));
+        assert!(!is_duplicate(&[candidate(0.5), candidate(0.79)]));
+        assert!(is_duplicate(&[candidate(0.5), candidate(0.8)]));
+    }
+}
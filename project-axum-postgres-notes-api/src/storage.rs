@@ -0,0 +1,180 @@
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Content-addressed blob storage on local disk.
+///
+/// Every blob lives at `<root>/<first two hex chars>/<sha256>`, so uploading the
+/// same file twice stores it once. Uploads are first written to `<root>/tmp` and
+/// only moved into place once their hash is known.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+/// A blob that has been fully written and hashed but not yet moved into place.
+/// Dropping it removes the staged file.
+#[derive(Debug)]
+pub struct StagedBlob {
+    pub sha256: String,
+    pub size: i64,
+    temp: TempFile,
+}
+
+/// Dropping a writer before it is finished removes what it wrote, e.g. when
+/// the client goes away mid-upload.
+pub struct BlobWriter {
+    file: fs::File,
+    temp: TempFile,
+    hasher: Sha256,
+    size: i64,
+}
+
+/// A file under `<root>/tmp`, removed when dropped unless it was kept.
+#[derive(Debug)]
+struct TempFile {
+    path: Option<PathBuf>,
+}
+
+impl TempFile {
+    fn path(&self) -> &PathBuf {
+        self.path.as_ref().expect("only kept files lose their path")
+    }
+
+    fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            // Best effort; there is no one to report a failure to here.
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl BlobStore {
+    pub async fn new(root: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(root.join("tmp")).await?;
+        Ok(BlobStore { root })
+    }
+
+    pub fn path(&self, sha256: &str) -> PathBuf {
+        self.root.join(&sha256[..2]).join(sha256)
+    }
+
+    pub async fn writer(&self) -> io::Result<BlobWriter> {
+        let temp_path = self.root.join("tmp").join(uuid::Uuid::new_v4().to_string());
+        let file = fs::File::create(&temp_path).await?;
+        Ok(BlobWriter {
+            file,
+            temp: TempFile {
+                path: Some(temp_path),
+            },
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    /// Moves a staged blob into place. If a blob with the same hash already
+    /// exists the staged copy is dropped instead.
+    pub async fn commit(&self, staged: StagedBlob) -> io::Result<()> {
+        let path = self.path(&staged.sha256);
+        if fs::try_exists(&path).await? {
+            return self.discard(staged).await;
+        }
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        fs::rename(staged.temp.path(), &path).await?;
+        staged.temp.keep();
+        Ok(())
+    }
+
+    pub async fn discard(&self, staged: StagedBlob) -> io::Result<()> {
+        remove_if_exists(staged.temp.path().clone()).await?;
+        staged.temp.keep();
+        Ok(())
+    }
+
+    pub async fn remove(&self, sha256: &str) -> io::Result<()> {
+        remove_if_exists(self.path(sha256)).await
+    }
+}
+
+impl BlobWriter {
+    pub fn size(&self) -> i64 {
+        self.size
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as i64;
+        self.file.write_all(chunk).await
+    }
+
+    pub async fn finish(mut self) -> io::Result<StagedBlob> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        Ok(StagedBlob {
+            sha256: hex::encode(self.hasher.finalize()),
+            size: self.size,
+            temp: self.temp,
+        })
+    }
+
+    pub async fn abort(self) -> io::Result<()> {
+        drop(self.file);
+        remove_if_exists(self.temp.path().clone()).await?;
+        self.temp.keep();
+        Ok(())
+    }
+}
+
+async fn remove_if_exists(path: PathBuf) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn store() -> (BlobStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("blobs-{}", uuid::Uuid::new_v4()));
+        (BlobStore::new(root.clone()).await.unwrap(), root)
+    }
+
+    async fn staged_files(root: &std::path::Path) -> usize {
+        std::fs::read_dir(root.join("tmp")).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn dropped_uploads_leave_nothing_behind() {
+        let (blobs, root) = store().await;
+        let mut writer = blobs.writer().await.unwrap();
+        writer.write(b"half an upl").await.unwrap();
+        assert_eq!(staged_files(&root).await, 1);
+        drop(writer);
+        assert_eq!(staged_files(&root).await, 0);
+
+        let mut writer = blobs.writer().await.unwrap();
+        writer.write(b"a whole upload").await.unwrap();
+        let staged = writer.finish().await.unwrap();
+        drop(staged);
+        assert_eq!(staged_files(&root).await, 0);
+
+        let mut writer = blobs.writer().await.unwrap();
+        writer.write(b"a kept upload").await.unwrap();
+        let staged = writer.finish().await.unwrap();
+        let path = blobs.path(&staged.sha256);
+        blobs.commit(staged).await.unwrap();
+        assert!(path.exists());
+        assert_eq!(staged_files(&root).await, 0);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
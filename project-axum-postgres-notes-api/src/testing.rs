@@ -0,0 +1,104 @@
+//! Helpers for tests that need Postgres, which run against the server in
+//! `DATABASE_URL`, just like the compile-time query checks.
+//!
+//! Every test gets a database of its own, named `notes_test_<uuid>` and
+//! migrated from scratch, so tests can run in parallel without seeing each
+//! other's rows. Databases left behind by earlier runs are dropped the first
+//! time one is asked for.
+
+use dotenv::dotenv;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, Executor, PgConnection, Pool, Postgres};
+use std::str::FromStr;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::events::NoteEvents;
+use crate::graphql;
+use crate::ordering::Rebalancer;
+use crate::scheduler::Scheduler;
+use crate::storage::BlobStore;
+use crate::webhook::WebhookDispatcher;
+use crate::AppState;
+
+const PREFIX: &str = "notes_test_";
+
+/// Held while creating a database, since Postgres refuses to copy
+/// `template1` for two of them at once.
+static CREATING: Mutex<bool> = Mutex::const_new(false);
+
+/// A pool on a new, fully migrated database.
+pub async fn database() -> Pool<Postgres> {
+    dotenv().ok();
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let options = PgConnectOptions::from_str(&url).expect("DATABASE_URL must be valid");
+    let name = format!("{}{}", PREFIX, uuid::Uuid::new_v4().simple());
+
+    {
+        let mut swept = CREATING.lock().await;
+        let mut admin = options
+            .connect()
+            .await
+            .expect("Failed to connect to Postgres");
+        if !*swept {
+            drop_leftovers(&mut admin).await;
+            *swept = true;
+        }
+        admin
+            .execute(format!("CREATE DATABASE {}", name).as_str())
+            .await
+            .expect("Failed to create a test database");
+        admin.close().await.ok();
+    }
+
+    let db = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options.database(&name))
+        .await
+        .expect("Failed to connect to the test database");
+    sqlx::migrate!()
+        .run(&db)
+        .await
+        .expect("Failed to migrate the test database");
+    db
+}
+
+/// An application on a new database, configured from the environment like
+/// the server, with attachments kept in a temporary directory.
+pub async fn state() -> AppState {
+    let db = database().await;
+    let config = Config {
+        attachments_dir: std::env::temp_dir().join(format!("{}{}", PREFIX, uuid::Uuid::new_v4())),
+        ..Config::init()
+    };
+    let blobs = BlobStore::new(config.attachments_dir.clone())
+        .await
+        .expect("Failed to open the attachment store");
+    let events = NoteEvents::default();
+    AppState {
+        scheduler: Scheduler::spawn(db.clone(), config.scheduler_interval, events.clone()),
+        rebalancer: Rebalancer::spawn(db.clone()),
+        webhooks: WebhookDispatcher::spawn(db.clone(), &config, &events),
+        graphql: graphql::build_schema(),
+        db,
+        config,
+        blobs,
+        events,
+    }
+}
+
+/// Drops the databases of earlier runs; those still in use by a run going on
+/// in parallel refuse to be dropped and are skipped.
+async fn drop_leftovers(admin: &mut PgConnection) {
+    let names: Vec<String> =
+        sqlx::query_scalar("SELECT datname FROM pg_database WHERE starts_with(datname, $1)")
+            .bind(PREFIX)
+            .fetch_all(&mut *admin)
+            .await
+            .unwrap_or_default();
+    for name in names {
+        let _ = admin
+            .execute(format!("DROP DATABASE IF EXISTS {}", name).as_str())
+            .await;
+    }
+}
@@ -0,0 +1,196 @@
+use axum::extract::Host;
+use axum::http::header::STRICT_TRANSPORT_SECURITY;
+use axum::http::{HeaderValue, Uri};
+use axum::response::Redirect;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use notify::{RecursiveMode, Watcher};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+use crate::config::TlsConfig;
+
+/// Certificate renewals tend to write several files in a row; wait for them
+/// to settle before reloading.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Serves `app` over HTTPS, and plain HTTP on `http_addr` either as well or
+/// only to redirect to HTTPS.
+pub async fn serve(app: Router, http_addr: SocketAddr, tls: TlsConfig) -> io::Result<()> {
+    let pem = read_pem(&tls.cert_path, &tls.key_path).await?;
+    check_certificate(&pem.0, &tls.cert_path)?;
+    let rustls = RustlsConfig::from_pem(pem.0.clone(), pem.1.clone()).await?;
+    watch_certificate(rustls.clone(), &tls, pem).map_err(io::Error::other)?;
+
+    let https_app = app.clone().layer(SetResponseHeaderLayer::overriding(
+        STRICT_TRANSPORT_SECURITY,
+        hsts(tls.hsts_max_age),
+    ));
+    let http_app = if tls.redirect_http {
+        let https_port = tls.https_port;
+        Router::new().fallback(move |Host(host): Host, uri: Uri| async move {
+            Redirect::permanent(&https_url(&host, &uri, https_port))
+        })
+    } else {
+        app
+    };
+
+    let https_addr = SocketAddr::from(([0, 0, 0, 0], tls.https_port));
+    let https = axum_server::bind_rustls(https_addr, rustls)
+        .serve(https_app.into_make_service_with_connect_info::<SocketAddr>());
+    let http = async {
+        axum::Server::bind(&http_addr)
+            .serve(http_app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(io::Error::other)
+    };
+    tokio::try_join!(https, http)?;
+    Ok(())
+}
+
+/// Reloads the certificate and key whenever either changes. A pair that fails
+/// to load is reported and the one in use is kept.
+fn watch_certificate(
+    rustls: RustlsConfig,
+    tls: &TlsConfig,
+    mut loaded: (Vec<u8>, Vec<u8>),
+) -> notify::Result<()> {
+    let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Reading the files ourselves shows up as access events.
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = sender.send(());
+        }
+    })?;
+    // Watch the directories: renewals usually replace the files (or swap a
+    // symlink) rather than write into them.
+    let mut dirs = vec![parent_dir(&tls.cert_path), parent_dir(&tls.key_path)];
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    let cert_path = tls.cert_path.clone();
+    let key_path = tls.key_path.clone();
+    tokio::spawn(async move {
+        // Dropping the watcher would stop the events.
+        let _watcher = watcher;
+        while changes.recv().await.is_some() {
+            tokio::time::sleep(SETTLE).await;
+            while changes.try_recv().is_ok() {}
+            let reloaded = match read_pem(&cert_path, &key_path).await {
+                // Something else in the directories changed.
+                Ok(pem) if pem == loaded => continue,
+                Ok(pem) => {
+                    loaded = pem.clone();
+                    match check_certificate(&pem.0, &cert_path) {
+                        Ok(()) => rustls.reload_from_pem(pem.0, pem.1).await,
+                        Err(err) => Err(err),
+                    }
+                }
+                Err(err) => Err(err),
+            };
+            match reloaded {
+                Ok(()) => println!("🔐 Reloaded the TLS certificate"),
+                Err(err) => println!(
+                    "❌ Failed to reload the TLS certificate, keeping the current one: {:?}",
+                    err
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn read_pem(cert_path: &Path, key_path: &Path) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    Ok((
+        tokio::fs::read(cert_path).await?,
+        tokio::fs::read(key_path).await?,
+    ))
+}
+
+/// Refuses a chain without any certificate: rustls would accept it and then
+/// fail every handshake.
+fn check_certificate(cert: &[u8], cert_path: &Path) -> io::Result<()> {
+    if rustls_pemfile::certs(&mut &cert[..])?.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificate found in {}", cert_path.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn hsts(max_age: Duration) -> HeaderValue {
+    HeaderValue::from_str(&format!("max-age={}", max_age.as_secs())).unwrap()
+}
+
+/// The HTTPS address of the requested resource, on `https_port` of the same host.
+fn https_url(host: &str, uri: &Uri, https_port: u16) -> String {
+    let hostname = match host.find(']') {
+        // An IPv6 literal such as `[::1]:8000`.
+        Some(end) if host.starts_with('[') => &host[..=end],
+        _ => host.split(':').next().unwrap_or(host),
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    if https_port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, https_port, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redirects_keep_host_path_and_query() {
+        let uri: Uri = "/api/notes?page=2".parse().unwrap();
+        assert_eq!(
+            https_url("notes.example.com:8000", &uri, 8443),
+            "https://notes.example.com:8443/api/notes?page=2"
+        );
+        assert_eq!(
+            https_url("notes.example.com", &uri, 443),
+            "https://notes.example.com/api/notes?page=2"
+        );
+        assert_eq!(
+            https_url("[::1]:8000", &"/".parse().unwrap(), 8443),
+            "https://[::1]:8443/"
+        );
+    }
+
+    #[test]
+    fn rejects_a_certificate_file_without_certificates() {
+        let path = Path::new("cert.pem");
+        let err = check_certificate(b"garbage", path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let pem = "-----BEGIN CERTIFICATE-----\nMAA=\n-----END CERTIFICATE-----\n";
+        assert!(check_certificate(pem.as_bytes(), path).is_ok());
+    }
+
+    #[test]
+    fn hsts_header() {
+        assert_eq!(hsts(Duration::from_secs(31536000)), "max-age=31536000");
+    }
+
+    #[test]
+    fn watches_the_directory_of_bare_file_names() {
+        assert_eq!(parent_dir(Path::new("cert.pem")), Path::new("."));
+        assert_eq!(
+            parent_dir(Path::new("/etc/notes/cert.pem")),
+            Path::new("/etc/notes")
+        );
+    }
+}
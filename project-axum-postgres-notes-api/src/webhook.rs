@@ -0,0 +1,576 @@
+use axum::http::StatusCode;
+use futures_util::future;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{Pool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+use crate::audit::Action;
+use crate::config::Config;
+use crate::events::{NoteEvent, NoteEvents};
+use crate::model::{NoteModel, WebhookDeliveryModel, WebhookModel};
+use crate::response::ApiError;
+use crate::schema::{CreateWebhookSchema, DeliveryFilterOptions, UpdateWebhookSchema};
+use crate::AppState;
+
+// Deliveries go through an outbox: `enqueue` writes one row per subscribed
+// webhook in the same transaction as the note change, and the dispatcher sends
+// them after commit. A change is therefore never announced without being
+// saved, nor lost when the process stops before sending. Receivers may see a
+// delivery more than once and should deduplicate on `X-Webhook-Delivery`.
+
+pub const EVENTS: [&str; 4] = [
+    "note.created",
+    "note.updated",
+    "note.deleted",
+    "note.published",
+];
+
+/// Deliveries claimed per round.
+const BATCH_SIZE: i64 = 50;
+/// Upper bound on how long the dispatcher sleeps when nothing wakes it.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BASE_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Unpublishing is reported as a plain update.
+pub fn event_name(action: Action) -> &'static str {
+    match action {
+        Action::Create => "note.created",
+        Action::Update | Action::Unpublish => "note.updated",
+        Action::Delete => "note.deleted",
+        Action::Publish => "note.published",
+    }
+}
+
+/// Queues the change for every active webhook subscribed to it.
+pub async fn enqueue(
+    tx: &mut Transaction<'_, Postgres>,
+    action: Action,
+    note: &NoteModel,
+) -> Result<(), sqlx::Error> {
+    let event = event_name(action);
+    let payload = serde_json::json!({
+        "event": event,
+        "createdAt": chrono::Utc::now(),
+        "data": { "note": note },
+    });
+    sqlx::query!(
+        r#"INSERT INTO webhook_deliveries (webhook_id, event, payload)
+        SELECT id, $1::VARCHAR, $2 FROM webhooks WHERE active AND $1::VARCHAR = ANY(events)"#,
+        event,
+        payload
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Blanks the content of the note in every delivery about it, queued or
+/// logged, for when it gets encrypted.
+pub async fn redact_content(
+    tx: &mut Transaction<'_, Postgres>,
+    note_id: uuid::Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE webhook_deliveries SET payload = jsonb_set(payload, '{data,note,content}', '""')
+        WHERE payload #>> '{data,note,id}' = $1"#,
+        note_id.to_string()
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub async fn create(data: &AppState, body: CreateWebhookSchema) -> Result<WebhookModel, ApiError> {
+    validate_url(&body.url)?;
+    let events = validate_events(body.events)?;
+    let secret = match body.secret {
+        Some(secret) if secret.len() < 16 => {
+            return Err(ApiError::fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Webhook secret must be at least 16 characters long!",
+            ));
+        }
+        Some(secret) => secret,
+        None => format!("whsec_{}", uuid::Uuid::new_v4().simple()),
+    };
+
+    let webhook = sqlx::query_as!(
+        WebhookModel,
+        "INSERT INTO webhooks (url, secret, events) VALUES ($1, $2, $3) RETURNING *",
+        body.url,
+        secret,
+        &events[..]
+    )
+    .fetch_one(&data.db)
+    .await?;
+    Ok(webhook)
+}
+
+pub async fn list(data: &AppState) -> Result<Vec<WebhookModel>, sqlx::Error> {
+    sqlx::query_as!(WebhookModel, "SELECT * FROM webhooks ORDER BY created_at")
+        .fetch_all(&data.db)
+        .await
+}
+
+pub async fn get(data: &AppState, id: uuid::Uuid) -> Result<WebhookModel, ApiError> {
+    sqlx::query_as!(WebhookModel, "SELECT * FROM webhooks WHERE id = $1", id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| not_found(id))
+}
+
+pub async fn update(
+    data: &AppState,
+    id: uuid::Uuid,
+    body: UpdateWebhookSchema,
+) -> Result<WebhookModel, ApiError> {
+    if let Some(url) = &body.url {
+        validate_url(url)?;
+    }
+    let events = body.events.map(validate_events).transpose()?;
+
+    let webhook = sqlx::query_as!(
+        WebhookModel,
+        r#"UPDATE webhooks SET
+            url = COALESCE($1, url),
+            events = COALESCE($2, events),
+            active = COALESCE($3, active),
+            updated_at = NOW()
+        WHERE id = $4
+        RETURNING *"#,
+        body.url,
+        events.as_deref(),
+        body.active,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| not_found(id))?;
+    if body.active == Some(true) {
+        // Deliveries queued before the webhook was paused are still pending.
+        data.webhooks.wake();
+    }
+    Ok(webhook)
+}
+
+pub async fn delete(data: &AppState, id: uuid::Uuid) -> Result<(), ApiError> {
+    let rows_affected = sqlx::query!("DELETE FROM webhooks WHERE id = $1", id)
+        .execute(&data.db)
+        .await?
+        .rows_affected();
+    if rows_affected == 0 {
+        return Err(not_found(id));
+    }
+    Ok(())
+}
+
+/// The delivery log of a webhook, newest first.
+pub async fn deliveries(
+    data: &AppState,
+    id: uuid::Uuid,
+    opts: DeliveryFilterOptions,
+) -> Result<Vec<WebhookDeliveryModel>, ApiError> {
+    get(data, id).await?;
+    let page = opts.page.unwrap_or(1).max(1);
+    let limit = opts.limit.unwrap_or(50);
+    let offset = (page - 1) * limit;
+
+    let deliveries = sqlx::query_as!(
+        WebhookDeliveryModel,
+        r#"SELECT * FROM webhook_deliveries
+        WHERE webhook_id = $1 AND ($2::VARCHAR IS NULL OR status = $2)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3 OFFSET $4"#,
+        id,
+        opts.status,
+        limit as i64,
+        offset as i64
+    )
+    .fetch_all(&data.db)
+    .await?;
+    Ok(deliveries)
+}
+
+/// Queues a fresh copy of a past delivery, whatever became of the original.
+pub async fn redeliver(
+    data: &AppState,
+    id: uuid::Uuid,
+    delivery_id: i64,
+) -> Result<WebhookDeliveryModel, ApiError> {
+    let delivery = sqlx::query_as!(
+        WebhookDeliveryModel,
+        r#"INSERT INTO webhook_deliveries (webhook_id, event, payload)
+        SELECT webhook_id, event, payload FROM webhook_deliveries
+        WHERE id = $1 AND webhook_id = $2
+        RETURNING *"#,
+        delivery_id,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| {
+        ApiError::fail(
+            StatusCode::NOT_FOUND,
+            format!("Delivery with id {} not found!", delivery_id),
+        )
+    })?;
+    data.webhooks.wake();
+    Ok(delivery)
+}
+
+/// Sends queued deliveries in the background.
+///
+/// Like the publishing scheduler, it keeps no state of its own. Due rows are
+/// claimed by pushing `next_attempt_at` past the request timeout, so several
+/// instances can run side by side, and a delivery whose sender died is picked
+/// up again once that lease runs out.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    wake: Arc<Notify>,
+}
+
+impl WebhookDispatcher {
+    pub fn spawn(db: Pool<Postgres>, config: &Config, events: &NoteEvents) -> WebhookDispatcher {
+        let wake = Arc::new(Notify::new());
+        let client = reqwest::Client::builder()
+            .timeout(config.webhook_timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build the webhook HTTP client");
+        let dispatcher = Dispatcher {
+            db,
+            client,
+            max_attempts: config.webhook_max_attempts,
+            lease: config.webhook_timeout + Duration::from_secs(60),
+        };
+        tokio::spawn(run(dispatcher, events.subscribe(), wake.clone()));
+        WebhookDispatcher { wake }
+    }
+
+    /// Makes the dispatcher look for due deliveries right away.
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+}
+
+struct Dispatcher {
+    db: Pool<Postgres>,
+    client: reqwest::Client,
+    max_attempts: i32,
+    lease: Duration,
+}
+
+/// A claimed delivery together with where and how to send it.
+struct DueDelivery {
+    id: i64,
+    event: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+struct Outcome {
+    status_code: Option<u16>,
+    error: Option<String>,
+}
+
+async fn run(
+    dispatcher: Dispatcher,
+    mut changes: broadcast::Receiver<NoteEvent>,
+    wake: Arc<Notify>,
+) {
+    loop {
+        match dispatcher.dispatch_due().await {
+            Ok(0) => {}
+            Ok(count) => println!("📨 Attempted {} webhook delivery(ies)", count),
+            Err(err) => println!("❌ Failed to dispatch webhook deliveries: {:?}", err),
+        }
+
+        // Every committed note change may have queued deliveries.
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = wake.notified() => {}
+            _ = changes.recv() => {}
+        }
+    }
+}
+
+impl Dispatcher {
+    /// Sends every due delivery and returns how many were attempted.
+    async fn dispatch_due(&self) -> Result<usize, sqlx::Error> {
+        let mut total = 0;
+        loop {
+            let due = sqlx::query_as!(
+                DueDelivery,
+                r#"UPDATE webhook_deliveries d
+                SET next_attempt_at = NOW() + make_interval(secs => $1)
+                FROM webhooks w
+                WHERE w.id = d.webhook_id AND d.id IN (
+                    SELECT due.id FROM webhook_deliveries due
+                    JOIN webhooks hook ON hook.id = due.webhook_id
+                    WHERE due.status = 'pending' AND due.next_attempt_at <= NOW() AND hook.active
+                    ORDER BY due.next_attempt_at, due.id
+                    LIMIT $2
+                    FOR UPDATE OF due SKIP LOCKED
+                )
+                RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret"#,
+                self.lease.as_secs_f64(),
+                BATCH_SIZE
+            )
+            .fetch_all(&self.db)
+            .await?;
+
+            let outcomes =
+                future::join_all(due.iter().map(|delivery| send(&self.client, delivery))).await;
+            for (delivery, outcome) in due.iter().zip(outcomes) {
+                self.record(delivery, outcome).await?;
+            }
+
+            total += due.len();
+            if (due.len() as i64) < BATCH_SIZE {
+                return Ok(total);
+            }
+        }
+    }
+
+    async fn record(&self, delivery: &DueDelivery, outcome: Outcome) -> Result<(), sqlx::Error> {
+        let attempts = delivery.attempts + 1;
+        let status = match outcome.error {
+            None => "delivered",
+            Some(_) if attempts >= self.max_attempts => "dead",
+            Some(_) => "pending",
+        };
+        sqlx::query!(
+            r#"UPDATE webhook_deliveries SET
+                status = $1::VARCHAR,
+                attempts = $2,
+                next_attempt_at = NOW() + make_interval(secs => $3),
+                last_status_code = $4,
+                last_error = $5,
+                delivered_at = CASE WHEN $1::VARCHAR = 'delivered' THEN NOW() END
+            WHERE id = $6"#,
+            status,
+            attempts,
+            backoff(attempts).as_secs_f64(),
+            outcome.status_code.map(|code| code as i16),
+            outcome.error,
+            delivery.id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Posts the payload; any 2xx response counts as delivered.
+async fn send(client: &reqwest::Client, delivery: &DueDelivery) -> Outcome {
+    let body = serde_json::to_vec(&delivery.payload).unwrap();
+    let signature = signature(&delivery.secret, chrono::Utc::now().timestamp(), &body);
+    let response = client
+        .post(&delivery.url)
+        .header("content-type", "application/json")
+        .header("x-webhook-event", &delivery.event)
+        .header("x-webhook-delivery", delivery.id.to_string())
+        .header("x-webhook-signature", signature)
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => Outcome {
+            status_code: Some(response.status().as_u16()),
+            error: None,
+        },
+        Ok(response) => Outcome {
+            status_code: Some(response.status().as_u16()),
+            error: Some(format!("Receiver responded with {}", response.status())),
+        },
+        Err(err) => Outcome {
+            status_code: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+/// `X-Webhook-Signature` value: `t=<unix time>,v1=<hex>`, where the hex is
+/// HMAC-SHA256 over `<unix time>.<body>` keyed with the webhook secret.
+/// Receivers should recompute it and reject stale timestamps.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Delay before the attempt after `attempts` failed ones: doubling from ten
+/// seconds, up to an hour.
+fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    (BASE_BACKOFF * 2u32.pow(exponent)).min(MAX_BACKOFF)
+}
+
+fn validate_url(url: &str) -> Result<(), ApiError> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Webhook URL must be an absolute http or https URL!",
+        )),
+    }
+}
+
+fn validate_events(mut events: Vec<String>) -> Result<Vec<String>, ApiError> {
+    if let Some(unknown) = events
+        .iter()
+        .find(|event| !EVENTS.contains(&event.as_str()))
+    {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Unknown webhook event {:?}; expected one of {}",
+                unknown,
+                EVENTS.join(", ")
+            ),
+        ));
+    }
+    events.sort();
+    events.dedup();
+    if events.is_empty() {
+        return Err(ApiError::fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A webhook must subscribe to at least one event!",
+        ));
+    }
+    Ok(events)
+}
+
+fn not_found(id: uuid::Uuid) -> ApiError {
+    ApiError::fail(
+        StatusCode::NOT_FOUND,
+        format!("Webhook with id {} not found!", id),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+
+    /// Serves `status` on an ephemeral port and forwards what it receives.
+    async fn receiver(
+        status: StatusCode,
+    ) -> (String, mpsc::UnboundedReceiver<(HeaderMap, Vec<u8>)>) {
+        let (sender, received) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    sender.send((headers, body.to_vec())).unwrap();
+                    status
+                },
+            ),
+        );
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn delivery(url: String) -> DueDelivery {
+        DueDelivery {
+            id: 7,
+            event: "note.created".to_string(),
+            payload: serde_json::json!({ "event": "note.created" }),
+            attempts: 0,
+            url,
+            secret: "whsec_test".to_string(),
+        }
+    }
+
+    #[test]
+    fn signature_is_hmac_of_timestamp_and_body() {
+        assert_eq!(
+            signature("whsec_test", 1700000000, br#"{"event":"note.created"}"#),
+            "t=1700000000,v1=ccee60d908b6586927ef017430846d9aca0ebb59e9dcce185ae4e9921ee8d56b"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(2), Duration::from_secs(20));
+        assert_eq!(backoff(4), Duration::from_secs(80));
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(i32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn events_are_validated() {
+        let events = validate_events(vec![
+            "note.updated".to_string(),
+            "note.created".to_string(),
+            "note.updated".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(events, vec!["note.created", "note.updated"]);
+        assert!(validate_events(vec![]).is_err());
+        assert!(validate_events(vec!["note.archived".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_signed_payload() {
+        let (url, mut received) = receiver(StatusCode::NO_CONTENT).await;
+        let outcome = send(&reqwest::Client::new(), &delivery(url)).await;
+        assert_eq!(outcome.status_code, Some(204));
+        assert!(outcome.error.is_none());
+
+        let (headers, body) = received.recv().await.unwrap();
+        assert_eq!(body, br#"{"event":"note.created"}"#);
+        assert_eq!(headers["x-webhook-event"], "note.created");
+        assert_eq!(headers["x-webhook-delivery"], "7");
+        let signed = headers["x-webhook-signature"].to_str().unwrap();
+        let timestamp = signed
+            .strip_prefix("t=")
+            .and_then(|rest| rest.split(',').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(signed, signature("whsec_test", timestamp, &body));
+    }
+
+    #[tokio::test]
+    async fn error_responses_fail_the_attempt() {
+        let (url, _received) = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let outcome = send(&reqwest::Client::new(), &delivery(url)).await;
+        assert_eq!(outcome.status_code, Some(500));
+        assert!(outcome.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn unreachable_receivers_fail_the_attempt() {
+        // Nothing listens on a port once its listener is dropped.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+        let outcome = send(&reqwest::Client::new(), &delivery(url)).await;
+        assert_eq!(outcome.status_code, None);
+        assert!(outcome.error.is_some());
+    }
+}
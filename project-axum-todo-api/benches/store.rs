@@ -0,0 +1,77 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+use std::thread;
+
+use project_axum_todo_api::model::Task;
+use project_axum_todo_api::repository::TaskRepository;
+use project_axum_todo_api::store::TaskStore;
+
+const TASKS: usize = 100_000;
+
+fn filled_store() -> (TaskStore, Vec<String>) {
+    let store = TaskStore::new();
+    let ids = (0..TASKS)
+        .map(|i| {
+            let task = Task {
+                id: Some(uuid::Uuid::new_v4().to_string()),
+                title: format!("Task {}", i),
+                content: "Lorem ipsum dolor sit amet".to_string(),
+                completed: Some(false),
+                createdAt: Some(chrono::Utc::now()),
+                updatedAt: Some(chrono::Utc::now()),
+                ..Default::default()
+            };
+            store.insert(task).unwrap().id.unwrap()
+        })
+        .collect();
+    (store, ids)
+}
+
+fn store_at_100k(c: &mut Criterion) {
+    let (store, ids) = filled_store();
+
+    c.bench_function("get by id (100k tasks)", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 7919) % TASKS;
+            black_box(store.get(&ids[i]).unwrap())
+        })
+    });
+    c.bench_function("list first page of 10 (100k tasks)", |b| {
+        b.iter(|| black_box(store.list(0, 10).unwrap()))
+    });
+    c.bench_function("list last page of 10 (100k tasks)", |b| {
+        b.iter(|| black_box(store.list(TASKS - 10, 10).unwrap()))
+    });
+
+    // Lookups while three other threads keep reading: readers share the lock.
+    let store = Arc::new(store);
+    let ids = Arc::new(ids);
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let readers: Vec<_> = (0..3)
+        .map(|n| {
+            let (store, ids, stop) = (store.clone(), ids.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut i = n;
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    i = (i + 7919) % TASKS;
+                    black_box(store.get(&ids[i]).unwrap());
+                }
+            })
+        })
+        .collect();
+    c.bench_function("get by id with 3 concurrent readers (100k tasks)", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 7919) % TASKS;
+            black_box(store.get(&ids[i]).unwrap())
+        })
+    });
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+criterion_group!(benches, store_at_100k);
+criterion_main!(benches);
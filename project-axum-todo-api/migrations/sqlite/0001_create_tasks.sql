@@ -0,0 +1,29 @@
+-- Tasks are stored as the JSON the API returns; the columns next to it are
+-- what the store looks them up and orders them by.
+CREATE TABLE tasks (
+    id TEXT PRIMARY KEY NOT NULL,
+    -- Insertion order, which pages follow.
+    position INTEGER NOT NULL UNIQUE,
+    seq INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE INDEX tasks_seq ON tasks (seq);
+
+CREATE TABLE tombstones (
+    id TEXT PRIMARY KEY NOT NULL,
+    seq INTEGER NOT NULL,
+    -- Microseconds since the epoch, to find the old ones.
+    deleted_at INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE INDEX tombstones_seq ON tombstones (seq);
+
+CREATE TABLE sync_state (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    seq INTEGER NOT NULL,
+    horizon INTEGER NOT NULL
+);
+
+INSERT INTO sync_state (id, seq, horizon) VALUES (0, 0, 0);
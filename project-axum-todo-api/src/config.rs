@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::history;
+use crate::persist::Fsync;
+
+/// Where the tasks are kept, from `STORAGE_URL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Storage {
+    /// `memory:`; nothing survives a restart.
+    Memory,
+    /// `files:<dir>`, a snapshot and write-ahead log in `dir`. The default,
+    /// in `DATA_DIR`.
+    Files(PathBuf),
+    /// `sqlite:<path>`, or `sqlite::memory:`. Needs the `sqlite` feature.
+    Sqlite(String),
+}
+
+impl Storage {
+    fn parse(url: &str) -> Option<Storage> {
+        let (scheme, rest) = url.split_once(':')?;
+        match scheme {
+            "memory" if rest.is_empty() => Some(Storage::Memory),
+            "files" if !rest.is_empty() => Some(Storage::Files(rest.into())),
+            "sqlite" if !rest.is_empty() => {
+                let path = rest.strip_prefix("//").unwrap_or(rest);
+                Some(Storage::Sqlite(path.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub storage: Storage,
+    pub fsync: Fsync,
+    /// How often the log is folded into a fresh snapshot.
+    pub compaction_interval: Duration,
+    /// How long deleted tasks are remembered for clients that sync.
+    pub tombstone_retention: Duration,
+    /// How many changes each client can undo.
+    pub history_depth: usize,
+}
+
+impl Config {
+    pub fn init() -> Config {
+        let storage = match std::env::var("STORAGE_URL") {
+            Ok(url) => Storage::parse(&url).unwrap_or_else(|| {
+                panic!(
+                    "STORAGE_URL must be memory:, files:<dir> or sqlite:<path>, not {:?}",
+                    url
+                )
+            }),
+            Err(_) => Storage::Files(
+                std::env::var("DATA_DIR")
+                    .unwrap_or_else(|_| "./data".to_string())
+                    .into(),
+            ),
+        };
+        let fsync_interval = std::env::var("FSYNC_INTERVAL_MS")
+            .map(|v| v.parse().expect("FSYNC_INTERVAL_MS must be a number"))
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(1));
+        let fsync = match std::env::var("FSYNC").as_deref() {
+            Ok("always") => Fsync::Always,
+            Ok("interval") | Err(_) => Fsync::Every(fsync_interval),
+            Ok("never") => Fsync::Never,
+            Ok(other) => panic!("FSYNC must be always, interval or never, not {:?}", other),
+        };
+        let compaction_interval = std::env::var("COMPACTION_INTERVAL_SECS")
+            .map(|v| {
+                v.parse()
+                    .expect("COMPACTION_INTERVAL_SECS must be a number")
+            })
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(5 * 60));
+        let tombstone_retention = std::env::var("TOMBSTONE_RETENTION_DAYS")
+            .map(|v| {
+                v.parse::<u64>()
+                    .expect("TOMBSTONE_RETENTION_DAYS must be a number")
+            })
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+            .unwrap_or(Duration::from_secs(30 * 24 * 60 * 60));
+        let history_depth = std::env::var("HISTORY_DEPTH")
+            .map(|v| v.parse().expect("HISTORY_DEPTH must be a number"))
+            .unwrap_or(history::DEFAULT_DEPTH);
+
+        Config {
+            storage,
+            fsync,
+            compaction_interval,
+            tombstone_retention,
+            history_depth,
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::model::Task;
+
+// Tasks form two graphs over their ids: the subtask hierarchy (`parentId`)
+// and the dependencies (`blockedBy`). Both are kept acyclic by checking every
+// new edge before it is stored.
+
+/// How many levels of subtasks a tree may have, counting its root, so that
+/// trees stay cheap to build and to serialize, both of which recurse.
+pub const MAX_DEPTH: usize = 32;
+
+/// A task with its subtasks, recursively.
+#[derive(Debug, Serialize)]
+pub struct TaskTree {
+    pub task: Task,
+    pub subtasks: Vec<TaskTree>,
+}
+
+pub fn tree(tasks: &IndexMap<String, Task>, id: &str) -> Option<TaskTree> {
+    let mut children: HashMap<&str, Vec<&Task>> = HashMap::new();
+    for task in tasks.values() {
+        if let Some(parent) = &task.parentId {
+            children.entry(parent).or_default().push(task);
+        }
+    }
+
+    fn build(task: &Task, children: &HashMap<&str, Vec<&Task>>) -> TaskTree {
+        let subtasks = match task.id.as_deref().and_then(|id| children.get(id)) {
+            Some(subtasks) => subtasks.iter().map(|task| build(task, children)).collect(),
+            None => vec![],
+        };
+        TaskTree {
+            task: task.clone(),
+            subtasks,
+        }
+    }
+    tasks.get(id).map(|task| build(task, &children))
+}
+
+/// Open tasks with every task before the ones it blocks, otherwise in
+/// insertion order. Completed blockers no longer hold anything up.
+pub fn topological_order(tasks: &IndexMap<String, Task>) -> Vec<Task> {
+    let open: Vec<usize> = (0..tasks.len())
+        .filter(|&i| tasks[i].completed != Some(true))
+        .collect();
+    let mut blockers = vec![0; tasks.len()];
+    let mut blocks: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &i in &open {
+        for blocker in &tasks[i].blockedBy {
+            if let Some(b) = tasks.get_index_of(blocker) {
+                if tasks[b].completed != Some(true) {
+                    blockers[i] += 1;
+                    blocks.entry(b).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<usize>> = open
+        .iter()
+        .filter(|&&i| blockers[i] == 0)
+        .map(|&i| Reverse(i))
+        .collect();
+    let mut order = Vec::with_capacity(open.len());
+    while let Some(Reverse(i)) = ready.pop() {
+        order.push(tasks[i].clone());
+        for &blocked in blocks.get(&i).into_iter().flatten() {
+            blockers[blocked] -= 1;
+            if blockers[blocked] == 0 {
+                ready.push(Reverse(blocked));
+            }
+        }
+    }
+    order
+}
+
+/// Checks the links of `task`, about to replace `old` (if any): parents and
+/// blockers must exist, new edges must not close a cycle and subtasks must
+/// not nest deeper than [`MAX_DEPTH`]. Duplicate blockers are dropped.
+pub fn check_links(
+    tasks: &IndexMap<String, Task>,
+    task: &mut Task,
+    old: Option<&Task>,
+) -> Result<(), String> {
+    let id = task.id.clone().expect("stored tasks have an id");
+    let mut seen = HashSet::new();
+    task.blockedBy
+        .retain(|blocker| seen.insert(blocker.clone()));
+
+    let name = |other: &str| -> String {
+        match tasks.get(other) {
+            Some(other) if other.id.as_deref() != Some(&id) => format!("'{}'", other.title),
+            _ => format!("'{}'", task.title),
+        }
+    };
+    let cycle = |path: Vec<&str>| -> String {
+        std::iter::once(id.as_str())
+            .chain(path)
+            .map(name)
+            .collect::<Vec<_>>()
+            .join(" → ")
+    };
+
+    if let Some(parent) = &task.parentId {
+        if old.is_none_or(|old| old.parentId.as_ref() != Some(parent)) {
+            if *parent == id {
+                return Err(format!(
+                    "A task cannot be its own subtask: {}",
+                    cycle(vec![&id])
+                ));
+            }
+            if !tasks.contains_key(parent) {
+                return Err(format!("Parent task with ID: {} not found", parent));
+            }
+            if let Some(path) = ancestor_path(tasks, parent, &id) {
+                return Err(format!(
+                    "Moving {} under {} would create a cycle: {}",
+                    name(&id),
+                    name(parent),
+                    cycle(path)
+                ));
+            }
+            let below = match old {
+                Some(_) => height(tasks, &id),
+                None => 1,
+            };
+            if depth(tasks, parent) + below > MAX_DEPTH {
+                return Err(format!(
+                    "Moving {} under {} would nest subtasks more than {} levels deep",
+                    name(&id),
+                    name(parent),
+                    MAX_DEPTH
+                ));
+            }
+        }
+    }
+
+    for blocker in &task.blockedBy {
+        if old.is_some_and(|old| old.blockedBy.contains(blocker)) {
+            continue;
+        }
+        if *blocker == id {
+            return Err(format!("A task cannot block itself: {}", cycle(vec![&id])));
+        }
+        if !tasks.contains_key(blocker) {
+            return Err(format!("Blocking task with ID: {} not found", blocker));
+        }
+        if let Some(path) = blocker_path(tasks, blocker, &id) {
+            return Err(format!(
+                "Blocking {} on {} would create a cycle: {}",
+                name(&id),
+                name(blocker),
+                cycle(path)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `from` and its ancestors up to `to`, if `to` is one of them.
+fn ancestor_path<'a>(
+    tasks: &'a IndexMap<String, Task>,
+    from: &'a str,
+    to: &str,
+) -> Option<Vec<&'a str>> {
+    let mut path = vec![from];
+    let mut current = from;
+    while let Some(parent) = tasks.get(current).and_then(|task| task.parentId.as_deref()) {
+        if path.contains(&parent) {
+            return None;
+        }
+        path.push(parent);
+        if parent == to {
+            return Some(path);
+        }
+        current = parent;
+    }
+    None
+}
+
+/// The level of `id` in its tree, the root being at level 1.
+fn depth(tasks: &IndexMap<String, Task>, id: &str) -> usize {
+    let mut depth = 1;
+    let mut current = id;
+    while let Some(parent) = tasks.get(current).and_then(|task| task.parentId.as_deref()) {
+        depth += 1;
+        current = parent;
+    }
+    depth
+}
+
+/// The number of levels in the subtree of `id`, itself included.
+fn height(tasks: &IndexMap<String, Task>, id: &str) -> usize {
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (child, task) in tasks {
+        if let Some(parent) = &task.parentId {
+            children.entry(parent).or_default().push(child);
+        }
+    }
+    let mut height = 0;
+    let mut level = vec![id];
+    while !level.is_empty() {
+        height += 1;
+        level = level
+            .iter()
+            .flat_map(|id| children.get(id).into_iter().flatten().copied())
+            .collect();
+    }
+    height
+}
+
+/// The shortest chain of "is blocked by" edges from `from` to `to`, both
+/// included.
+fn blocker_path<'a>(
+    tasks: &'a IndexMap<String, Task>,
+    from: &'a str,
+    to: &str,
+) -> Option<Vec<&'a str>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(current) = queue.pop_front() {
+        if current == to {
+            let mut path = vec![current];
+            while let Some(&before) = previous.get(path.last().unwrap()) {
+                path.push(before);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for blocker in tasks
+            .get(current)
+            .into_iter()
+            .flat_map(|task| &task.blockedBy)
+        {
+            if blocker != from && !previous.contains_key(blocker.as_str()) {
+                previous.insert(blocker, current);
+                queue.push_back(blocker);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tasks(links: &[(&str, Option<&str>, &[&str])]) -> IndexMap<String, Task> {
+        links
+            .iter()
+            .map(|&(id, parent, blockers)| {
+                let task = Task {
+                    id: Some(id.to_string()),
+                    title: id.to_string(),
+                    parentId: parent.map(str::to_string),
+                    blockedBy: blockers.iter().map(|b| b.to_string()).collect(),
+                    ..Default::default()
+                };
+                (id.to_string(), task)
+            })
+            .collect()
+    }
+
+    fn ids(tasks: Vec<Task>) -> Vec<String> {
+        tasks.into_iter().map(|task| task.id.unwrap()).collect()
+    }
+
+    #[test]
+    fn cycles_are_reported_with_their_path() {
+        let existing = tasks(&[
+            ("deploy", None, &["test"]),
+            ("test", None, &["build"]),
+            ("build", None, &[]),
+        ]);
+        let mut build = existing["build"].clone();
+        build.blockedBy = vec!["deploy".to_string()];
+        assert_eq!(
+            check_links(&existing, &mut build, existing.get("build")),
+            Err("Blocking 'build' on 'deploy' would create a cycle: \
+                 'build' → 'deploy' → 'test' → 'build'"
+                .to_string())
+        );
+
+        let mut deploy = existing["deploy"].clone();
+        deploy.blockedBy = vec!["deploy".to_string()];
+        assert!(check_links(&existing, &mut deploy, existing.get("deploy")).is_err());
+
+        // Depending on the same task twice, or along two paths, is fine.
+        let mut deploy = existing["deploy"].clone();
+        deploy.blockedBy = vec!["test".into(), "build".into(), "test".into()];
+        check_links(&existing, &mut deploy, existing.get("deploy")).unwrap();
+        assert_eq!(deploy.blockedBy, ["test", "build"]);
+    }
+
+    #[test]
+    fn subtasks_cannot_contain_their_ancestors() {
+        let existing = tasks(&[
+            ("root", None, &[]),
+            ("child", Some("root"), &[]),
+            ("grandchild", Some("child"), &[]),
+        ]);
+        let mut root = existing["root"].clone();
+        root.parentId = Some("grandchild".to_string());
+        assert_eq!(
+            check_links(&existing, &mut root, existing.get("root")),
+            Err("Moving 'root' under 'grandchild' would create a cycle: \
+                 'root' → 'grandchild' → 'child' → 'root'"
+                .to_string())
+        );
+
+        let mut orphan = Task {
+            id: Some("orphan".to_string()),
+            parentId: Some("missing".to_string()),
+            ..Default::default()
+        };
+        assert!(check_links(&existing, &mut orphan, None).is_err());
+    }
+
+    #[test]
+    fn subtasks_nest_at_most_max_depth_levels() {
+        let names: Vec<String> = (0..MAX_DEPTH).map(|level| level.to_string()).collect();
+        let mut links: Vec<(&str, Option<&str>, &[&str])> = names
+            .iter()
+            .enumerate()
+            .map(|(level, name)| {
+                (
+                    name.as_str(),
+                    level.checked_sub(1).map(|up| names[up].as_str()),
+                    &[][..],
+                )
+            })
+            .collect();
+        links.extend([("top", None, &[][..]), ("under", Some("top"), &[][..])]);
+        let existing = tasks(&links);
+
+        let leaf = |parent: &str| Task {
+            id: Some("leaf".to_string()),
+            title: "leaf".to_string(),
+            parentId: Some(parent.to_string()),
+            ..Default::default()
+        };
+        check_links(&existing, &mut leaf(&names[MAX_DEPTH - 2]), None).unwrap();
+        assert_eq!(
+            check_links(&existing, &mut leaf(&names[MAX_DEPTH - 1]), None),
+            Err(format!(
+                "Moving 'leaf' under '{}' would nest subtasks more than {} levels deep",
+                MAX_DEPTH - 1,
+                MAX_DEPTH
+            ))
+        );
+
+        // Moving a task takes its subtasks along.
+        let mut first = existing["1"].clone();
+        first.parentId = Some("top".to_string());
+        check_links(&existing, &mut first, existing.get("1")).unwrap();
+        first.parentId = Some("under".to_string());
+        assert!(check_links(&existing, &mut first, existing.get("1")).is_err());
+    }
+
+    #[test]
+    fn trees_nest_subtasks_in_order() {
+        let existing = tasks(&[
+            ("root", None, &[]),
+            ("b", Some("root"), &[]),
+            ("a", Some("root"), &[]),
+            ("a1", Some("a"), &[]),
+        ]);
+        let tree = tree(&existing, "root").unwrap();
+        let subtasks: Vec<_> = tree
+            .subtasks
+            .iter()
+            .map(|t| t.task.title.as_str())
+            .collect();
+        assert_eq!(subtasks, ["b", "a"]);
+        assert_eq!(tree.subtasks[1].subtasks[0].task.title, "a1");
+    }
+
+    #[test]
+    fn order_puts_blockers_first() {
+        let mut existing = tasks(&[
+            ("deploy", None, &["test", "docs"]),
+            ("test", None, &["build"]),
+            ("docs", None, &[]),
+            ("build", None, &[]),
+            ("done", None, &[]),
+        ]);
+        existing["done"].completed = Some(true);
+        assert_eq!(
+            ids(topological_order(&existing)),
+            ["docs", "build", "test", "deploy"]
+        );
+
+        // A completed blocker no longer holds anything up.
+        existing["build"].completed = Some(true);
+        assert_eq!(
+            ids(topological_order(&existing)),
+            ["test", "docs", "deploy"]
+        );
+    }
+}
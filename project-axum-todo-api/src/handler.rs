@@ -0,0 +1,712 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::history::{self, Action, Command, Entry, Histories, TaskChange};
+use crate::ical;
+use crate::live::{Broadcasting, TaskEvent};
+use crate::model::{Task, DB};
+use crate::response::{
+    AgendaResponse, GenericResponse, HistoryResponse, ImportResponse, LiveReply, RejectedChange,
+    SingleTaskResponse, SyncResponse, TaskData, TaskTreeResponse, TodoListResponse,
+};
+use crate::schedule;
+use crate::schema::{
+    AgendaOptions, LiveMutation, LiveOptions, LiveRequest, QueryOptions, SortOrder, SyncOptions,
+    SyncRequest, UpdateTaskSchema,
+};
+use crate::store::StoreError;
+use crate::sync::Changes;
+
+type HandlerError = (StatusCode, Json<GenericResponse>);
+
+/// Names the client whose history a change goes into. Changes without it
+/// cannot be undone.
+const CLIENT_ID: &str = "x-client-id";
+
+pub async fn health_checker_handler() -> impl IntoResponse {
+    const MESSAGE: &str = "API is up and running!";
+    let json_response = serde_json::json!(
+        {
+            "status": "success",
+            "message": MESSAGE,
+        }
+    );
+    Json(json_response)
+}
+
+pub async fn todos_list_handler(
+    opts: Option<Query<QueryOptions>>,
+    State(db): State<DB>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let limit = opts.limit.unwrap_or(10).clamp(1, 100);
+    let offset = (opts.page.unwrap_or(1).max(1) - 1).saturating_mul(limit);
+
+    let tasks = if opts.overdue.is_none() && opts.due_before.is_none() && opts.sort.is_none() {
+        blocking(&db, move |db| db.list(offset, limit)).await
+    } else {
+        let now = chrono::Utc::now();
+        let (overdue, due_before) = (opts.overdue, opts.due_before);
+        let filter = move |task: &Task| {
+            overdue.is_none_or(|overdue| schedule::is_overdue(task, now) == overdue)
+                && due_before.is_none_or(|before| task.dueAt.is_some_and(|due| due < before))
+        };
+        let order: Option<fn(&Task, &Task) -> Ordering> = opts.sort.map(|sort| match sort {
+            SortOrder::Priority => schedule::by_priority as fn(&Task, &Task) -> Ordering,
+            SortOrder::Due => schedule::by_due_date,
+        });
+        blocking(&db, move |db| db.select(&filter, order, offset, limit)).await
+    }
+    .map_err(store_error)?;
+    let todos: Vec<TaskData> = tasks.into_iter().map(|task| TaskData { task }).collect();
+
+    Ok(Json(TodoListResponse {
+        status: "success".to_string(),
+        results: todos.len(),
+        data: todos,
+    }))
+}
+
+pub async fn create_todo_handler(
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+    Json(body): Json<Task>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let command = Command::Create(new_task(body));
+    let changes = run(&db, &histories, client_id(&headers), command).await?;
+    let task = changes
+        .into_iter()
+        .next()
+        .and_then(|change| change.after)
+        .unwrap();
+
+    Ok((
+        StatusCode::CREATED,
+        Json(SingleTaskResponse {
+            status: "success".to_string(),
+            data: TaskData { task },
+        }),
+    ))
+}
+
+pub async fn agenda_handler(
+    opts: Option<Query<AgendaOptions>>,
+    State(db): State<DB>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let now = chrono::Utc::now();
+    let from = opts.from.unwrap_or(now.date_naive());
+    let days = opts.days.unwrap_or(7).clamp(1, 366);
+
+    let end = (from + chrono::Days::new(u64::from(days)))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    let open_until_end =
+        move |task: &Task| task.completed != Some(true) && task.dueAt.is_some_and(|due| due < end);
+    let tasks = blocking(&db, move |db| {
+        db.select(&open_until_end, None, 0, usize::MAX)
+    })
+    .await
+    .map_err(store_error)?;
+    let (overdue, days) = schedule::agenda(tasks, from, days, now);
+
+    Ok(Json(AgendaResponse {
+        status: "success".to_string(),
+        overdue,
+        days,
+    }))
+}
+
+pub async fn order_handler(State(db): State<DB>) -> Result<impl IntoResponse, HandlerError> {
+    let todos: Vec<TaskData> = blocking(&db, |db| db.order())
+        .await
+        .map_err(store_error)?
+        .into_iter()
+        .map(|task| TaskData { task })
+        .collect();
+    Ok(Json(TodoListResponse {
+        status: "success".to_string(),
+        results: todos.len(),
+        data: todos,
+    }))
+}
+
+pub async fn tree_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let id = id.to_string();
+    let tree = blocking(&db, move |db| db.tree(&id)?.ok_or(StoreError::NotFound(id)))
+        .await
+        .map_err(store_error)?;
+    Ok(Json(TaskTreeResponse {
+        status: "success".to_string(),
+        data: tree,
+    }))
+}
+
+pub async fn export_ics_handler(State(db): State<DB>) -> Result<impl IntoResponse, HandlerError> {
+    let tasks = blocking(&db, |db| db.select(&|_| true, None, 0, usize::MAX))
+        .await
+        .map_err(store_error)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical::export(&tasks, chrono::Utc::now()),
+    ))
+}
+
+/// Creates or updates a task for every VTODO, matching them on `UID`. Entries
+/// that cannot be stored are reported and skipped. The whole import goes into
+/// the client's history as one command, so a single undo takes it back.
+pub async fn import_ics_handler(
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<impl IntoResponse, HandlerError> {
+    let todos = ical::parse(&body).map_err(|message| fail(StatusCode::BAD_REQUEST, message))?;
+    let (response, changes) = blocking(&db, move |db| import(db, &todos)).await?;
+    if let (Some(client), Some(first)) = (client_id(&headers), changes.first()) {
+        let entry = Entry {
+            action: Action::Import,
+            task_id: first.id().to_string(),
+            changes: history::squash(changes),
+        };
+        histories.record(client, entry);
+    }
+    Ok(Json(response))
+}
+
+/// Runs the import, returning every change it made in order.
+fn import(
+    db: &DB,
+    todos: &[ical::VTodo],
+) -> Result<(ImportResponse, Vec<TaskChange>), HandlerError> {
+    let mut ids: HashMap<String, String> = db
+        .select(&|_| true, None, 0, usize::MAX)
+        .map_err(store_error)?
+        .iter()
+        .map(|task| (ical::uid(task).to_string(), task.id.clone().unwrap()))
+        .collect();
+    let mut created = 0;
+    let mut updated = 0;
+    let mut warnings = vec![];
+    let mut changed = vec![];
+
+    for todo in todos {
+        let recurrence = match &todo.recurrence {
+            Some(Ok(_)) if todo.due.is_none() => {
+                warnings.push(format!(
+                    "{}: ignored the RRULE of a VTODO without DUE",
+                    todo.uid
+                ));
+                None
+            }
+            Some(Ok(recurrence)) => Some(recurrence.clone()),
+            Some(Err(message)) => {
+                warnings.push(format!("{}: {}", todo.uid, message));
+                None
+            }
+            None => None,
+        };
+        let result = match ids.get(&todo.uid) {
+            Some(id) => {
+                let changes = UpdateTaskSchema {
+                    title: Some(todo.summary.clone()),
+                    content: Some(todo.description.clone()),
+                    completed: Some(todo.completed),
+                    dueAt: Some(todo.due),
+                    priority: todo.priority,
+                    recurrence: Some(recurrence),
+                    ..Default::default()
+                };
+                let id = id.clone();
+                db.execute(Command::Update { id, changes }).map(|changes| {
+                    changed.extend(changes);
+                    updated += 1;
+                })
+            }
+            None => {
+                let now = chrono::Utc::now();
+                let task = Task {
+                    id: Some(Uuid::new_v4().to_string()),
+                    uid: Some(todo.uid.clone()),
+                    title: todo.summary.clone(),
+                    content: todo.description.clone(),
+                    completed: Some(todo.completed),
+                    createdAt: Some(now),
+                    updatedAt: Some(now),
+                    dueAt: todo.due,
+                    priority: Some(todo.priority.unwrap_or_default()),
+                    recurrence,
+                    ..Default::default()
+                };
+                let id = task.id.clone().unwrap();
+                db.execute(Command::Create(task)).map(|changes| {
+                    ids.insert(todo.uid.clone(), id);
+                    changed.extend(changes);
+                    created += 1;
+                })
+            }
+        };
+        if let Err(err) = result {
+            warnings.push(format!("{}: {}", todo.uid, store_error(err).1.message));
+        }
+    }
+
+    // Links go in once every task exists, wherever it appears in the file.
+    for todo in todos {
+        let Some(id) = ids.get(&todo.uid) else {
+            continue;
+        };
+        let mut resolve = |uid: &String| {
+            let id = ids.get(uid).cloned();
+            if id.is_none() {
+                warnings.push(format!("{}: no task with UID {}", todo.uid, uid));
+            }
+            id
+        };
+        let parent_id = todo.parent.as_ref().and_then(&mut resolve);
+        let blocked_by: Vec<String> = todo.depends_on.iter().filter_map(&mut resolve).collect();
+        let Some(task) = db.get(id).map_err(store_error)? else {
+            continue;
+        };
+        if task.parentId == parent_id && task.blockedBy == blocked_by {
+            continue;
+        }
+        let changes = UpdateTaskSchema {
+            parentId: Some(parent_id),
+            blockedBy: Some(blocked_by),
+            ..Default::default()
+        };
+        let id = id.clone();
+        match db.execute(Command::Update { id, changes }) {
+            Ok(changes) => changed.extend(changes),
+            Err(err) => warnings.push(format!("{}: {}", todo.uid, store_error(err).1.message)),
+        }
+    }
+
+    let response = ImportResponse {
+        status: "success".to_string(),
+        created,
+        updated,
+        warnings,
+    };
+    Ok((response, changed))
+}
+
+pub async fn sync_changes_handler(
+    opts: Option<Query<SyncOptions>>,
+    State(db): State<DB>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let Query(opts) = opts.unwrap_or_default();
+    let since = sync_token(opts.since)?;
+    let changes = blocking(&db, move |db| db.changes(since, &[]))
+        .await
+        .map_err(store_error)?;
+    Ok(Json(sync_response(changes, vec![])))
+}
+
+/// Merges a batch of changes from a client, then answers with everything the
+/// client is missing: what changed since its token, and the resulting state of
+/// every task it sent, whether its change won or not.
+pub async fn sync_handler(
+    State(db): State<DB>,
+    Json(body): Json<SyncRequest>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let since = sync_token(body.since)?;
+    if body.clientId.is_empty() {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            "clientId must not be empty".to_string(),
+        ));
+    }
+
+    let (changes, rejected) = blocking(&db, move |db| {
+        let mut ids = vec![];
+        let mut rejected = vec![];
+        for change in body.changes {
+            let id = change.id.to_string();
+            if let Err(err) = db.merge(&body.clientId, change) {
+                let (_, Json(error)) = store_error(err);
+                rejected.push(RejectedChange {
+                    id: id.clone(),
+                    message: error.message,
+                });
+            }
+            ids.push(id);
+        }
+        db.changes(since, &ids).map(|changes| (changes, rejected))
+    })
+    .await
+    .map_err(store_error)?;
+    Ok(Json(sync_response(changes, rejected)))
+}
+
+fn sync_token(token: Option<String>) -> Result<Option<u64>, HandlerError> {
+    token
+        .map(|token| token.parse())
+        .transpose()
+        .map_err(|_| fail(StatusCode::BAD_REQUEST, "Invalid sync token".to_string()))
+}
+
+fn sync_response(changes: Changes, rejected: Vec<RejectedChange>) -> SyncResponse {
+    SyncResponse {
+        status: "success".to_string(),
+        token: changes.token.to_string(),
+        reset: changes.reset,
+        tasks: changes.tasks,
+        deleted: changes.deleted,
+        rejected,
+    }
+}
+
+pub async fn get_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let id = id.to_string();
+    let task = blocking(&db, move |db| db.get(&id)?.ok_or(StoreError::NotFound(id)))
+        .await
+        .map_err(store_error)?;
+    Ok(Json(SingleTaskResponse {
+        status: "success".to_string(),
+        data: TaskData { task },
+    }))
+}
+
+pub async fn edit_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+    Json(body): Json<UpdateTaskSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let command = Command::Update {
+        id: id.to_string(),
+        changes: body,
+    };
+    let changes = run(&db, &histories, client_id(&headers), command).await?;
+    let task = changes
+        .into_iter()
+        .next()
+        .and_then(|change| change.after)
+        .unwrap();
+    Ok(Json(SingleTaskResponse {
+        status: "success".to_string(),
+        data: TaskData { task },
+    }))
+}
+
+pub async fn delete_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HandlerError> {
+    let command = Command::Delete { id: id.to_string() };
+    run(&db, &histories, client_id(&headers), command).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Undoes the latest create, update, completion, deletion or import made by
+/// the client named in `X-Client-Id`.
+pub async fn undo_handler(
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HandlerError> {
+    let client = required_client_id(&headers)?;
+    let entry = histories
+        .take_undo(client)
+        .ok_or_else(|| fail(StatusCode::CONFLICT, "Nothing to undo".to_string()))?;
+    match revert(&db, &entry).await {
+        Ok(undone) => {
+            histories.push_redo(client, undone.clone());
+            Ok(Json(history_response(undone)))
+        }
+        Err(err) => {
+            if !matches!(err, StoreError::Conflict(_)) {
+                histories.push_undo(client, entry);
+            }
+            Err(store_error(err))
+        }
+    }
+}
+
+/// Redoes the latest command the client undid, unless it has made a new one
+/// since.
+pub async fn redo_handler(
+    State(db): State<DB>,
+    State(histories): State<Arc<Histories>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HandlerError> {
+    let client = required_client_id(&headers)?;
+    let entry = histories
+        .take_redo(client)
+        .ok_or_else(|| fail(StatusCode::CONFLICT, "Nothing to redo".to_string()))?;
+    match revert(&db, &entry).await {
+        Ok(redone) => {
+            histories.push_undo(client, redone.clone());
+            Ok(Json(history_response(redone)))
+        }
+        Err(err) => {
+            if !matches!(err, StoreError::Conflict(_)) {
+                histories.push_redo(client, entry);
+            }
+            Err(store_error(err))
+        }
+    }
+}
+
+fn client_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CLIENT_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty())
+}
+
+fn required_client_id(headers: &HeaderMap) -> Result<&str, HandlerError> {
+    client_id(headers).ok_or_else(|| {
+        fail(
+            StatusCode::BAD_REQUEST,
+            "X-Client-Id must name the client whose changes to undo or redo".to_string(),
+        )
+    })
+}
+
+/// Fills in what the server decides about a task a client creates.
+fn new_task(mut task: Task) -> Task {
+    let now = chrono::Utc::now();
+    task.id = Some(Uuid::new_v4().to_string());
+    task.completed = Some(task.completed.unwrap_or(false));
+    task.priority = Some(task.priority.unwrap_or_default());
+    task.createdAt = Some(now);
+    task.updatedAt = Some(now);
+    task
+}
+
+/// Runs `f` on the repository in the blocking pool, since repository calls
+/// wait on locks and, with a persistent backend, on the disk.
+async fn blocking<T: Send + 'static>(db: &DB, f: impl FnOnce(&DB) -> T + Send + 'static) -> T {
+    let db = db.clone();
+    match tokio::task::spawn_blocking(move || f(&db)).await {
+        Ok(result) => result,
+        // A panic carries on as if the call had been made right here.
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Runs `command`, remembering it in the history of the client that sent it.
+async fn run(
+    db: &DB,
+    histories: &Histories,
+    client: Option<&str>,
+    command: Command,
+) -> Result<Vec<TaskChange>, HandlerError> {
+    let (action, task_id) = match &command {
+        Command::Create(task) => (Action::Create, task.id.clone().unwrap()),
+        Command::Update { id, .. } => (Action::Update, id.clone()),
+        Command::Delete { id } => (Action::Delete, id.clone()),
+        Command::Revert(_) => unreachable!("reverting is for undo and redo"),
+    };
+    let changes = blocking(db, move |db| db.execute(command))
+        .await
+        .map_err(store_error)?;
+    if let Some(client) = client {
+        let action = match action {
+            Action::Update => Action::of_update(&changes),
+            action => action,
+        };
+        let entry = Entry {
+            action,
+            task_id,
+            changes: changes.clone(),
+        };
+        histories.record(client, entry);
+    }
+    Ok(changes)
+}
+
+/// Puts back what `entry` changed, returning the entry for going the other
+/// way.
+async fn revert(db: &DB, entry: &Entry) -> Result<Entry, StoreError> {
+    let command = Command::Revert(entry.changes.clone());
+    let changes = blocking(db, move |db| db.execute(command)).await?;
+    Ok(Entry {
+        changes,
+        ..entry.clone()
+    })
+}
+
+fn history_response(entry: Entry) -> HistoryResponse {
+    HistoryResponse {
+        status: "success".to_string(),
+        action: entry.action,
+        taskId: entry.task_id,
+        changes: entry.changes,
+    }
+}
+
+/// Pushes task events to the client, only those about one list when it
+/// names the list's `parentId`, and takes changes sent as [`LiveRequest`]s,
+/// acknowledging each. Changes made elsewhere, including through the REST
+/// endpoints, show up as events.
+pub async fn live_handler(
+    ws: WebSocketUpgrade,
+    opts: Option<Query<LiveOptions>>,
+    State(db): State<DB>,
+    State(live): State<Arc<Broadcasting>>,
+    State(histories): State<Arc<Histories>>,
+) -> impl IntoResponse {
+    let Query(opts) = opts.unwrap_or_default();
+    // Subscribing before the upgrade, so nothing made in between is missed.
+    let events = live.subscribe();
+    ws.on_upgrade(move |socket| live_session(socket, db, histories, events, opts))
+}
+
+async fn live_session(
+    mut socket: WebSocket,
+    db: DB,
+    histories: Arc<Histories>,
+    mut events: broadcast::Receiver<TaskEvent>,
+    opts: LiveOptions,
+) {
+    let list = opts.parentId.map(|id| id.to_string());
+    let client = opts.clientId.as_deref().filter(|id| !id.is_empty());
+    loop {
+        let reply = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if list.as_deref().is_some_and(|id| !event.concerns(id)) => continue,
+                Ok(event) => serde_json::to_string(&event),
+                Err(RecvError::Lagged(missed)) => serde_json::to_string(&LiveReply::Lagged { missed }),
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    serde_json::to_string(&live_request(&db, &histories, client, &text).await)
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => continue,
+            },
+        };
+        let reply = reply.expect("replies serialize");
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn live_request(
+    db: &DB,
+    histories: &Histories,
+    client: Option<&str>,
+    text: &str,
+) -> LiveReply {
+    let request: LiveRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(err) => {
+            return LiveReply::Ack {
+                requestId: None,
+                status: "fail".to_string(),
+                task: None,
+                message: Some(format!("Invalid request: {}", err)),
+            }
+        }
+    };
+    let (command, deleting) = match request.mutation {
+        LiveMutation::Create { task } => (Command::Create(new_task(task)), false),
+        LiveMutation::Update { todoId, changes } => {
+            let id = todoId.to_string();
+            (Command::Update { id, changes }, false)
+        }
+        LiveMutation::Delete { todoId } => {
+            let id = todoId.to_string();
+            (Command::Delete { id }, true)
+        }
+    };
+    match run(db, histories, client, command).await {
+        Ok(mut changes) => {
+            let task = match deleting {
+                true => changes.pop().and_then(|change| change.before),
+                false => changes.into_iter().next().and_then(|change| change.after),
+            };
+            LiveReply::Ack {
+                requestId: request.requestId,
+                status: "success".to_string(),
+                task: task.map(Box::new),
+                message: None,
+            }
+        }
+        Err((_, Json(error))) => LiveReply::Ack {
+            requestId: request.requestId,
+            status: error.status,
+            task: None,
+            message: Some(error.message),
+        },
+    }
+}
+
+fn fail(status: StatusCode, message: String) -> HandlerError {
+    println!("❌ Returning error response...");
+    (
+        status,
+        Json(GenericResponse {
+            status: "fail".to_string(),
+            message,
+        }),
+    )
+}
+
+fn store_error(err: StoreError) -> HandlerError {
+    match err {
+        StoreError::NotFound(id) => fail(
+            StatusCode::NOT_FOUND,
+            format!("Todo with ID: {} not found", id),
+        ),
+        StoreError::TitleTaken(title) => fail(
+            StatusCode::CONFLICT,
+            format!("Todo with title: '{}' already exists", title),
+        ),
+        StoreError::Invalid(message) => fail(StatusCode::UNPROCESSABLE_ENTITY, message),
+        StoreError::Blocked(blockers) => fail(
+            StatusCode::CONFLICT,
+            format!(
+                "Todo is blocked by open task(s): {}",
+                blockers
+                    .iter()
+                    .map(|title| format!("'{}'", title))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+        StoreError::Conflict(id) => fail(
+            StatusCode::CONFLICT,
+            format!(
+                "Todo with ID: {} was changed since, so this can no longer be undone or redone",
+                id
+            ),
+        ),
+        StoreError::Persistence(message) => {
+            println!("❌ Returning error response...");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(GenericResponse {
+                    status: "error".to_string(),
+                    message,
+                }),
+            )
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Undo and redo.
+//!
+//! Every change the API makes to tasks is a [`Command`]. Running one reports
+//! each task it touched as it was before and after, which is all it takes to
+//! run it backwards: [`Command::Revert`] puts those tasks back the way they
+//! were, provided nothing changed them since. Undoing is reverting what a
+//! command did, and redoing is reverting the undo.
+//!
+//! Histories are kept per client, in memory, so they do not survive a
+//! restart.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::model::Task;
+use crate::schema::UpdateTaskSchema;
+
+/// How many commands a client can undo, unless `HISTORY_DEPTH` says otherwise.
+pub const DEFAULT_DEPTH: usize = 50;
+
+/// Histories of clients that have not written for a while are dropped past
+/// this many clients.
+const MAX_CLIENTS: usize = 1024;
+
+/// A task as it was before and after a command; `None` where it did not
+/// exist.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskChange {
+    pub before: Option<Task>,
+    pub after: Option<Task>,
+}
+
+impl TaskChange {
+    pub fn id(&self) -> &str {
+        self.before
+            .as_ref()
+            .or(self.after.as_ref())
+            .and_then(|task| task.id.as_deref())
+            .expect("stored tasks have an id")
+    }
+}
+
+/// Whether `current` is still `expected`, apart from what the server keeps
+/// track of on its own: undoing a later command restores a task as it was
+/// with a new `updatedAt`, which should not keep the command before it from
+/// being undone too.
+pub(crate) fn unchanged(current: Option<&Task>, expected: Option<&Task>) -> bool {
+    let content = |task: &Task| Task {
+        updatedAt: None,
+        versions: Default::default(),
+        ..task.clone()
+    };
+    current.map(content) == expected.map(content)
+}
+
+/// One change to tasks, as a [`crate::repository::TaskRepository`] runs it.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Adds a task that already has its id.
+    Create(Task),
+    Update {
+        id: String,
+        changes: UpdateTaskSchema,
+    },
+    Delete {
+        id: String,
+    },
+    /// Puts every task back as it was `before`, if it is still as it was
+    /// `after`.
+    Revert(Vec<TaskChange>),
+}
+
+/// What a client did, as reported when it is undone or redone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Create,
+    Update,
+    Complete,
+    Delete,
+    /// An iCalendar import, undone as a whole.
+    Import,
+}
+
+impl Action {
+    /// An update that completes its task counts as completing it.
+    pub fn of_update(changes: &[TaskChange]) -> Action {
+        let completed = |task: &Option<Task>| {
+            task.as_ref()
+                .is_some_and(|task| task.completed == Some(true))
+        };
+        match changes.first() {
+            Some(change) if !completed(&change.before) && completed(&change.after) => {
+                Action::Complete
+            }
+            _ => Action::Update,
+        }
+    }
+}
+
+/// A command in a history, with what running it last did.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub action: Action,
+    pub task_id: String,
+    pub changes: Vec<TaskChange>,
+}
+
+#[derive(Debug, Default)]
+struct Stacks {
+    undo: VecDeque<Entry>,
+    redo: Vec<Entry>,
+}
+
+/// Every client's undo and redo stacks, each up to `depth` entries deep.
+#[derive(Debug)]
+pub struct Histories {
+    depth: usize,
+    /// Least recently written first.
+    clients: Mutex<IndexMap<String, Stacks>>,
+}
+
+impl Default for Histories {
+    fn default() -> Histories {
+        Histories::new(DEFAULT_DEPTH)
+    }
+}
+
+impl Histories {
+    /// With a `depth` of 0 nothing is remembered.
+    pub fn new(depth: usize) -> Histories {
+        Histories {
+            depth,
+            clients: Mutex::new(IndexMap::new()),
+        }
+    }
+
+    /// Remembers a new command, which makes whatever was undone before it
+    /// impossible to redo.
+    pub fn record(&self, client: &str, entry: Entry) {
+        self.with(client, |depth, stacks| {
+            stacks.redo.clear();
+            push(&mut stacks.undo, entry, depth);
+        });
+    }
+
+    /// The latest command, to undo.
+    pub fn take_undo(&self, client: &str) -> Option<Entry> {
+        self.with(client, |_, stacks| stacks.undo.pop_back())
+    }
+
+    /// The latest undone command, to redo.
+    pub fn take_redo(&self, client: &str) -> Option<Entry> {
+        self.with(client, |_, stacks| stacks.redo.pop())
+    }
+
+    /// Makes `entry` the next to undo, keeping what can be redone; for a
+    /// command just redone, or one that failed to undo.
+    pub fn push_undo(&self, client: &str, entry: Entry) {
+        self.with(client, |depth, stacks| push(&mut stacks.undo, entry, depth));
+    }
+
+    /// Makes `entry` the next to redo; for a command just undone, with what
+    /// undoing it did, or one that failed to redo.
+    pub fn push_redo(&self, client: &str, entry: Entry) {
+        self.with(client, |depth, stacks| {
+            stacks.redo.push(entry);
+            if stacks.redo.len() > depth {
+                stacks.redo.remove(0);
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IndexMap<String, Stacks>> {
+        self.clients.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Runs `f` on the stacks of `client`, which become the most recently
+    /// used.
+    fn with<T>(&self, client: &str, f: impl FnOnce(usize, &mut Stacks) -> T) -> T {
+        let mut clients = self.lock();
+        let mut stacks = clients.shift_remove(client).unwrap_or_default();
+        let result = f(self.depth, &mut stacks);
+        if !stacks.undo.is_empty() || !stacks.redo.is_empty() {
+            clients.insert(client.to_string(), stacks);
+            if clients.len() > MAX_CLIENTS {
+                clients.shift_remove_index(0);
+            }
+        }
+        result
+    }
+}
+
+fn push(stack: &mut VecDeque<Entry>, entry: Entry, depth: usize) {
+    stack.push_back(entry);
+    while stack.len() > depth {
+        stack.pop_front();
+    }
+}
+
+/// One change per task, from what it was before the first of `changes` to
+/// what it was after the last, in the order the tasks were first touched.
+pub(crate) fn squash(changes: Vec<TaskChange>) -> Vec<TaskChange> {
+    let mut squashed: IndexMap<String, TaskChange> = IndexMap::new();
+    for change in changes {
+        match squashed.get_mut(change.id()) {
+            Some(first) => first.after = change.after,
+            None => {
+                squashed.insert(change.id().to_string(), change);
+            }
+        }
+    }
+    squashed.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(task_id: &str) -> Entry {
+        Entry {
+            action: Action::Update,
+            task_id: task_id.to_string(),
+            changes: vec![],
+        }
+    }
+
+    fn task(id: &str, title: &str) -> Task {
+        Task {
+            id: Some(id.to_string()),
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn histories_keep_the_latest_entries_per_client() {
+        let histories = Histories::new(2);
+        for id in ["a", "b", "c"] {
+            histories.record("phone", entry(id));
+        }
+        histories.record("laptop", entry("d"));
+
+        assert_eq!(histories.take_undo("phone").unwrap().task_id, "c");
+        assert_eq!(histories.take_undo("phone").unwrap().task_id, "b");
+        assert!(histories.take_undo("phone").is_none());
+        assert_eq!(histories.take_undo("laptop").unwrap().task_id, "d");
+    }
+
+    #[test]
+    fn new_commands_clear_what_can_be_redone() {
+        let histories = Histories::default();
+        histories.record("phone", entry("a"));
+        histories.record("phone", entry("b"));
+        let undone = histories.take_undo("phone").unwrap();
+        histories.push_redo("phone", undone);
+
+        let redo = histories.take_redo("phone").unwrap();
+        assert_eq!(redo.task_id, "b");
+        histories.push_undo("phone", redo);
+        let undone = histories.take_undo("phone").unwrap();
+        histories.push_redo("phone", undone);
+
+        histories.record("phone", entry("c"));
+        assert!(histories.take_redo("phone").is_none());
+    }
+
+    #[test]
+    fn squashing_keeps_the_first_before_and_the_last_after() {
+        let changes = vec![
+            TaskChange {
+                before: Some(task("1", "one")),
+                after: Some(task("1", "uno")),
+            },
+            TaskChange {
+                before: None,
+                after: Some(task("2", "two")),
+            },
+            TaskChange {
+                before: Some(task("1", "uno")),
+                after: None,
+            },
+        ];
+        assert_eq!(
+            squash(changes),
+            vec![
+                TaskChange {
+                    before: Some(task("1", "one")),
+                    after: None,
+                },
+                TaskChange {
+                    before: None,
+                    after: Some(task("2", "two")),
+                },
+            ]
+        );
+    }
+}
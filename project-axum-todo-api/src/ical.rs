@@ -0,0 +1,500 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc, Weekday};
+
+use crate::model::Task;
+use crate::schedule::{Priority, Recurrence};
+
+// Just enough of RFC 5545 to hand tasks to calendar clients as VTODOs and
+// read them back. Times are written in UTC. Times read with a TZID or without
+// a zone are taken as UTC as well, and dates as midnight UTC.
+
+const PRODID: &str = "-//project-axum-todo-api//EN";
+const DATE_TIME: &str = "%Y%m%dT%H%M%SZ";
+/// Lines are folded after this many octets.
+const LINE_LIMIT: usize = 75;
+
+/// A VTODO as read from a calendar.
+#[derive(Debug, Default, PartialEq)]
+pub struct VTodo {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub completed: bool,
+    pub due: Option<DateTime<Utc>>,
+    pub priority: Option<Priority>,
+    /// `Err` holds an `RRULE` that tasks cannot express.
+    pub recurrence: Option<Result<Recurrence, String>>,
+    /// UID of the parent.
+    pub parent: Option<String>,
+    /// UIDs of the tasks this one depends on.
+    pub depends_on: Vec<String>,
+}
+
+/// The UID a task is exported under: the one it was imported with, if any.
+pub fn uid(task: &Task) -> &str {
+    task.uid
+        .as_deref()
+        .or(task.id.as_deref())
+        .expect("stored tasks have an id")
+}
+
+/// A VCALENDAR with one VTODO per task. Parents and dependencies outside
+/// `tasks` are referred to by id.
+pub fn export(tasks: &[Task], now: DateTime<Utc>) -> String {
+    let uids: std::collections::HashMap<&str, &str> = tasks
+        .iter()
+        .map(|task| (task.id.as_deref().unwrap_or_default(), uid(task)))
+        .collect();
+    let uid_of = |id: &str| uids.get(id).copied().unwrap_or(id).to_string();
+
+    let mut out = String::new();
+    let mut line = |line: String| fold(&mut out, &line);
+    line("BEGIN:VCALENDAR".into());
+    line("VERSION:2.0".into());
+    line(format!("PRODID:{}", PRODID));
+    for task in tasks {
+        line("BEGIN:VTODO".into());
+        line(format!("UID:{}", escape(uid(task))));
+        line(format!("DTSTAMP:{}", now.format(DATE_TIME)));
+        if let Some(created) = task.createdAt {
+            line(format!("CREATED:{}", created.format(DATE_TIME)));
+        }
+        if let Some(updated) = task.updatedAt {
+            line(format!("LAST-MODIFIED:{}", updated.format(DATE_TIME)));
+        }
+        line(format!("SUMMARY:{}", escape(&task.title)));
+        if !task.content.is_empty() {
+            line(format!("DESCRIPTION:{}", escape(&task.content)));
+        }
+        if let Some(due) = task.dueAt {
+            line(format!("DUE:{}", due.format(DATE_TIME)));
+        }
+        if let Some(priority) = task.priority {
+            line(format!("PRIORITY:{}", ical_priority(priority)));
+        }
+        if task.completed == Some(true) {
+            line("STATUS:COMPLETED".into());
+            // Completion time is not tracked; the last change is the closest.
+            if let Some(updated) = task.updatedAt {
+                line(format!("COMPLETED:{}", updated.format(DATE_TIME)));
+            }
+        } else {
+            line("STATUS:NEEDS-ACTION".into());
+        }
+        if let Some(recurrence) = &task.recurrence {
+            line(format!("RRULE:{}", rrule(recurrence)));
+        }
+        if let Some(parent) = &task.parentId {
+            line(format!(
+                "RELATED-TO;RELTYPE=PARENT:{}",
+                escape(&uid_of(parent))
+            ));
+        }
+        for blocker in &task.blockedBy {
+            line(format!(
+                "RELATED-TO;RELTYPE=DEPENDS-ON:{}",
+                escape(&uid_of(blocker))
+            ));
+        }
+        line("END:VTODO".into());
+    }
+    line("END:VCALENDAR".into());
+    out
+}
+
+/// Every VTODO in `input`; other components are skipped.
+pub fn parse(input: &str) -> Result<Vec<VTodo>, String> {
+    let mut todos = vec![];
+    let mut current: Option<VTodo> = None;
+    // Components nested in a VTODO, such as VALARM.
+    let mut nested = 0;
+
+    for (number, line) in unfold(input).into_iter().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let (name, params, value) =
+            split(&line).ok_or_else(|| format!("Line {} is not a content line", number + 1))?;
+        match (name.as_str(), value, current.as_mut()) {
+            ("BEGIN", "VTODO", None) => current = Some(VTodo::default()),
+            ("BEGIN", _, Some(_)) => nested += 1,
+            ("END", _, Some(_)) if nested > 0 => nested -= 1,
+            ("END", "VTODO", Some(_)) => {
+                let todo = current.take().unwrap();
+                if todo.uid.is_empty() {
+                    return Err(format!(
+                        "The VTODO ending on line {} has no UID",
+                        number + 1
+                    ));
+                }
+                todos.push(todo);
+            }
+            (_, _, Some(_)) if nested > 0 => {}
+            ("UID", value, Some(todo)) => todo.uid = unescape(value),
+            ("SUMMARY", value, Some(todo)) => todo.summary = unescape(value),
+            ("DESCRIPTION", value, Some(todo)) => todo.description = unescape(value),
+            ("STATUS", value, Some(todo)) => todo.completed = value == "COMPLETED",
+            ("COMPLETED", _, Some(todo)) => todo.completed = true,
+            ("DUE", value, Some(todo)) => {
+                let due = parse_time(value)
+                    .ok_or_else(|| format!("Line {} has an invalid DUE: {}", number + 1, value))?;
+                todo.due = Some(due);
+            }
+            ("PRIORITY", value, Some(todo)) => {
+                let priority = value.parse().map_err(|_| {
+                    format!("Line {} has an invalid PRIORITY: {}", number + 1, value)
+                })?;
+                todo.priority = task_priority(priority);
+            }
+            ("RRULE", value, Some(todo)) => todo.recurrence = Some(parse_rrule(value)),
+            ("RELATED-TO", value, Some(todo)) => {
+                match params.iter().find(|(name, _)| name == "RELTYPE") {
+                    Some((_, reltype)) if reltype == "DEPENDS-ON" => {
+                        todo.depends_on.push(unescape(value))
+                    }
+                    Some((_, reltype)) if reltype != "PARENT" => {}
+                    _ => todo.parent = Some(unescape(value)),
+                }
+            }
+            _ => {}
+        }
+    }
+    match current {
+        Some(_) => Err("The last VTODO has no END".to_string()),
+        None => Ok(todos),
+    }
+}
+
+/// RFC 5545 ranks from 1 (highest) to 9 (lowest).
+fn ical_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::Urgent => 1,
+        Priority::High => 3,
+        Priority::Normal => 5,
+        Priority::Low => 9,
+    }
+}
+
+fn task_priority(priority: u8) -> Option<Priority> {
+    match priority {
+        1..=2 => Some(Priority::Urgent),
+        3..=4 => Some(Priority::High),
+        5 => Some(Priority::Normal),
+        6..=9 => Some(Priority::Low),
+        // 0 means undefined.
+        _ => None,
+    }
+}
+
+fn rrule(recurrence: &Recurrence) -> String {
+    match recurrence {
+        Recurrence::Daily => "FREQ=DAILY".to_string(),
+        Recurrence::Weekly { weekdays } if weekdays.is_empty() => "FREQ=WEEKLY".to_string(),
+        Recurrence::Weekly { weekdays } => {
+            let days: Vec<&str> = weekdays.iter().map(|&day| weekday_code(day)).collect();
+            format!("FREQ=WEEKLY;BYDAY={}", days.join(","))
+        }
+        Recurrence::Monthly { day: Some(day) } => format!("FREQ=MONTHLY;BYMONTHDAY={}", day),
+        Recurrence::Monthly { day: None } => "FREQ=MONTHLY".to_string(),
+        Recurrence::Interval { days } => format!("FREQ=DAILY;INTERVAL={}", days),
+    }
+}
+
+fn parse_rrule(value: &str) -> Result<Recurrence, String> {
+    let unsupported = || Err(format!("Unsupported RRULE: {}", value));
+    let mut freq = None;
+    let mut interval = 1;
+    let mut by_day = None;
+    let mut by_month_day = None;
+    for part in value.split(';') {
+        let Some((name, value)) = part.split_once('=') else {
+            return unsupported();
+        };
+        match name {
+            "FREQ" => freq = Some(value),
+            "INTERVAL" => match value.parse() {
+                Ok(value) if value > 0 => interval = value,
+                _ => return unsupported(),
+            },
+            "BYDAY" => by_day = Some(value),
+            "BYMONTHDAY" => by_month_day = Some(value),
+            // Starting the week on another day changes nothing for us.
+            "WKST" => {}
+            _ => return unsupported(),
+        }
+    }
+
+    match (freq, interval, by_day, by_month_day) {
+        (Some("DAILY"), 1, None, None) => Ok(Recurrence::Daily),
+        (Some("DAILY"), days, None, None) => Ok(Recurrence::Interval { days }),
+        (Some("WEEKLY"), 1, by_day, None) => {
+            let weekdays = match by_day {
+                Some(days) => days
+                    .split(',')
+                    .map(parse_weekday)
+                    .collect::<Option<Vec<_>>>(),
+                None => Some(vec![]),
+            };
+            match weekdays {
+                Some(weekdays) => Ok(Recurrence::Weekly { weekdays }),
+                None => unsupported(),
+            }
+        }
+        (Some("MONTHLY"), 1, None, day) => match day.map(str::parse) {
+            None => Ok(Recurrence::Monthly { day: None }),
+            Some(Ok(day @ 1..=31)) => Ok(Recurrence::Monthly { day: Some(day) }),
+            Some(_) => unsupported(),
+        },
+        _ => unsupported(),
+    }
+}
+
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "MO"),
+    (Weekday::Tue, "TU"),
+    (Weekday::Wed, "WE"),
+    (Weekday::Thu, "TH"),
+    (Weekday::Fri, "FR"),
+    (Weekday::Sat, "SA"),
+    (Weekday::Sun, "SU"),
+];
+
+fn weekday_code(day: Weekday) -> &'static str {
+    WEEKDAYS[day.num_days_from_monday() as usize].1
+}
+
+fn parse_weekday(code: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(day, _)| *day)
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim_end_matches('Z');
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Some(time.and_utc());
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Appends `line` with CRLF, continuing on lines that start with a space
+/// wherever it runs past the limit. Never splits a character.
+fn fold(out: &mut String, line: &str) {
+    let mut start = 0;
+    let mut limit = LINE_LIMIT;
+    while line.len() - start > limit {
+        let mut end = start + limit;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.push_str(&line[start..end]);
+        out.push_str("\r\n ");
+        start = end;
+        // The leading space counts towards the limit.
+        limit = LINE_LIMIT - 1;
+    }
+    out.push_str(&line[start..]);
+    out.push_str("\r\n");
+}
+
+fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in input.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Parameter names and values of a content line.
+type Params = Vec<(String, String)>;
+
+/// A content line's name, parameters and value, with names upper-cased.
+fn split(line: &str) -> Option<(String, Params, &str)> {
+    // Parameter values may be quoted and contain ':' or ';'.
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?.to_ascii_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| {
+            (
+                name.to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    Some((name, params, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn exported_tasks_read_back() {
+        let parent = Task {
+            id: Some("1".to_string()),
+            title: "Plan, then ship; maybe".to_string(),
+            content: "line one\nline two \\ done".to_string(),
+            completed: Some(true),
+            updatedAt: Some(at("2024-01-02T10:00:00Z")),
+            priority: Some(Priority::Urgent),
+            ..Default::default()
+        };
+        let child = Task {
+            id: Some("2".to_string()),
+            uid: Some("abc@example.com".to_string()),
+            title: "Water the plants".to_string(),
+            completed: Some(false),
+            dueAt: Some(at("2024-01-03T09:30:00Z")),
+            priority: Some(Priority::Low),
+            recurrence: Some(Recurrence::Weekly {
+                weekdays: vec![Weekday::Mon, Weekday::Thu],
+            }),
+            parentId: Some("1".to_string()),
+            blockedBy: vec!["1".to_string()],
+            ..Default::default()
+        };
+        let ics = export(&[parent, child], at("2024-01-05T00:00:00Z"));
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.contains("SUMMARY:Plan\\, then ship\\; maybe\r\n"));
+        assert!(ics.contains("RRULE:FREQ=WEEKLY;BYDAY=MO,TH\r\n"));
+
+        let todos = parse(&ics).unwrap();
+        assert_eq!(
+            todos[0],
+            VTodo {
+                uid: "1".to_string(),
+                summary: "Plan, then ship; maybe".to_string(),
+                description: "line one\nline two \\ done".to_string(),
+                completed: true,
+                priority: Some(Priority::Urgent),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            todos[1],
+            VTodo {
+                uid: "abc@example.com".to_string(),
+                summary: "Water the plants".to_string(),
+                due: Some(at("2024-01-03T09:30:00Z")),
+                priority: Some(Priority::Low),
+                recurrence: Some(Ok(Recurrence::Weekly {
+                    weekdays: vec![Weekday::Mon, Weekday::Thu],
+                })),
+                parent: Some("1".to_string()),
+                depends_on: vec!["1".to_string()],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn long_lines_are_folded_on_character_boundaries() {
+        let title = "é".repeat(100);
+        let task = Task {
+            id: Some("1".to_string()),
+            title: title.clone(),
+            ..Default::default()
+        };
+        let ics = export(&[task], Utc::now());
+        assert!(ics.split("\r\n").all(|line| line.len() <= LINE_LIMIT));
+        assert_eq!(parse(&ics).unwrap()[0].summary, title);
+    }
+
+    #[test]
+    fn reads_what_calendar_clients_write() {
+        let ics = "BEGIN:VCALENDAR\n\
+                   BEGIN:VTODO\n\
+                   UID:x-1\n\
+                   SUMMARY:Renew \n passport\n\
+                   DUE;VALUE=DATE:20240301\n\
+                   PRIORITY:0\n\
+                   RRULE:FREQ=MONTHLY;COUNT=3\n\
+                   BEGIN:VALARM\n\
+                   ACTION:DISPLAY\n\
+                   SUMMARY:Not the task\n\
+                   END:VALARM\n\
+                   END:VTODO\n\
+                   BEGIN:VEVENT\n\
+                   UID:event\n\
+                   END:VEVENT\n\
+                   END:VCALENDAR\n";
+        let todos = parse(ics).unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].summary, "Renew passport");
+        assert_eq!(todos[0].due, Some(at("2024-03-01T00:00:00Z")));
+        assert_eq!(todos[0].priority, None);
+        assert!(matches!(todos[0].recurrence, Some(Err(_))));
+
+        assert!(parse("BEGIN:VTODO\nSUMMARY:x\nEND:VTODO\n").is_err());
+        assert!(parse("BEGIN:VTODO\nUID:1\n").is_err());
+    }
+
+    #[test]
+    fn rrules_map_onto_recurrences() {
+        for recurrence in [
+            Recurrence::Daily,
+            Recurrence::Interval { days: 3 },
+            Recurrence::Weekly { weekdays: vec![] },
+            Recurrence::Weekly {
+                weekdays: vec![Weekday::Sun],
+            },
+            Recurrence::Monthly { day: Some(31) },
+        ] {
+            assert_eq!(parse_rrule(&rrule(&recurrence)), Ok(recurrence));
+        }
+        assert!(parse_rrule("FREQ=WEEKLY;BYDAY=1MO").is_err());
+        assert!(parse_rrule("FREQ=YEARLY").is_err());
+    }
+}
@@ -0,0 +1,17 @@
+pub mod config;
+pub mod graph;
+pub mod handler;
+pub mod history;
+pub mod ical;
+pub mod live;
+pub mod model;
+pub mod persist;
+pub mod repository;
+pub mod response;
+pub mod route;
+pub mod schedule;
+pub mod schema;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
+pub mod sync;
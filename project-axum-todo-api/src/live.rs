@@ -0,0 +1,243 @@
+//! Live updates. Every change that goes through the router's repository is
+//! published as [`TaskEvent`]s, which `/api/todos/ws` pushes to each
+//! connected client.
+
+use std::cmp::Ordering;
+use std::io;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::graph::TaskTree;
+use crate::history::{Command, TaskChange};
+use crate::model::{Task, DB};
+use crate::repository::TaskRepository;
+use crate::schema::SyncChange;
+use crate::store::StoreError;
+use crate::sync::Changes;
+
+/// Events a slow client can fall behind by before it misses some.
+const CAPACITY: usize = 256;
+
+/// What happened to a task, as sent to live clients.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[allow(non_snake_case)]
+pub enum TaskEvent {
+    Created {
+        task: Task,
+    },
+    Updated {
+        task: Task,
+        /// The list the task was in, when it moved to another one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        previousParentId: Option<String>,
+    },
+    /// With the task as it was last.
+    Deleted {
+        task: Task,
+    },
+}
+
+impl TaskEvent {
+    fn of(change: TaskChange) -> Option<TaskEvent> {
+        match (change.before, change.after) {
+            (None, Some(task)) => Some(TaskEvent::Created { task }),
+            (Some(before), Some(task)) => Some(TaskEvent::Updated {
+                previousParentId: (before.parentId != task.parentId)
+                    .then_some(before.parentId)
+                    .flatten(),
+                task,
+            }),
+            (Some(task), None) => Some(TaskEvent::Deleted { task }),
+            (None, None) => None,
+        }
+    }
+
+    pub fn task(&self) -> &Task {
+        match self {
+            TaskEvent::Created { task }
+            | TaskEvent::Updated { task, .. }
+            | TaskEvent::Deleted { task } => task,
+        }
+    }
+
+    /// Whether the event is about the task `id` or one of its direct
+    /// subtasks, including one that just moved out, which is what a client
+    /// watching one list cares about.
+    pub fn concerns(&self, id: &str) -> bool {
+        let task = self.task();
+        let moved_from = match self {
+            TaskEvent::Updated {
+                previousParentId, ..
+            } => previousParentId.as_deref(),
+            _ => None,
+        };
+        [task.id.as_deref(), task.parentId.as_deref(), moved_from].contains(&Some(id))
+    }
+}
+
+/// A repository that publishes every change made through it.
+#[derive(Debug)]
+pub struct Broadcasting {
+    inner: DB,
+    events: broadcast::Sender<TaskEvent>,
+    /// Held from making a change until its events are sent, so that events
+    /// go out in the order the changes were made.
+    writing: Mutex<()>,
+}
+
+impl Broadcasting {
+    pub fn new(inner: DB) -> Broadcasting {
+        let (events, _) = broadcast::channel(CAPACITY);
+        Broadcasting {
+            inner,
+            events,
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Events from now on. A receiver that falls behind by more than the
+    /// channel holds gets told how many it missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
+    }
+
+    /// Makes a change with `f` and publishes what it did.
+    fn write(
+        &self,
+        f: impl FnOnce(&DB) -> Result<Vec<TaskChange>, StoreError>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let _writing = self.writing.lock().unwrap_or_else(|err| err.into_inner());
+        let changes = f(&self.inner)?;
+        for event in changes.iter().cloned().filter_map(TaskEvent::of) {
+            // Nobody listening is not an error.
+            let _ = self.events.send(event);
+        }
+        Ok(changes)
+    }
+}
+
+impl TaskRepository for Broadcasting {
+    fn len(&self) -> Result<usize, StoreError> {
+        self.inner.len()
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Task>, StoreError> {
+        self.inner.get(id)
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Task>, StoreError> {
+        self.inner.list(offset, limit)
+    }
+
+    fn select(
+        &self,
+        filter: &dyn Fn(&Task) -> bool,
+        order: Option<fn(&Task, &Task) -> Ordering>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Task>, StoreError> {
+        self.inner.select(filter, order, offset, limit)
+    }
+
+    fn execute(&self, command: Command) -> Result<Vec<TaskChange>, StoreError> {
+        self.write(|inner| inner.execute(command))
+    }
+
+    fn merge(&self, origin: &str, change: SyncChange) -> Result<Vec<TaskChange>, StoreError> {
+        self.write(|inner| inner.merge(origin, change))
+    }
+
+    fn changes(&self, since: Option<u64>, also: &[String]) -> Result<Changes, StoreError> {
+        self.inner.changes(since, also)
+    }
+
+    fn prune_tombstones(&self, cutoff: DateTime<Utc>) -> Result<(), StoreError> {
+        self.inner.prune_tombstones(cutoff)
+    }
+
+    fn tree(&self, id: &str) -> Result<Option<TaskTree>, StoreError> {
+        self.inner.tree(id)
+    }
+
+    fn order(&self) -> Result<Vec<Task>, StoreError> {
+        self.inner.order()
+    }
+
+    fn compact(&self) -> io::Result<bool> {
+        self.inner.compact()
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.inner.sync()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::create_db;
+    use crate::repository::conformance::task;
+
+    #[test]
+    fn every_change_is_published_in_order() {
+        let db = Broadcasting::new(create_db());
+        let mut events = db.subscribe();
+        let list = db.insert(task("groceries")).unwrap();
+        let list_id = list.id.clone().unwrap();
+        let milk = db
+            .insert(Task {
+                parentId: Some(list_id.clone()),
+                ..task("milk")
+            })
+            .unwrap();
+        db.remove(&list_id).unwrap();
+
+        assert_eq!(events.try_recv(), Ok(TaskEvent::Created { task: list }));
+        let created = events.try_recv().unwrap();
+        assert_eq!(created, TaskEvent::Created { task: milk.clone() });
+        assert!(created.concerns(&list_id));
+        // Removing the list moves its items up before it goes, which
+        // watchers of the list still hear about.
+        let moved = events.try_recv().unwrap();
+        assert!(matches!(
+            &moved,
+            TaskEvent::Updated { task, previousParentId }
+                if task.parentId.is_none() && previousParentId.as_deref() == Some(&list_id)
+        ));
+        assert!(moved.concerns(&list_id));
+        let deleted = events.try_recv().unwrap();
+        assert!(matches!(deleted, TaskEvent::Deleted { .. }));
+        assert!(deleted.concerns(&list_id));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn concurrent_changes_are_published_in_the_order_they_were_made() {
+        let db = std::sync::Arc::new(Broadcasting::new(create_db()));
+        let mut events = db.subscribe();
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for n in 0..20 {
+                        db.insert(task(&format!("{}-{}", writer, n))).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut last = 0;
+        for _ in 0..160 {
+            let seq = events.try_recv().unwrap().task().versions.seq;
+            assert!(seq > last, "{} came after {}", seq, last);
+            last = seq;
+        }
+    }
+}
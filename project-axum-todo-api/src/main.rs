@@ -1,25 +1,41 @@
-use axum::response::IntoResponse;
-use axum::routing::get;
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderName, HeaderValue, Method};
 
-mod model;
-mod response;
-mod schema;
+use project_axum_todo_api::config::Config;
+use project_axum_todo_api::history::Histories;
+use project_axum_todo_api::persist;
+use project_axum_todo_api::repository;
+use project_axum_todo_api::route::create_router;
 
-async fn health_checker_handler() -> impl IntoResponse {
-    const MESSAGE: &str = "API is up and running!";
-    let json_response = serde_json::json!(
-        {
-            "status": "success",
-            "message": MESSAGE,
+#[tokio::main]
+async fn main() {
+    let config = Config::init();
+    let db = match repository::open(&config) {
+        Ok(db) => db,
+        Err(err) => {
+            println!("❌ Failed to open the task storage: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+    match db.len() {
+        Ok(len) => println!("✅ Loaded {} task(s) from {:?}", len, config.storage),
+        Err(err) => {
+            println!("❌ Failed to read the tasks: {:?}", err);
+            std::process::exit(1);
         }
+    }
+    persist::spawn_maintenance(
+        db.clone(),
+        config.fsync,
+        config.compaction_interval,
+        config.tombstone_retention,
     );
-    axum::Json(json_response)
-}
 
-#[tokio::main]
-async fn main() {
-    let app = axum::Router::new() // Create a new router instance.
-        .route("/api/healthcheker", get(health_checker_handler)); // Specify that the health checker endpoint should only respond to GET requests.
+    let cors = tower_http::cors::CorsLayer::new()
+        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([ACCEPT, CONTENT_TYPE, HeaderName::from_static("x-client-id")]);
+    let app = create_router(db, Histories::new(config.history_depth)).layer(cors);
 
     println!("🚀 Server started successfully");
     axum::Server::bind(&"0.0.0.0:8000".parse().unwrap()) // Bind the HTTP server to the address.
@@ -2,10 +2,14 @@ use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+
+use crate::repository::TaskRepository;
+use crate::schedule::{Priority, Recurrence};
+use crate::store::TaskStore;
+use crate::sync::Versions;
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct Task {
     pub id: Option<String>,
     pub title: String,
@@ -13,10 +17,25 @@ pub struct Task {
     pub completed: Option<bool>,
     pub createdAt: Option<DateTime<Utc>>,
     pub updatedAt: Option<DateTime<Utc>>,
+    pub dueAt: Option<DateTime<Utc>>,
+    pub priority: Option<Priority>,
+    /// Completing a recurring task adds its next occurrence.
+    pub recurrence: Option<Recurrence>,
+    /// The task this is a subtask of.
+    pub parentId: Option<String>,
+    /// Tasks that have to be completed before this one can be.
+    #[serde(default)]
+    pub blockedBy: Vec<String>,
+    /// UID of the calendar entry the task was imported from, which it keeps
+    /// when exported again.
+    pub uid: Option<String>,
+    /// Kept by the server; see [`crate::sync`].
+    #[serde(default)]
+    pub versions: Versions,
 }
 
-pub type DB = Arc<Mutex<Vec<Task>>>;
+pub type DB = Arc<dyn TaskRepository>;
 
 pub fn create_db() -> DB {
-    Arc::new(Mutex::new(vec![]))
+    Arc::new(TaskStore::new())
 }
@@ -0,0 +1,516 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Task, DB};
+use crate::sync::Tombstone;
+
+// The store is kept in memory and made durable with a snapshot plus a
+// write-ahead log of JSON lines. Logs are numbered by generation: compaction
+// switches to the next generation, writes every task to `snapshot.json` along
+// with that generation, and only then deletes the older logs. Startup loads
+// the snapshot and replays every log from its generation on, so a crash at any
+// point of a compaction loses nothing.
+//
+// A record only counts once its newline is written. Anything after the last
+// newline of a log is a write cut short by a crash and is truncated away.
+
+const SNAPSHOT: &str = "snapshot.json";
+const SNAPSHOT_TMP: &str = "snapshot.json.tmp";
+
+/// When appended records are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// Before every mutation returns; nothing acknowledged is ever lost.
+    Always,
+    /// In the background; a crash of the machine (not just the process) loses
+    /// at most this long of writes.
+    Every(Duration),
+    /// Left to the operating system.
+    Never,
+}
+
+/// One mutation, as written to the log. Updates store the whole task, so
+/// replaying a record twice does no harm.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Record {
+    Put {
+        task: Task,
+    },
+    /// Logs written before tombstones existed have none.
+    Delete {
+        id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tombstone: Option<Tombstone>,
+    },
+}
+
+impl Record {
+    pub fn apply(self, state: &mut State) {
+        match self {
+            // Replacing an existing key keeps its place in the order.
+            Record::Put { task } => {
+                let id = task.id.clone().expect("stored tasks have an id");
+                state.tombstones.shift_remove(&id);
+                state.tasks.insert(id, task);
+            }
+            Record::Delete { id, tombstone } => {
+                state.tasks.shift_remove(&id);
+                if let Some(tombstone) = tombstone {
+                    state.tombstones.insert(id, tombstone);
+                }
+            }
+        }
+    }
+}
+
+/// Everything that is persisted.
+#[derive(Debug, Default)]
+pub struct State {
+    pub tasks: IndexMap<String, Task>,
+    /// By id, oldest first.
+    pub tombstones: IndexMap<String, Tombstone>,
+    /// The latest sequence number of a tombstone that was dropped.
+    pub horizon: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// The first log generation not contained in the snapshot.
+    generation: u64,
+    tasks: Vec<Task>,
+    #[serde(default)]
+    tombstones: Vec<Tombstone>,
+    #[serde(default)]
+    horizon: u64,
+}
+
+/// The open log of the current generation.
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    generation: u64,
+    file: File,
+    len: u64,
+    fsync: Fsync,
+    unsynced: bool,
+    /// Records appended since the last compaction.
+    records: usize,
+}
+
+impl Wal {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Appends the records of one change in a single write.
+    pub fn append(&mut self, records: &[Record]) -> io::Result<()> {
+        let mut lines = vec![];
+        for record in records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+        if let Err(err) = self.file.write_all(&lines) {
+            // Don't leave half a record for the next one to be appended to.
+            self.file.set_len(self.len)?;
+            return Err(err);
+        }
+        self.len += lines.len() as u64;
+        self.records += records.len();
+        match self.fsync {
+            Fsync::Always => self.file.sync_data()?,
+            Fsync::Every(_) | Fsync::Never => self.unsynced = true,
+        }
+        Ok(())
+    }
+
+    /// A handle to flush outside the store's lock, if anything is unflushed.
+    pub fn take_unsynced(&mut self) -> io::Result<Option<File>> {
+        if !self.unsynced {
+            return Ok(None);
+        }
+        self.unsynced = false;
+        self.file.try_clone().map(Some)
+    }
+
+    /// Continues in the next generation's log and returns that generation.
+    pub fn rotate(&mut self) -> io::Result<u64> {
+        self.file.sync_data()?;
+        let generation = self.generation + 1;
+        self.file = open_log(&self.dir, generation)?;
+        sync_dir(&self.dir)?;
+        self.generation = generation;
+        self.len = 0;
+        self.unsynced = false;
+        self.records = 0;
+        Ok(generation)
+    }
+}
+
+/// Restores the tasks kept in `dir`, creating it if needed, and opens the log
+/// for further writes.
+pub fn load(dir: &Path, fsync: Fsync) -> io::Result<(State, Wal)> {
+    fs::create_dir_all(dir)?;
+    let (snapshot_generation, mut state) = match File::open(dir.join(SNAPSHOT)) {
+        Ok(file) => {
+            let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))?;
+            let state = State {
+                tasks: snapshot
+                    .tasks
+                    .into_iter()
+                    .map(|task| (task.id.clone().expect("stored tasks have an id"), task))
+                    .collect(),
+                tombstones: snapshot
+                    .tombstones
+                    .into_iter()
+                    .map(|tombstone| (tombstone.id.clone(), tombstone))
+                    .collect(),
+                horizon: snapshot.horizon,
+            };
+            (snapshot.generation, state)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (0, State::default()),
+        Err(err) => return Err(err),
+    };
+
+    let mut generation = snapshot_generation;
+    let mut records = 0;
+    for log in log_generations(dir)? {
+        if log < snapshot_generation {
+            // Left behind by a compaction that stopped right after its snapshot.
+            fs::remove_file(log_path(dir, log))?;
+            continue;
+        }
+        records += replay(&log_path(dir, log), &mut state)?;
+        generation = log;
+    }
+
+    let file = open_log(dir, generation)?;
+    let len = file.metadata()?.len();
+    let wal = Wal {
+        dir: dir.to_path_buf(),
+        generation,
+        file,
+        len,
+        fsync,
+        unsynced: false,
+        records,
+    };
+    Ok((state, wal))
+}
+
+/// Applies every complete record of a log and returns how many there were.
+fn replay(path: &Path, state: &mut State) -> io::Result<usize> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    if complete < bytes.len() {
+        println!(
+            "⚠️ Dropping a record cut short at the end of {} ({} bytes)",
+            path.display(),
+            bytes.len() - complete
+        );
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(complete as u64)?;
+        file.sync_all()?;
+    }
+
+    let mut records = 0;
+    for (number, line) in bytes[..complete].split(|&b| b == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_slice(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "corrupt record at {}:{}: {}",
+                    path.display(),
+                    number + 1,
+                    err
+                ),
+            )
+        })?;
+        record.apply(state);
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Atomically replaces the snapshot with `state`, which must be the state
+/// right before log `generation` begins, and deletes the logs it covers.
+pub fn write_snapshot(dir: &Path, generation: u64, state: State) -> io::Result<()> {
+    let snapshot = Snapshot {
+        generation,
+        tasks: state.tasks.into_values().collect(),
+        tombstones: state.tombstones.into_values().collect(),
+        horizon: state.horizon,
+    };
+    let tmp = dir.join(SNAPSHOT_TMP);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, &snapshot)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp, dir.join(SNAPSHOT))?;
+    sync_dir(dir)?;
+
+    for log in log_generations(dir)? {
+        if log < generation {
+            fs::remove_file(log_path(dir, log))?;
+        }
+    }
+    Ok(())
+}
+
+/// Flushes the log and compacts it on the configured intervals. Tombstones
+/// older than `tombstone_retention` are dropped before each compaction.
+pub fn spawn_maintenance(
+    db: DB,
+    fsync: Fsync,
+    compaction_interval: Duration,
+    tombstone_retention: Duration,
+) {
+    tokio::spawn(async move {
+        let mut compaction = tokio::time::interval(compaction_interval);
+        compaction.tick().await;
+        let mut flush = match fsync {
+            Fsync::Every(interval) => Some(tokio::time::interval(interval)),
+            Fsync::Always | Fsync::Never => None,
+        };
+        loop {
+            let flush_tick = async {
+                match flush.as_mut() {
+                    Some(flush) => flush.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = flush_tick => {
+                    let db = db.clone();
+                    if let Ok(Err(err)) = tokio::task::spawn_blocking(move || db.sync()).await {
+                        println!("❌ Failed to flush the task log: {:?}", err);
+                    }
+                }
+                _ = compaction.tick() => {
+                    let db = db.clone();
+                    let compact = move || {
+                        let retention = chrono::Duration::from_std(tombstone_retention)
+                            .unwrap_or(chrono::Duration::MAX);
+                        let cutoff = chrono::Utc::now()
+                            .checked_sub_signed(retention)
+                            .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+                        if let Err(err) = db.prune_tombstones(cutoff) {
+                            println!("❌ Failed to drop old tombstones: {:?}", err);
+                        }
+                        db.compact()
+                    };
+                    match tokio::task::spawn_blocking(compact).await {
+                        Ok(Ok(true)) => println!("🗜️ Compacted the task log into a snapshot"),
+                        Ok(Ok(false)) => {}
+                        Ok(Err(err)) => println!("❌ Failed to compact the task log: {:?}", err),
+                        Err(err) => println!("❌ Failed to compact the task log: {:?}", err),
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn log_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("wal-{:020}.log", generation))
+}
+
+/// Generations of the logs in `dir`, oldest first.
+fn log_generations(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut generations = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let generation = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("wal-"))
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|generation| generation.parse::<u64>().ok());
+        generations.extend(generation);
+    }
+    generations.sort_unstable();
+    Ok(generations)
+}
+
+fn open_log(dir: &Path, generation: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(dir, generation))
+}
+
+/// Makes created, renamed and deleted files in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::Command;
+    use crate::repository::conformance::task;
+    use crate::repository::TaskRepository;
+    use crate::schema::UpdateTaskSchema;
+    use crate::store::TaskStore;
+
+    fn titles(store: &TaskStore) -> Vec<String> {
+        store
+            .list(0, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|task| task.title)
+            .collect()
+    }
+
+    fn complete(id: &str, store: &TaskStore) {
+        let changes = UpdateTaskSchema {
+            completed: Some(true),
+            ..Default::default()
+        };
+        store.update(id, changes).unwrap();
+    }
+
+    #[test]
+    fn reopening_replays_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Never).unwrap();
+        let ids: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|title| store.insert(task(title)).unwrap().id.unwrap())
+            .collect();
+        complete(&ids[0], &store);
+        store.remove(&ids[1]).unwrap();
+        drop(store);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a", "c"]);
+        assert_eq!(store.get(&ids[0]).unwrap().unwrap().completed, Some(true));
+        // The title index is rebuilt too.
+        assert!(store.insert(task("c")).is_err());
+    }
+
+    #[test]
+    fn compaction_moves_the_log_into_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        let first = store.insert(task("a")).unwrap().id.unwrap();
+        store.insert(task("b")).unwrap();
+        assert!(store.compact().unwrap());
+        assert!(!store.compact().unwrap(), "nothing new to compact");
+        assert_eq!(log_generations(dir.path()).unwrap(), [1]);
+
+        complete(&first, &store);
+        store.insert(task("c")).unwrap();
+        drop(store);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a", "b", "c"]);
+        assert_eq!(store.get(&first).unwrap().unwrap().completed, Some(true));
+    }
+
+    #[test]
+    fn tombstones_and_sequence_numbers_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        let a = store.insert(task("a")).unwrap().id.unwrap();
+        let b = store.insert(task("b")).unwrap().id.unwrap();
+        store.remove(&a).unwrap();
+        assert!(store.compact().unwrap());
+        store.remove(&b).unwrap();
+        let before = store.changes(Some(0), &[]).unwrap();
+        drop(store);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        let after = store.changes(Some(0), &[]).unwrap();
+        assert_eq!(after.token, before.token);
+        assert_eq!(after.deleted, before.deleted);
+        assert_eq!(after.deleted.len(), 2);
+    }
+
+    #[test]
+    fn undone_deletions_stay_undone_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        let a = task("a");
+        let id = a.id.clone().unwrap();
+        store.insert(a).unwrap();
+        let deleted = store.execute(Command::Delete { id: id.clone() }).unwrap();
+        assert!(store.compact().unwrap());
+        store.execute(Command::Revert(deleted)).unwrap();
+        drop(store);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a"]);
+        assert!(store.changes(Some(0), &[]).unwrap().deleted.is_empty());
+    }
+
+    #[test]
+    fn logs_are_replayed_when_the_snapshot_was_never_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut wal) = load(dir.path(), Fsync::Always).unwrap();
+        wal.append(&[Record::Put { task: task("a") }]).unwrap();
+        // Compaction switched logs, then crashed before the snapshot.
+        wal.rotate().unwrap();
+        wal.append(&[Record::Put { task: task("b") }]).unwrap();
+        drop(wal);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a", "b"]);
+    }
+
+    #[test]
+    fn a_torn_last_record_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        store.insert(task("a")).unwrap();
+        drop(store);
+
+        let log = log_path(dir.path(), 0);
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(br#"{"op":"put","task":{"id":"x","ti"#)
+            .unwrap();
+        drop(file);
+
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a"]);
+        // Later records land on a clean line.
+        store.insert(task("b")).unwrap();
+        drop(store);
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        assert_eq!(titles(&store), ["a", "b"]);
+    }
+
+    #[test]
+    fn a_corrupt_complete_record_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::open(dir.path(), Fsync::Always).unwrap();
+        store.insert(task("a")).unwrap();
+        drop(store);
+
+        let log = log_path(dir.path(), 0);
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(b"not json\n").unwrap();
+        drop(file);
+
+        let err = TaskStore::open(dir.path(), Fsync::Always).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
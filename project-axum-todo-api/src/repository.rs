@@ -0,0 +1,576 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::io;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{Config, Storage};
+use crate::graph::TaskTree;
+use crate::history::{Command, TaskChange};
+use crate::model::{Task, DB};
+use crate::schema::{SyncChange, UpdateTaskSchema};
+use crate::store::{StoreError, TaskStore};
+use crate::sync::Changes;
+
+/// Where the handlers keep tasks. Every backend follows the same rules, which
+/// the conformance suite below checks.
+pub trait TaskRepository: Send + Sync + Debug {
+    fn len(&self) -> Result<usize, StoreError>;
+
+    fn is_empty(&self) -> Result<bool, StoreError> {
+        self.len().map(|len| len == 0)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Task>, StoreError>;
+
+    /// Up to `limit` tasks starting at the `offset`-th oldest one.
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Task>, StoreError>;
+
+    /// The page of tasks matching `filter`, in `order` or else insertion
+    /// order. Unlike [`TaskRepository::list`], this looks at every task.
+    fn select(
+        &self,
+        filter: &dyn Fn(&Task) -> bool,
+        order: Option<fn(&Task, &Task) -> Ordering>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Task>, StoreError>;
+
+    /// Runs `command`, returning every task it touched as it was before and
+    /// after, the task it was about first. Reverting that undoes it.
+    fn execute(&self, command: Command) -> Result<Vec<TaskChange>, StoreError>;
+
+    /// Adds a task that already has its id.
+    fn insert(&self, task: Task) -> Result<Task, StoreError> {
+        let changes = self.execute(Command::Create(task))?;
+        Ok(changes
+            .into_iter()
+            .next()
+            .and_then(|change| change.after)
+            .expect("a task was added"))
+    }
+
+    /// Applies the fields set in `changes` and bumps `updatedAt`. Completing a
+    /// recurring task hands its recurrence on to a new task for the next
+    /// occurrence.
+    fn update(&self, id: &str, changes: UpdateTaskSchema) -> Result<Task, StoreError> {
+        let id = id.to_string();
+        let changes = self.execute(Command::Update { id, changes })?;
+        Ok(changes
+            .into_iter()
+            .next()
+            .and_then(|change| change.after)
+            .expect("a task was updated"))
+    }
+
+    /// Subtasks of a removed task move up to its parent, and tasks it was
+    /// blocking stop waiting for it.
+    fn remove(&self, id: &str) -> Result<Task, StoreError> {
+        let id = id.to_string();
+        let changes = self.execute(Command::Delete { id })?;
+        Ok(changes
+            .into_iter()
+            .last()
+            .and_then(|change| change.before)
+            .expect("a task was removed"))
+    }
+
+    /// Applies a change a client made while it may have been offline, field by
+    /// field for the fields whose last change is older. A deletion only goes
+    /// through when it is newer than every field, and deleted tasks stay
+    /// deleted. Changes dated in the future count as made now, so a client
+    /// with a fast clock cannot shadow everyone else's edits. Returns what
+    /// changed, which is nothing when the change lost.
+    fn merge(&self, origin: &str, change: SyncChange) -> Result<Vec<TaskChange>, StoreError>;
+
+    /// Tasks and tombstones changed after `since`, plus the current state of
+    /// `also`. Without a token, or with one from before the oldest tombstone
+    /// kept, every task is returned instead.
+    fn changes(&self, since: Option<u64>, also: &[String]) -> Result<Changes, StoreError>;
+
+    /// Forgets deletions made before `cutoff`. Clients that last synced
+    /// before the latest of them have to start over.
+    fn prune_tombstones(&self, cutoff: DateTime<Utc>) -> Result<(), StoreError>;
+
+    /// The task with its subtasks, recursively.
+    fn tree(&self, id: &str) -> Result<Option<TaskTree>, StoreError>;
+
+    /// Open tasks, each after the tasks blocking it.
+    fn order(&self) -> Result<Vec<Task>, StoreError>;
+
+    /// Folds whatever the backend logged into a compact form. Returns whether
+    /// there was anything to compact.
+    fn compact(&self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Flushes changes that were made but not yet made durable.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the backend `STORAGE_URL` points to.
+pub fn open(config: &Config) -> io::Result<DB> {
+    match &config.storage {
+        Storage::Memory => Ok(Arc::new(TaskStore::new())),
+        Storage::Files(dir) => Ok(Arc::new(TaskStore::open(dir, config.fsync)?)),
+        #[cfg(feature = "sqlite")]
+        Storage::Sqlite(path) => Ok(Arc::new(crate::sqlite::SqliteStore::open(path)?)),
+        #[cfg(not(feature = "sqlite"))]
+        Storage::Sqlite(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "STORAGE_URL names a SQLite database, but the server was built without the sqlite feature",
+        )),
+    }
+}
+
+/// Runs every case of [`conformance`] against the backend opened by `$open`,
+/// a function returning something to keep alive for the test along with the
+/// backend.
+#[cfg(test)]
+macro_rules! conformance_suite {
+    ($open:path) => {
+        $crate::repository::conformance_suite!(
+            $open;
+            pages_follow_insertion_order,
+            titles_stay_unique,
+            completing_a_recurring_task_adds_the_next_occurrence,
+            recurring_tasks_need_a_due_date,
+            open_blockers_hold_up_completion,
+            removing_a_task_relinks_its_neighbours,
+            missing_ids_are_reported,
+            selections_filter_and_sort,
+            trees_and_order_follow_the_links,
+            sync_merges_changes_field_by_field,
+            sync_deletions_need_to_be_the_last_change,
+            sync_changes_are_checked_like_any_other,
+            reverting_a_deletion_restores_the_task_and_its_links,
+            reverting_a_completion_drops_the_next_occurrence,
+            reverts_are_refused_once_a_task_changed,
+            reverts_go_through_whole_or_not_at_all
+        );
+    };
+    ($open:path; $($case:ident),+) => {
+        $(
+            #[test]
+            fn $case() {
+                let (_guard, repository) = $open();
+                $crate::repository::conformance::$case(&repository);
+            }
+        )+
+    };
+}
+
+#[cfg(test)]
+pub(crate) use conformance_suite;
+
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use crate::schedule::{self, Priority, Recurrence};
+
+    /// An open task with a fresh id, for tests across the crate.
+    pub(crate) fn task(title: &str) -> Task {
+        Task {
+            id: Some(uuid::Uuid::new_v4().to_string()),
+            title: title.to_string(),
+            content: String::new(),
+            completed: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn titles(tasks: Vec<Task>) -> Vec<String> {
+        tasks.into_iter().map(|task| task.title).collect()
+    }
+
+    fn complete(completed: bool) -> UpdateTaskSchema {
+        UpdateTaskSchema {
+            completed: Some(completed),
+            ..Default::default()
+        }
+    }
+
+    pub fn pages_follow_insertion_order(store: &dyn TaskRepository) {
+        let ids: Vec<String> = (0..5)
+            .map(|i| store.insert(task(&i.to_string())).unwrap().id.unwrap())
+            .collect();
+
+        assert_eq!(titles(store.list(1, 2).unwrap()), ["1", "2"]);
+        assert_eq!(titles(store.list(4, 10).unwrap()), ["4"]);
+        assert!(store.list(5, 10).unwrap().is_empty());
+        assert!(store.list(usize::MAX, usize::MAX).unwrap().is_empty());
+
+        store.remove(&ids[1]).unwrap();
+        assert_eq!(titles(store.list(0, 10).unwrap()), ["0", "2", "3", "4"]);
+        assert_eq!(store.get(&ids[3]).unwrap().unwrap().title, "3");
+        assert_eq!(store.len(), Ok(4));
+        // Updates keep a task in its place.
+        store.update(&ids[0], complete(true)).unwrap();
+        assert_eq!(titles(store.list(0, 1).unwrap()), ["0"]);
+    }
+
+    pub fn titles_stay_unique(store: &dyn TaskRepository) {
+        let milk = store.insert(task("milk")).unwrap().id.unwrap();
+        let eggs = store.insert(task("eggs")).unwrap().id.unwrap();
+        assert_eq!(
+            store.insert(task("milk")),
+            Err(StoreError::TitleTaken("milk".to_string()))
+        );
+
+        let rename = |title: &str| UpdateTaskSchema {
+            title: Some(title.to_string()),
+            ..Default::default()
+        };
+        assert!(store.update(&eggs, rename("milk")).is_err());
+        // Keeping its own title is not a conflict.
+        assert!(store.update(&milk, rename("milk")).is_ok());
+
+        // Renaming and removing free the old title.
+        store.update(&milk, rename("oat milk")).unwrap();
+        store.insert(task("milk")).unwrap();
+        store.remove(&eggs).unwrap();
+        store.insert(task("eggs")).unwrap();
+    }
+
+    pub fn completing_a_recurring_task_adds_the_next_occurrence(store: &dyn TaskRepository) {
+        let due = Utc::now() + chrono::Days::new(1);
+        let first = Task {
+            dueAt: Some(due),
+            recurrence: Some(Recurrence::Daily),
+            ..task("water plants")
+        };
+        let first = store.insert(first).unwrap().id.unwrap();
+
+        let done = store.update(&first, complete(true)).unwrap();
+        assert_eq!(done.recurrence, None);
+        let tasks = store.list(0, 10).unwrap();
+        assert_eq!(tasks.len(), 2);
+        let next = &tasks[1];
+        assert_eq!(next.title, "water plants");
+        assert_eq!(next.completed, Some(false));
+        assert_eq!(next.dueAt, Some(due + chrono::Days::new(1)));
+        assert_eq!(next.recurrence, Some(Recurrence::Daily));
+
+        // The open occurrence holds the title now.
+        assert_eq!(
+            store.update(&first, complete(false)),
+            Err(StoreError::TitleTaken("water plants".to_string()))
+        );
+        // Completing the same task twice does not add another.
+        store.update(&first, complete(true)).unwrap();
+        assert_eq!(store.len(), Ok(2));
+    }
+
+    pub fn recurring_tasks_need_a_due_date(store: &dyn TaskRepository) {
+        let recurring = Task {
+            recurrence: Some(Recurrence::Daily),
+            ..task("standup")
+        };
+        assert!(matches!(
+            store.insert(recurring),
+            Err(StoreError::Invalid(_))
+        ));
+        assert_eq!(store.is_empty(), Ok(true));
+    }
+
+    pub fn open_blockers_hold_up_completion(store: &dyn TaskRepository) {
+        let build = store.insert(task("build")).unwrap().id.unwrap();
+        let deploy = Task {
+            blockedBy: vec![build.clone()],
+            ..task("deploy")
+        };
+        let deploy = store.insert(deploy).unwrap().id.unwrap();
+
+        assert_eq!(
+            store.update(&deploy, complete(true)),
+            Err(StoreError::Blocked(vec!["build".to_string()]))
+        );
+        store.update(&build, complete(true)).unwrap();
+        store.update(&deploy, complete(true)).unwrap();
+    }
+
+    pub fn removing_a_task_relinks_its_neighbours(store: &dyn TaskRepository) {
+        let root = store.insert(task("root")).unwrap().id.unwrap();
+        let middle = Task {
+            parentId: Some(root.clone()),
+            ..task("middle")
+        };
+        let middle = store.insert(middle).unwrap().id.unwrap();
+        let leaf = Task {
+            parentId: Some(middle.clone()),
+            blockedBy: vec![middle.clone()],
+            ..task("leaf")
+        };
+        let leaf = store.insert(leaf).unwrap().id.unwrap();
+
+        store.remove(&middle).unwrap();
+        let leaf = store.get(&leaf).unwrap().unwrap();
+        assert_eq!(leaf.parentId, Some(root));
+        assert!(leaf.blockedBy.is_empty());
+    }
+
+    pub fn missing_ids_are_reported(store: &dyn TaskRepository) {
+        let missing = StoreError::NotFound("nope".to_string());
+        assert_eq!(store.get("nope"), Ok(None));
+        assert_eq!(store.tree("nope").unwrap().map(|tree| tree.task), None);
+        assert_eq!(store.remove("nope"), Err(missing.clone()));
+        assert_eq!(store.update("nope", complete(true)), Err(missing));
+    }
+
+    pub fn selections_filter_and_sort(store: &dyn TaskRepository) {
+        let soon = Utc::now() + chrono::Days::new(1);
+        for (title, priority, due) in [
+            ("low", Priority::Low, None),
+            (
+                "urgent",
+                Priority::Urgent,
+                Some(soon + chrono::Days::new(1)),
+            ),
+            ("high", Priority::High, Some(soon)),
+        ] {
+            let task = Task {
+                priority: Some(priority),
+                dueAt: due,
+                ..task(title)
+            };
+            store.insert(task).unwrap();
+        }
+
+        let all = |_: &Task| true;
+        let by_priority = store
+            .select(&all, Some(schedule::by_priority), 0, 10)
+            .unwrap();
+        assert_eq!(titles(by_priority), ["urgent", "high", "low"]);
+        let by_due = store
+            .select(&all, Some(schedule::by_due_date), 1, 1)
+            .unwrap();
+        assert_eq!(titles(by_due), ["urgent"]);
+        let due = store
+            .select(&|task| task.dueAt.is_some(), None, 0, usize::MAX)
+            .unwrap();
+        assert_eq!(titles(due), ["urgent", "high"]);
+    }
+
+    pub fn trees_and_order_follow_the_links(store: &dyn TaskRepository) {
+        let root = store.insert(task("root")).unwrap().id.unwrap();
+        let child = Task {
+            parentId: Some(root.clone()),
+            ..task("child")
+        };
+        let child = store.insert(child).unwrap().id.unwrap();
+        let blocked = Task {
+            blockedBy: vec![child.clone()],
+            ..task("blocked")
+        };
+        let blocked = store.insert(blocked).unwrap().id.unwrap();
+
+        let tree = store.tree(&root).unwrap().unwrap();
+        assert_eq!(tree.subtasks.len(), 1);
+        assert_eq!(tree.subtasks[0].task.title, "child");
+        assert_eq!(titles(store.order().unwrap()), ["root", "child", "blocked"]);
+
+        // Links are checked against what is stored.
+        let cycle = UpdateTaskSchema {
+            blockedBy: Some(vec![blocked]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.update(&child, cycle),
+            Err(StoreError::Invalid(_))
+        ));
+    }
+
+    fn change(id: &str, seconds: i64, fields: serde_json::Value) -> SyncChange {
+        SyncChange {
+            id: id.parse().unwrap(),
+            changedAt: Utc::now() + chrono::Duration::seconds(seconds),
+            fields: serde_json::from_value(fields).unwrap(),
+            deleted: false,
+        }
+    }
+
+    fn deletion(id: &str, seconds: i64) -> SyncChange {
+        SyncChange {
+            deleted: true,
+            ..change(id, seconds, serde_json::json!({}))
+        }
+    }
+
+    pub fn sync_merges_changes_field_by_field(store: &dyn TaskRepository) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let created = serde_json::json!({ "title": "offline", "content": "draft" });
+        store.merge("phone", change(&id, -60, created)).unwrap();
+        let token = store.changes(None, &[]).unwrap().token;
+
+        // An edit made on the server after the phone went offline again...
+        let rename = UpdateTaskSchema {
+            title: Some("renamed".to_string()),
+            ..Default::default()
+        };
+        store.update(&id, rename).unwrap();
+        // ...keeps its title, but not the content edited in between.
+        let edit = serde_json::json!({ "title": "stale", "content": "final" });
+        store.merge("phone", change(&id, -30, edit)).unwrap();
+        let task = store.get(&id).unwrap().unwrap();
+        assert_eq!(
+            (task.title.as_str(), task.content.as_str()),
+            ("renamed", "final")
+        );
+
+        let changes = store.changes(Some(token), &[]).unwrap();
+        assert!(!changes.reset);
+        assert_eq!(changes.tasks, [task]);
+        let later = store.changes(Some(changes.token), &[]).unwrap();
+        assert!(later.tasks.is_empty());
+        // Tasks asked for come along anyway.
+        let also = store.changes(Some(changes.token), &[id]).unwrap();
+        assert_eq!(also.tasks.len(), 1);
+        // Unknown tokens start over.
+        assert!(store.changes(Some(changes.token + 1), &[]).unwrap().reset);
+    }
+
+    pub fn sync_deletions_need_to_be_the_last_change(store: &dyn TaskRepository) {
+        let id = store.insert(task("milk")).unwrap().id.unwrap();
+        store.merge("phone", deletion(&id, -60)).unwrap();
+        assert!(
+            store.get(&id).unwrap().is_some(),
+            "the task was created later"
+        );
+
+        let token = store.changes(None, &[]).unwrap().token;
+        store.merge("phone", deletion(&id, 0)).unwrap();
+        assert_eq!(store.get(&id), Ok(None));
+        let changes = store.changes(Some(token), &[]).unwrap();
+        assert_eq!(changes.deleted.len(), 1);
+        assert_eq!(changes.deleted[0].deleted.origin, "phone");
+
+        // Deleted tasks are not brought back by late edits.
+        let edit = serde_json::json!({ "title": "oat milk" });
+        store.merge("laptop", change(&id, 0, edit)).unwrap();
+        assert_eq!(store.get(&id), Ok(None));
+
+        // Clients that last synced before a forgotten deletion start over.
+        store
+            .prune_tombstones(Utc::now() + chrono::Duration::seconds(1))
+            .unwrap();
+        assert!(store.changes(Some(token), &[]).unwrap().reset);
+        assert!(!store.changes(Some(changes.token), &[]).unwrap().reset);
+    }
+
+    pub fn sync_changes_are_checked_like_any_other(store: &dyn TaskRepository) {
+        store.insert(task("milk")).unwrap();
+        let id = uuid::Uuid::new_v4().to_string();
+        assert!(matches!(
+            store.merge(
+                "phone",
+                change(&id, 0, serde_json::json!({ "content": "?" }))
+            ),
+            Err(StoreError::Invalid(_))
+        ));
+        assert_eq!(
+            store.merge(
+                "phone",
+                change(&id, 0, serde_json::json!({ "title": "milk" }))
+            ),
+            Err(StoreError::TitleTaken("milk".to_string()))
+        );
+        assert_eq!(store.len(), Ok(1));
+    }
+
+    pub fn reverting_a_deletion_restores_the_task_and_its_links(store: &dyn TaskRepository) {
+        let root = store.insert(task("root")).unwrap().id.unwrap();
+        let leaf = Task {
+            parentId: Some(root.clone()),
+            blockedBy: vec![root.clone()],
+            ..task("leaf")
+        };
+        let leaf = store.insert(leaf).unwrap().id.unwrap();
+        let deleted = store.execute(Command::Delete { id: root.clone() }).unwrap();
+        let token = store.changes(None, &[]).unwrap().token;
+
+        let restored = store.execute(Command::Revert(deleted)).unwrap();
+        assert_eq!(store.get(&root).unwrap().unwrap().title, "root");
+        let linked = store.get(&leaf).unwrap().unwrap();
+        assert_eq!(linked.parentId, Some(root.clone()));
+        assert_eq!(linked.blockedBy, vec![root.clone()]);
+        // Clients that saw the deletion get the task back.
+        let changes = store.changes(Some(token), &[]).unwrap();
+        assert_eq!(changes.tasks.len(), 2);
+        assert!(changes.deleted.is_empty());
+
+        // Reverting the revert deletes it again.
+        store.execute(Command::Revert(restored)).unwrap();
+        assert_eq!(store.get(&root), Ok(None));
+        assert_eq!(store.get(&leaf).unwrap().unwrap().parentId, None);
+    }
+
+    pub fn reverting_a_completion_drops_the_next_occurrence(store: &dyn TaskRepository) {
+        let first = Task {
+            dueAt: Some(Utc::now() + chrono::Days::new(1)),
+            recurrence: Some(Recurrence::Daily),
+            ..task("water plants")
+        };
+        let first = store.insert(first).unwrap().id.unwrap();
+        let completed = store
+            .execute(Command::Update {
+                id: first.clone(),
+                changes: complete(true),
+            })
+            .unwrap();
+        assert_eq!(completed.len(), 2);
+
+        let reopened = store.execute(Command::Revert(completed)).unwrap();
+        assert_eq!(store.len(), Ok(1));
+        let task = store.get(&first).unwrap().unwrap();
+        assert_eq!(task.completed, Some(false));
+        assert_eq!(task.recurrence, Some(Recurrence::Daily));
+
+        // Redoing brings back the same next occurrence rather than a new one.
+        store.execute(Command::Revert(reopened)).unwrap();
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!(store.get(&first).unwrap().unwrap().completed, Some(true));
+    }
+
+    pub fn reverts_are_refused_once_a_task_changed(store: &dyn TaskRepository) {
+        let milk = task("milk");
+        let id = milk.id.clone().unwrap();
+        let created = store.execute(Command::Create(milk)).unwrap();
+        store.update(&id, complete(true)).unwrap();
+
+        assert_eq!(
+            store.execute(Command::Revert(created)),
+            Err(StoreError::Conflict(id.clone()))
+        );
+        assert_eq!(store.get(&id).unwrap().unwrap().completed, Some(true));
+    }
+
+    pub fn reverts_go_through_whole_or_not_at_all(store: &dyn TaskRepository) {
+        let milk = store.insert(task("milk")).unwrap().id.unwrap();
+        let eggs = store.insert(task("eggs")).unwrap().id.unwrap();
+        let rename = UpdateTaskSchema {
+            title: Some("oat milk".to_string()),
+            ..Default::default()
+        };
+        let renamed = store
+            .execute(Command::Update {
+                id: milk.clone(),
+                changes: rename,
+            })
+            .unwrap();
+        let deleted = store.execute(Command::Delete { id: eggs.clone() }).unwrap();
+        // Undoing both brings back "eggs" first, then fails to rename
+        // "oat milk" as "milk" is taken again.
+        store.insert(task("milk")).unwrap();
+        let both = [renamed, deleted].concat();
+
+        assert_eq!(
+            store.execute(Command::Revert(both)),
+            Err(StoreError::TitleTaken("milk".to_string()))
+        );
+        assert_eq!(store.get(&eggs), Ok(None));
+        assert_eq!(store.get(&milk).unwrap().unwrap().title, "oat milk");
+    }
+}
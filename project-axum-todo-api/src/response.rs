@@ -1,6 +1,10 @@
 use serde::Serialize;
 
+use crate::graph::TaskTree;
+use crate::history::{Action, TaskChange};
 use crate::model::Task;
+use crate::schedule::AgendaDay;
+use crate::sync::Tombstone;
 
 #[derive(Serialize)]
 pub struct GenericResponse {
@@ -25,3 +29,72 @@ pub struct TodoListResponse {
     pub results: usize,
     pub data: Vec<TaskData>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct AgendaResponse {
+    pub status: String,
+    pub overdue: Vec<Task>,
+    pub days: Vec<AgendaDay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskTreeResponse {
+    pub status: String,
+    pub data: TaskTree,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub status: String,
+    pub created: usize,
+    pub updated: usize,
+    /// What could not be imported as it was, and why.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    pub status: String,
+    /// To send as `since` next time.
+    pub token: String,
+    /// Whether `tasks` is the whole list, replacing what the client has.
+    pub reset: bool,
+    pub tasks: Vec<Task>,
+    pub deleted: Vec<Tombstone>,
+    /// Changes that could not be applied, and why.
+    pub rejected: Vec<RejectedChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedChange {
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+pub struct HistoryResponse {
+    pub status: String,
+    /// What was undone or redone.
+    pub action: Action,
+    pub taskId: String,
+    /// Every task that changed, as it was and as it is now.
+    pub changes: Vec<TaskChange>,
+}
+
+/// Sent over the live socket besides task events.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[allow(non_snake_case)]
+pub enum LiveReply {
+    /// Answers a request; `task` is the one it created, updated or deleted.
+    Ack {
+        requestId: Option<String>,
+        status: String,
+        task: Option<Box<Task>>,
+        message: Option<String>,
+    },
+    /// The client fell behind and missed this many events, so it should
+    /// load the list again.
+    Lagged { missed: u64 },
+}
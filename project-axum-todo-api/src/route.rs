@@ -0,0 +1,788 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use axum::routing::{get, post};
+use axum::Router;
+
+use crate::handler::{
+    agenda_handler, create_todo_handler, delete_todo_handler, edit_todo_handler,
+    export_ics_handler, get_todo_handler, health_checker_handler, import_ics_handler, live_handler,
+    order_handler, redo_handler, sync_changes_handler, sync_handler, todos_list_handler,
+    tree_handler, undo_handler,
+};
+use crate::history::Histories;
+use crate::live::Broadcasting;
+use crate::model::DB;
+
+/// What the handlers share; each takes the parts it needs.
+#[derive(Clone)]
+pub struct AppState {
+    /// Publishes every change to `live`.
+    pub db: DB,
+    pub live: Arc<Broadcasting>,
+    pub histories: Arc<Histories>,
+}
+
+impl FromRef<AppState> for DB {
+    fn from_ref(state: &AppState) -> DB {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Broadcasting> {
+    fn from_ref(state: &AppState) -> Arc<Broadcasting> {
+        state.live.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Histories> {
+    fn from_ref(state: &AppState) -> Arc<Histories> {
+        state.histories.clone()
+    }
+}
+
+pub fn create_router(db: DB, histories: Histories) -> Router {
+    let live = Arc::new(Broadcasting::new(db));
+    Router::new()
+        .route("/api/healthcheker", get(health_checker_handler))
+        .route(
+            "/api/todos",
+            get(todos_list_handler).post(create_todo_handler),
+        )
+        .route("/api/todos.ics", get(export_ics_handler))
+        .route("/api/todos/import", post(import_ics_handler))
+        .route("/api/todos/agenda", get(agenda_handler))
+        .route("/api/todos/order", get(order_handler))
+        .route("/api/todos/undo", post(undo_handler))
+        .route("/api/todos/redo", post(redo_handler))
+        .route("/api/todos/ws", get(live_handler))
+        .route(
+            "/api/todos/:id",
+            get(get_todo_handler)
+                .patch(edit_todo_handler)
+                .delete(delete_todo_handler),
+        )
+        .route("/api/todos/:id/tree", get(tree_handler))
+        .route("/api/sync", get(sync_changes_handler).post(sync_handler))
+        .with_state(AppState {
+            db: live.clone(),
+            live,
+            histories: Arc::new(histories),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::model::create_db;
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        send_as(app, None, method, uri, body).await
+    }
+
+    /// Like [`send`], naming the client whose history the request uses.
+    async fn send_as(
+        app: &Router,
+        client: Option<&str>,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(client) = client {
+            request = request.header("x-client-id", client);
+        }
+        let request = match body {
+            Some(body) => request.body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        };
+        (status, body)
+    }
+
+    async fn create(app: &Router, title: &str) -> String {
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": title, "content": "..." })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        body["data"]["task"]["id"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn crud_round_trip() {
+        let app = create_router(create_db(), Histories::default());
+
+        let id = create(&app, "Buy milk").await;
+        let (status, body) = send(&app, Method::GET, &format!("/api/todos/{}", id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["task"]["title"], "Buy milk");
+        assert_eq!(body["data"]["task"]["completed"], false);
+
+        let (status, body) = send(
+            &app,
+            Method::PATCH,
+            &format!("/api/todos/{}", id),
+            Some(json!({ "completed": true })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["task"]["completed"], true);
+        assert_eq!(body["data"]["task"]["title"], "Buy milk");
+
+        let (status, _) = send(&app, Method::DELETE, &format!("/api/todos/{}", id), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send(&app, Method::GET, &format!("/api/todos/{}", id), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status"], "fail");
+    }
+
+    #[tokio::test]
+    async fn lists_pages() {
+        let app = create_router(create_db(), Histories::default());
+        for i in 0..5 {
+            create(&app, &format!("Task {}", i)).await;
+        }
+
+        let (status, body) = send(&app, Method::GET, "/api/todos?page=2&limit=2", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["results"], 2);
+        assert_eq!(body["data"][0]["task"]["title"], "Task 2");
+
+        let (_, body) = send(&app, Method::GET, "/api/todos?page=3&limit=2", None).await;
+        assert_eq!(body["results"], 1);
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        assert_eq!(body["results"], 5);
+        let (_, body) = send(&app, Method::GET, "/api/todos?limit=0", None).await;
+        assert_eq!(body["results"], 1);
+    }
+
+    #[tokio::test]
+    async fn duplicate_titles_conflict() {
+        let app = create_router(create_db(), Histories::default());
+        create(&app, "Buy milk").await;
+        let other = create(&app, "Buy eggs").await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "Buy milk", "content": "again" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["status"], "fail");
+
+        let (status, _) = send(
+            &app,
+            Method::PATCH,
+            &format!("/api/todos/{}", other),
+            Some(json!({ "title": "Buy milk" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn unknown_ids_are_not_found() {
+        let app = create_router(create_db(), Histories::default());
+        let uri = format!("/api/todos/{}", uuid::Uuid::new_v4());
+        for (method, body) in [
+            (Method::GET, None),
+            (Method::PATCH, Some(json!({ "completed": true }))),
+            (Method::DELETE, None),
+        ] {
+            let (status, response) = send(&app, method, &uri, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(response["status"], "fail");
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_sorts_and_agenda() {
+        let app = create_router(create_db(), Histories::default());
+        let now = chrono::Utc::now();
+        let yesterday = now - chrono::Days::new(1);
+        let tomorrow = now + chrono::Days::new(1);
+        for (title, due, priority) in [
+            ("late", Some(yesterday), "low"),
+            ("soon", Some(tomorrow), "urgent"),
+            ("whenever", None, "high"),
+        ] {
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/api/todos",
+                Some(json!({ "title": title, "content": "", "dueAt": due, "priority": priority })),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let titles = |body: &Value| -> Vec<String> {
+            body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["task"]["title"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let (_, body) = send(&app, Method::GET, "/api/todos?overdue=true", None).await;
+        assert_eq!(titles(&body), ["late"]);
+        let (_, body) = send(&app, Method::GET, "/api/todos?sort=priority", None).await;
+        assert_eq!(titles(&body), ["soon", "whenever", "late"]);
+        let before =
+            (tomorrow + chrono::Days::new(1)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let (_, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/todos?due_before={}&sort=due", before),
+            None,
+        )
+        .await;
+        assert_eq!(titles(&body), ["late", "soon"]);
+
+        let (status, body) = send(&app, Method::GET, "/api/todos/agenda?days=3", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["overdue"][0]["title"], "late");
+        assert_eq!(body["days"].as_array().unwrap().len(), 3);
+        assert_eq!(body["days"][0]["date"], now.date_naive().to_string());
+        assert_eq!(body["days"][1]["tasks"][0]["title"], "soon");
+    }
+
+    #[tokio::test]
+    async fn invalid_recurrence_is_unprocessable() {
+        let app = create_router(create_db(), Histories::default());
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "standup", "content": "", "recurrence": { "frequency": "daily" } })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["status"], "fail");
+    }
+
+    #[tokio::test]
+    async fn dependencies_tree_and_order() {
+        let app = create_router(create_db(), Histories::default());
+        let build = create(&app, "build").await;
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "deploy", "content": "", "parentId": build, "blockedBy": [build] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let deploy = body["data"]["task"]["id"].as_str().unwrap().to_string();
+
+        let (status, body) = send(
+            &app,
+            Method::PATCH,
+            &format!("/api/todos/{}", build),
+            Some(json!({ "blockedBy": [deploy] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["message"],
+            "Blocking 'build' on 'deploy' would create a cycle: 'build' → 'deploy' → 'build'"
+        );
+
+        let (status, body) = send(
+            &app,
+            Method::PATCH,
+            &format!("/api/todos/{}", deploy),
+            Some(json!({ "completed": true })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["message"], "Todo is blocked by open task(s): 'build'");
+
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/todos/{}/tree", build),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["subtasks"][0]["task"]["title"], "deploy");
+
+        let (_, body) = send(&app, Method::GET, "/api/todos/order", None).await;
+        assert_eq!(body["results"], 2);
+        assert_eq!(body["data"][0]["task"]["title"], "build");
+    }
+
+    async fn send_text(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: String,
+    ) -> (StatusCode, String) {
+        send_text_as(app, None, method, uri, body).await
+    }
+
+    /// Like [`send_text`], naming the client whose history the request uses.
+    async fn send_text_as(
+        app: &Router,
+        client: Option<&str>,
+        method: Method,
+        uri: &str,
+        body: String,
+    ) -> (StatusCode, String) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "text/calendar");
+        if let Some(client) = client {
+            request = request.header("x-client-id", client);
+        }
+        let request = request.body(Body::from(body)).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ics_export_imports_into_another_list() {
+        let app = create_router(create_db(), Histories::default());
+        let build = create(&app, "build").await;
+        send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "deploy", "content": "", "blockedBy": [build] })),
+        )
+        .await;
+        let (status, ics) = send_text(&app, Method::GET, "/api/todos.ics", String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ics.contains(&format!("UID:{}", build)));
+
+        let other = create_router(create_db(), Histories::default());
+        let (status, body) =
+            send_text(&other, Method::POST, "/api/todos/import", ics.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            (body["created"].as_u64(), body["updated"].as_u64()),
+            (Some(2), Some(0))
+        );
+        assert_eq!(body["warnings"], json!([]));
+
+        // Importing again matches on UID instead of adding copies.
+        let (_, body) = send_text(&other, Method::POST, "/api/todos/import", ics).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            (body["created"].as_u64(), body["updated"].as_u64()),
+            (Some(0), Some(2))
+        );
+
+        let (_, body) = send(&other, Method::GET, "/api/todos/order", None).await;
+        assert_eq!(body["results"], 2);
+        let imported_build = body["data"][0]["task"]["id"].clone();
+        assert_eq!(body["data"][0]["task"]["uid"], json!(build));
+        assert_eq!(
+            body["data"][1]["task"]["blockedBy"],
+            json!([imported_build])
+        );
+
+        let (status, _) = send_text(
+            &other,
+            Method::POST,
+            "/api/todos/import",
+            "BEGIN:VTODO".into(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn completed_occurrences_keep_their_uid_to_themselves() {
+        let app = create_router(create_db(), Histories::default());
+        let ics = "BEGIN:VCALENDAR\r\n\
+                   BEGIN:VTODO\r\n\
+                   UID:standup@example.com\r\n\
+                   SUMMARY:Standup notes\r\n\
+                   DUE:20240304T090000Z\r\n\
+                   RRULE:FREQ=WEEKLY\r\n\
+                   END:VTODO\r\n\
+                   END:VCALENDAR\r\n";
+        send_text(&app, Method::POST, "/api/todos/import", ics.into()).await;
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        let id = body["data"][0]["task"]["id"].as_str().unwrap().to_string();
+        send(
+            &app,
+            Method::PATCH,
+            &format!("/api/todos/{}", id),
+            Some(json!({ "completed": true })),
+        )
+        .await;
+
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        assert_eq!(body["results"], 2);
+        assert_eq!(body["data"][0]["task"]["uid"], "standup@example.com");
+        assert_eq!(body["data"][1]["task"]["uid"], Value::Null);
+
+        let (_, ics) = send_text(&app, Method::GET, "/api/todos.ics", String::new()).await;
+        assert_eq!(ics.matches("UID:standup@example.com").count(), 1);
+        let (_, body) = send_text(&app, Method::POST, "/api/todos/import", ics).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            (body["created"].as_u64(), body["updated"].as_u64()),
+            (Some(0), Some(2))
+        );
+        assert_eq!(body["warnings"], json!([]));
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        assert_eq!(body["results"], 2);
+        assert_eq!(body["data"][0]["task"]["completed"], true);
+        assert_eq!(body["data"][1]["task"]["completed"], false);
+    }
+
+    #[tokio::test]
+    async fn sync_round_trip() {
+        let app = create_router(create_db(), Histories::default());
+        let (status, body) = send(&app, Method::GET, "/api/sync", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["reset"], true);
+        let token = body["token"].clone();
+
+        let milk = create(&app, "milk").await;
+        let offline = uuid::Uuid::new_v4().to_string();
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/sync",
+            Some(json!({
+                "clientId": "phone",
+                "since": token,
+                "changes": [
+                    { "id": offline, "changedAt": "2024-01-01T00:00:00Z",
+                      "fields": { "title": "eggs" } },
+                    { "id": offline, "changedAt": "2024-01-01T00:01:00Z",
+                      "fields": { "title": "milk" } },
+                ],
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["reset"], false);
+        assert_eq!(body["rejected"][0]["id"], json!(offline));
+        let titles: Vec<&str> = body["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["milk", "eggs"]);
+        assert_eq!(
+            body["tasks"][1]["versions"]["fields"]["title"]["origin"],
+            "phone"
+        );
+
+        send(&app, Method::DELETE, &format!("/api/todos/{}", milk), None).await;
+        let uri = format!("/api/sync?since={}", body["token"].as_str().unwrap());
+        let (_, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(body["tasks"], json!([]));
+        assert_eq!(body["deleted"][0]["id"], json!(milk));
+
+        let (status, _) = send(&app, Method::GET, "/api/sync?since=abc", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_follow_each_clients_history() {
+        let app = create_router(create_db(), Histories::default());
+        let phone = Some("phone");
+        let (_, body) = send_as(
+            &app,
+            phone,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "milk", "content": "" })),
+        )
+        .await;
+        let id = body["data"]["task"]["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/todos/{}", id);
+        send_as(
+            &app,
+            phone,
+            Method::PATCH,
+            &uri,
+            Some(json!({ "completed": true })),
+        )
+        .await;
+        send_as(&app, phone, Method::DELETE, &uri, None).await;
+
+        // Without a client id there is no history to undo.
+        let (status, _) = send(&app, Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) =
+            send_as(&app, Some("laptop"), Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = send_as(&app, phone, Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["action"], "delete");
+        assert_eq!(body["changes"][0]["after"]["title"], "milk");
+        let (_, body) = send_as(&app, phone, Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(body["action"], "complete");
+        let (_, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(body["data"]["task"]["completed"], false);
+
+        let (status, body) = send_as(&app, phone, Method::POST, "/api/todos/redo", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["action"], "complete");
+        let (_, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(body["data"]["task"]["completed"], true);
+
+        // A new change means the deletion can no longer be redone.
+        send_as(
+            &app,
+            phone,
+            Method::PATCH,
+            &uri,
+            Some(json!({ "content": "oat" })),
+        )
+        .await;
+        let (status, _) = send_as(&app, phone, Method::POST, "/api/todos/redo", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Someone else changing the task keeps this client from undoing its
+        // own change to it.
+        send(&app, Method::PATCH, &uri, Some(json!({ "content": "soy" }))).await;
+        let (status, body) = send_as(&app, phone, Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["message"].as_str().unwrap().contains("changed since"));
+    }
+
+    #[tokio::test]
+    async fn imports_are_undone_as_a_whole() {
+        let app = create_router(create_db(), Histories::default());
+        let phone = Some("phone");
+        let build = create(&app, "build").await;
+        let ics = format!(
+            "BEGIN:VCALENDAR\r\n\
+             BEGIN:VTODO\r\n\
+             UID:{}\r\n\
+             SUMMARY:build all\r\n\
+             END:VTODO\r\n\
+             BEGIN:VTODO\r\n\
+             UID:deploy@example.com\r\n\
+             SUMMARY:deploy\r\n\
+             RELATED-TO;RELTYPE=DEPENDS-ON:{}\r\n\
+             END:VTODO\r\n\
+             END:VCALENDAR\r\n",
+            build, build
+        );
+        let (status, _) = send_text_as(&app, phone, Method::POST, "/api/todos/import", ics).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        assert_eq!(body["results"], 2);
+
+        let (status, body) = send_as(&app, phone, Method::POST, "/api/todos/undo", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["action"], "import");
+        let (_, body) = send(&app, Method::GET, "/api/todos", None).await;
+        assert_eq!(body["results"], 1);
+        assert_eq!(body["data"][0]["task"]["title"], "build");
+
+        let (status, _) = send_as(&app, phone, Method::POST, "/api/todos/redo", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, Method::GET, "/api/todos/order", None).await;
+        assert_eq!(body["results"], 2);
+        assert_eq!(body["data"][0]["task"]["title"], "build all");
+        assert_eq!(body["data"][1]["task"]["blockedBy"], json!([build]));
+    }
+
+    /// The next text frame, as JSON.
+    async fn receive<S>(socket: &mut S) -> Value
+    where
+        S: futures_util::Stream<
+                Item = Result<
+                    tokio_tungstenite::tungstenite::Message,
+                    tokio_tungstenite::tungstenite::Error,
+                >,
+            > + Unpin,
+    {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        loop {
+            if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Serves `app` on a free port, returning the URL of its socket.
+    fn serve(app: &Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/api/todos/ws", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener).unwrap();
+        tokio::spawn(server.serve(app.clone().into_make_service()));
+        url
+    }
+
+    #[tokio::test]
+    async fn live_clients_see_every_change() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::connect_async;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app = create_router(create_db(), Histories::default());
+        let url = serve(&app);
+        let (mut tab, _) = connect_async(&url).await.unwrap();
+        let (mut other, _) = connect_async(&url).await.unwrap();
+
+        let request = json!({
+            "requestId": "1",
+            "op": "create",
+            "task": { "title": "groceries", "content": "" },
+        });
+        tab.send(Message::Text(request.to_string())).await.unwrap();
+        let ack = receive(&mut tab).await;
+        assert_eq!(ack["type"], "ack");
+        assert_eq!(ack["requestId"], "1");
+        assert_eq!(ack["status"], "success");
+        let list = ack["task"]["id"].as_str().unwrap().to_string();
+        for socket in [&mut tab, &mut other] {
+            let event = receive(socket).await;
+            assert_eq!(event["type"], "created");
+            assert_eq!(event["task"]["id"], json!(list));
+        }
+
+        // Changes made over REST are pushed too, and a client can watch one
+        // list only.
+        let (mut watcher, _) = connect_async(format!("{}?parentId={}", url, list))
+            .await
+            .unwrap();
+        create(&app, "unrelated").await;
+        let (_, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "milk", "content": "", "parentId": list })),
+        )
+        .await;
+        let milk = body["data"]["task"]["id"].clone();
+        let event = receive(&mut watcher).await;
+        assert_eq!(event["type"], "created");
+        assert_eq!(event["task"]["id"], milk);
+        assert_eq!(receive(&mut other).await["task"]["title"], "unrelated");
+        assert_eq!(receive(&mut other).await["task"]["title"], "milk");
+
+        let request = json!({ "requestId": "2", "op": "delete", "todoId": milk });
+        other
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+        let ack = receive(&mut other).await;
+        assert_eq!(ack["status"], "success");
+        assert_eq!(ack["task"]["title"], "milk");
+        for socket in [&mut other, &mut watcher] {
+            let event = receive(socket).await;
+            assert_eq!(event["type"], "deleted");
+            assert_eq!(event["task"]["id"], milk);
+        }
+
+        // Failures are acknowledged rather than ending the session.
+        let request = json!({ "requestId": "3", "op": "delete", "todoId": milk });
+        other
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(receive(&mut other).await["status"], "fail");
+        other
+            .send(Message::Text("nonsense".to_string()))
+            .await
+            .unwrap();
+        let ack = receive(&mut other).await;
+        assert_eq!(ack["status"], "fail");
+        assert!(ack["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request"));
+    }
+
+    #[tokio::test]
+    async fn list_watchers_see_tasks_move_out() {
+        use tokio_tungstenite::connect_async;
+
+        let app = create_router(create_db(), Histories::default());
+        let url = serve(&app);
+        let groceries = create(&app, "groceries").await;
+        let hardware = create(&app, "hardware").await;
+        let (_, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "tape", "content": "", "parentId": groceries })),
+        )
+        .await;
+        let tape = body["data"]["task"]["id"].as_str().unwrap().to_string();
+        let (mut watcher, _) = connect_async(format!("{}?parentId={}", url, groceries))
+            .await
+            .unwrap();
+
+        let uri = format!("/api/todos/{}", tape);
+        send(
+            &app,
+            Method::PATCH,
+            &uri,
+            Some(json!({ "parentId": hardware })),
+        )
+        .await;
+        let event = receive(&mut watcher).await;
+        assert_eq!(event["type"], "updated");
+        assert_eq!(event["task"]["id"], json!(tape));
+        assert_eq!(event["task"]["parentId"], json!(hardware));
+        assert_eq!(event["previousParentId"], json!(groceries));
+
+        // Once gone, the task is no longer the watcher's business.
+        send(
+            &app,
+            Method::PATCH,
+            &uri,
+            Some(json!({ "content": "duct" })),
+        )
+        .await;
+        create(&app, "unrelated").await;
+        let (_, body) = send(
+            &app,
+            Method::POST,
+            "/api/todos",
+            Some(json!({ "title": "milk", "content": "", "parentId": groceries })),
+        )
+        .await;
+        let event = receive(&mut watcher).await;
+        assert_eq!(event["type"], "created");
+        assert_eq!(event["task"]["id"], body["data"]["task"]["id"]);
+    }
+}
@@ -0,0 +1,390 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::model::Task;
+
+/// Longest interval a recurrence can have, about ten years.
+const MAX_INTERVAL_DAYS: u32 = 3653;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+/// How a task repeats, counted from its due date.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "frequency", rename_all = "lowercase")]
+pub enum Recurrence {
+    Daily,
+    /// On each of `weekdays`, or every seven days when there are none.
+    Weekly {
+        #[serde(default)]
+        weekdays: Vec<Weekday>,
+    },
+    /// On `day` of every month, or its last day in months that are shorter.
+    /// Taken from the first due date when not given.
+    Monthly {
+        #[serde(default)]
+        day: Option<u32>,
+    },
+    /// Every `days` days.
+    Interval {
+        days: u32,
+    },
+}
+
+impl Recurrence {
+    /// The first occurrence after `due`, or `None` past the last date that
+    /// can be represented.
+    fn after(&self, due: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Recurrence::Daily => due.checked_add_days(Days::new(1)),
+            Recurrence::Weekly { weekdays } if weekdays.is_empty() => {
+                due.checked_add_days(Days::new(7))
+            }
+            Recurrence::Weekly { weekdays } => (1..=7)
+                .map_while(|days| due.checked_add_days(Days::new(days)))
+                .find(|next| weekdays.contains(&next.weekday())),
+            Recurrence::Monthly { day } => in_month(due, day.unwrap_or(due.day()), 1),
+            Recurrence::Interval { days } => due.checked_add_days(Days::new(u64::from(*days))),
+        }
+    }
+
+    /// The next occurrence after `due` that is still ahead of `now`, so that
+    /// finishing a task late does not leave a trail of overdue ones.
+    pub fn next(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = self.skip(due, now)?;
+        loop {
+            next = self.after(next)?;
+            if next > now {
+                return Some(next);
+            }
+        }
+    }
+
+    /// A point from `due` on and not past `now` that the occurrences after it
+    /// are the same from, reached in whole periods at once rather than one by
+    /// one, however long ago `due` was.
+    fn skip(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let Ok(behind) = u64::try_from((now - due).num_days()) else {
+            return Some(due);
+        };
+        let whole = |period: u64| due.checked_add_days(Days::new(behind / period * period));
+        match self {
+            Recurrence::Daily => whole(1),
+            Recurrence::Weekly { .. } => whole(7),
+            Recurrence::Interval { days } => whole(u64::from(*days)),
+            Recurrence::Monthly { day } => {
+                let months =
+                    (now.year() - due.year()) * 12 + now.month() as i32 - due.month() as i32;
+                // The month before the one `now` is in is as far as is
+                // surely not past it.
+                match u32::try_from(months - 1) {
+                    Ok(months) if months > 0 => in_month(due, day.unwrap_or(due.day()), months),
+                    _ => Some(due),
+                }
+            }
+        }
+    }
+}
+
+/// `day` of the month `months` after the one `due` is in, at the time of day
+/// of `due`; the last day of months that are shorter.
+fn in_month(due: DateTime<Utc>, day: u32, months: u32) -> Option<DateTime<Utc>> {
+    let month = due
+        .date_naive()
+        .with_day(1)?
+        .checked_add_months(Months::new(months))?;
+    let date = month.with_day(day.min(days_in_month(month)))?;
+    Some(date.and_time(due.time()).and_utc())
+}
+
+fn days_in_month(first: NaiveDate) -> u32 {
+    (29..=31)
+        .rev()
+        .find(|day| first.with_day(*day).is_some())
+        .unwrap_or(28)
+}
+
+/// Checks the schedule of a task about to be stored and fills in what the
+/// recurrence leaves to the due date.
+pub fn normalize(task: &mut Task) -> Result<(), String> {
+    let Some(recurrence) = &mut task.recurrence else {
+        return Ok(());
+    };
+    let Some(due) = task.dueAt else {
+        return Err("Recurring tasks need a dueAt".to_string());
+    };
+    match recurrence {
+        Recurrence::Monthly { day: Some(day) } if !(1..=31).contains(day) => {
+            Err("The day of a monthly recurrence must be between 1 and 31".to_string())
+        }
+        Recurrence::Monthly { day } => {
+            day.get_or_insert(due.day());
+            Ok(())
+        }
+        Recurrence::Interval { days: 0 } => {
+            Err("An interval recurrence must be at least 1 day".to_string())
+        }
+        Recurrence::Interval { days } if *days > MAX_INTERVAL_DAYS => Err(format!(
+            "An interval recurrence must be at most {} days",
+            MAX_INTERVAL_DAYS
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The follow-up of a recurring task that was just completed, if it has one.
+pub fn next_occurrence(task: &Task, now: DateTime<Utc>) -> Result<Option<Task>, String> {
+    let (Some(recurrence), Some(due)) = (&task.recurrence, task.dueAt) else {
+        return Ok(None);
+    };
+    let Some(due) = recurrence.next(due, now) else {
+        return Err("The next occurrence would be too far in the future".to_string());
+    };
+    Ok(Some(Task {
+        id: Some(uuid::Uuid::new_v4().to_string()),
+        // The UID names the occurrence just completed; this one is exported
+        // under its own id.
+        uid: None,
+        completed: Some(false),
+        createdAt: Some(now),
+        updatedAt: Some(now),
+        dueAt: Some(due),
+        ..task.clone()
+    }))
+}
+
+pub fn is_overdue(task: &Task, now: DateTime<Utc>) -> bool {
+    task.completed != Some(true) && task.dueAt.is_some_and(|due| due < now)
+}
+
+/// Most urgent first, then soonest due; tasks without a due date go last.
+pub fn by_priority(a: &Task, b: &Task) -> Ordering {
+    let priority = |task: &Task| task.priority.unwrap_or_default();
+    priority(b)
+        .cmp(&priority(a))
+        .then_with(|| by_due_date(a, b))
+}
+
+/// Soonest due first; tasks without a due date go last.
+pub fn by_due_date(a: &Task, b: &Task) -> Ordering {
+    match (a.dueAt, b.dueAt) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Open tasks due on one day.
+#[derive(Debug, Serialize)]
+pub struct AgendaDay {
+    pub date: NaiveDate,
+    pub tasks: Vec<Task>,
+}
+
+/// Open tasks already overdue at `now`, and the ones due on each of the
+/// `days` days from `from` (UTC), every day listed even when empty.
+pub fn agenda(
+    tasks: Vec<Task>,
+    from: NaiveDate,
+    days: u32,
+    now: DateTime<Utc>,
+) -> (Vec<Task>, Vec<AgendaDay>) {
+    let mut overdue = vec![];
+    let mut agenda: Vec<AgendaDay> = (0..days)
+        .map(|day| AgendaDay {
+            date: from + Days::new(u64::from(day)),
+            tasks: vec![],
+        })
+        .collect();
+
+    for task in tasks {
+        if task.completed == Some(true) {
+            continue;
+        }
+        let Some(due) = task.dueAt else {
+            continue;
+        };
+        if due < now {
+            overdue.push(task);
+            continue;
+        }
+        let day = (due.date_naive() - from).num_days();
+        if let Some(agenda_day) = usize::try_from(day)
+            .ok()
+            .and_then(|day| agenda.get_mut(day))
+        {
+            agenda_day.tasks.push(task);
+        }
+    }
+
+    overdue.sort_by(by_due_date);
+    for day in &mut agenda {
+        day.tasks
+            .sort_by(|a, b| by_due_date(a, b).then_with(|| by_priority(a, b)));
+    }
+    (overdue, agenda)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        format!("{}T09:00:00Z", date).parse().unwrap()
+    }
+
+    #[test]
+    fn occurrences_follow_the_rule() {
+        let due = at("2024-01-31"); // A Wednesday.
+        let far_past = at("2000-01-01");
+        let next = |rule: Recurrence| rule.next(due, far_past).unwrap();
+
+        assert_eq!(next(Recurrence::Daily), at("2024-02-01"));
+        assert_eq!(
+            next(Recurrence::Weekly { weekdays: vec![] }),
+            at("2024-02-07")
+        );
+        assert_eq!(
+            next(Recurrence::Weekly {
+                weekdays: vec![Weekday::Mon, Weekday::Fri]
+            }),
+            at("2024-02-02")
+        );
+        assert_eq!(next(Recurrence::Interval { days: 10 }), at("2024-02-10"));
+        // Clamped in short months without drifting afterwards.
+        let monthly = Recurrence::Monthly { day: Some(31) };
+        assert_eq!(monthly.next(due, far_past), Some(at("2024-02-29")));
+        assert_eq!(
+            monthly.next(at("2024-02-29"), far_past),
+            Some(at("2024-03-31"))
+        );
+    }
+
+    #[test]
+    fn late_completion_skips_missed_occurrences() {
+        let rule = Recurrence::Daily;
+        assert_eq!(
+            rule.next(at("2024-01-01"), at("2024-01-09")),
+            Some(at("2024-01-10"))
+        );
+        let now = "2024-01-10T10:00:00Z".parse().unwrap();
+        assert_eq!(rule.next(at("2024-01-01"), now), Some(at("2024-01-11")));
+    }
+
+    #[test]
+    fn long_overdue_tasks_jump_straight_to_the_next_occurrence() {
+        let now = at("2024-01-31");
+        let cases = [
+            (Recurrence::Daily, at("1999-12-31"), at("2024-02-01")),
+            (
+                Recurrence::Weekly {
+                    weekdays: vec![Weekday::Mon, Weekday::Fri],
+                },
+                at("2000-01-03"),
+                at("2024-02-02"),
+            ),
+            (
+                Recurrence::Interval { days: 10 },
+                at("2024-01-01"),
+                at("2024-02-10"),
+            ),
+            (
+                Recurrence::Monthly { day: Some(31) },
+                at("2001-01-31"),
+                at("2024-02-29"),
+            ),
+            (
+                Recurrence::Monthly { day: Some(20) },
+                at("2023-12-31"),
+                at("2024-02-20"),
+            ),
+        ];
+        for (rule, due, next) in cases {
+            assert_eq!(rule.next(due, now), Some(next), "{:?}", rule);
+        }
+        // Stepping a day at a time from here would take hundreds of millions.
+        assert_eq!(
+            Recurrence::Daily.next(DateTime::<Utc>::MIN_UTC, now),
+            Some("2024-02-01T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn occurrences_past_the_end_of_time_are_refused() {
+        let last = DateTime::<Utc>::MAX_UTC;
+        let far_past = at("2000-01-01");
+        assert_eq!(Recurrence::Daily.next(last, far_past), None);
+        assert_eq!(
+            Recurrence::Monthly { day: Some(1) }.next(last, far_past),
+            None
+        );
+        let task = Task {
+            dueAt: Some(last),
+            recurrence: Some(Recurrence::Weekly {
+                weekdays: vec![Weekday::Mon],
+            }),
+            ..Default::default()
+        };
+        assert!(next_occurrence(&task, far_past).is_err());
+    }
+
+    #[test]
+    fn normalizing_checks_and_completes_the_rule() {
+        let mut task = Task {
+            dueAt: Some(at("2024-01-15")),
+            recurrence: Some(Recurrence::Monthly { day: None }),
+            ..Default::default()
+        };
+        normalize(&mut task).unwrap();
+        assert_eq!(task.recurrence, Some(Recurrence::Monthly { day: Some(15) }));
+
+        task.recurrence = Some(Recurrence::Interval { days: 0 });
+        assert!(normalize(&mut task).is_err());
+        task.recurrence = Some(Recurrence::Interval { days: u32::MAX });
+        assert!(normalize(&mut task).is_err());
+        task.recurrence = Some(Recurrence::Daily);
+        task.dueAt = None;
+        assert!(normalize(&mut task).is_err());
+    }
+
+    #[test]
+    fn agenda_groups_open_tasks_by_day() {
+        let task = |title: &str, due: Option<&str>, completed: bool| Task {
+            title: title.to_string(),
+            dueAt: due.map(|due| due.parse().unwrap()),
+            completed: Some(completed),
+            ..Default::default()
+        };
+        let tasks = vec![
+            task("late", Some("2024-01-01T12:00:00Z"), false),
+            task("done", Some("2024-01-02T12:00:00Z"), true),
+            task("evening", Some("2024-01-02T18:00:00Z"), false),
+            task("morning", Some("2024-01-02T08:00:00Z"), false),
+            task("later", Some("2024-01-04T08:00:00Z"), false),
+            task("someday", None, false),
+            task("beyond", Some("2024-01-05T08:00:00Z"), false),
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let now = "2024-01-02T00:00:00Z".parse().unwrap();
+        let (overdue, days) = agenda(tasks, today, 3, now);
+
+        let titles = |tasks: &[Task]| -> Vec<String> {
+            tasks.iter().map(|task| task.title.clone()).collect()
+        };
+        assert_eq!(titles(&overdue), ["late"]);
+        assert_eq!(days.len(), 3);
+        assert_eq!(titles(&days[0].tasks), ["morning", "evening"]);
+        assert!(days[1].tasks.is_empty());
+        assert_eq!(titles(&days[2].tasks), ["later"]);
+    }
+}
@@ -1,14 +1,130 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::model::Task;
+use crate::schedule::{Priority, Recurrence};
+use crate::sync::Field;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct QueryOptions {
     pub page: Option<usize>,
     pub limit: Option<usize>,
+    /// Only open tasks past their due date, or only the others.
+    pub overdue: Option<bool>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub sort: Option<SortOrder>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Most urgent first, then soonest due.
+    Priority,
+    /// Soonest due first.
+    Due,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AgendaOptions {
+    /// First day of the agenda (UTC); today by default.
+    pub from: Option<NaiveDate>,
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[allow(non_snake_case)]
 pub struct UpdateTaskSchema {
     pub title: Option<String>,
     pub content: Option<String>,
     pub completed: Option<bool>,
+    /// `Some(None)` (an explicit `null`) clears the due date.
+    #[serde(default, deserialize_with = "double_option")]
+    pub dueAt: Option<Option<DateTime<Utc>>>,
+    pub priority: Option<Priority>,
+    /// `Some(None)` stops the task from repeating.
+    #[serde(default, deserialize_with = "double_option")]
+    pub recurrence: Option<Option<Recurrence>>,
+    /// `Some(None)` makes the task a top-level one.
+    #[serde(default, deserialize_with = "double_option")]
+    pub parentId: Option<Option<String>>,
+    /// Replaces the whole list.
+    pub blockedBy: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SyncOptions {
+    /// The token returned by the last sync, if any.
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct SyncRequest {
+    /// Names the client; breaks ties between changes made at the same time.
+    pub clientId: String,
+    pub since: Option<String>,
+    #[serde(default)]
+    pub changes: Vec<SyncChange>,
+}
+
+/// Fields a client set on a task, or its deletion, at `changedAt`. Tasks the
+/// server has not seen yet are created with the id the client gave them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[allow(non_snake_case)]
+pub struct SyncChange {
+    pub id: Uuid,
+    pub changedAt: DateTime<Utc>,
+    #[serde(default)]
+    pub fields: BTreeMap<Field, Value>,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[allow(non_snake_case)]
+pub struct LiveOptions {
+    /// Only events about this task and its direct subtasks.
+    pub parentId: Option<Uuid>,
+    /// Whose undo history changes sent over the socket go into; browsers
+    /// cannot set `X-Client-Id` on a WebSocket.
+    pub clientId: Option<String>,
+}
+
+/// A change sent over the live socket, answered with an ack carrying the same
+/// `requestId`.
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct LiveRequest {
+    pub requestId: Option<String>,
+    #[serde(flatten)]
+    pub mutation: LiveMutation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+#[allow(non_snake_case)]
+pub enum LiveMutation {
+    Create {
+        task: Task,
+    },
+    Update {
+        todoId: Uuid,
+        changes: UpdateTaskSchema,
+    },
+    Delete {
+        todoId: Uuid,
+    },
+}
+
+/// Distinguishes a field that is present but `null` from one that is missing.
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
 }
@@ -0,0 +1,364 @@
+use std::cmp::Ordering;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::de::DeserializeOwned;
+
+use crate::graph::{self, TaskTree};
+use crate::history::{Command, TaskChange};
+use crate::model::Task;
+use crate::persist::{Record, State};
+use crate::repository::TaskRepository;
+use crate::schema::SyncChange;
+use crate::store::{self, Inner, Journal, StoreError};
+use crate::sync::{Changes, Tombstone};
+
+/// Applied in order, each once; `PRAGMA user_version` counts how many were.
+const MIGRATIONS: &[&str] = &[include_str!("../migrations/sqlite/0001_create_tasks.sql")];
+
+/// Tasks kept in a SQLite database.
+///
+/// A change is checked against the tasks it could conflict or link with by
+/// the same code as the in-memory store, inside one transaction; what that
+/// code logs is then written to the tables. Checking links needs the whole
+/// graph, so writes read every task, which is fine at the size of a todo list.
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path` (`:memory:` for one that only
+    /// lives as long as the store) and brings its schema up to date.
+    pub fn open(path: &str) -> io::Result<SqliteStore> {
+        let mut conn = Connection::open(path).map_err(io::Error::other)?;
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(io::Error::other)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))
+            .map_err(io::Error::other)?;
+        migrate(&mut conn)?;
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // A panic with the lock held drops its transaction, which rolls back.
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Runs `change` on the stored tasks and writes out what it logged, all
+    /// in one transaction. `tombstone` is the one deleted task it needs to
+    /// know about, if any.
+    fn write<T>(
+        &self,
+        tombstone: Option<&str>,
+        change: impl FnOnce(&mut Inner) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut conn = self.lock();
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(failed)?;
+        let (seq, horizon) = sync_state(&tx)?;
+        let mut tombstones = IndexMap::new();
+        if let Some(id) = tombstone {
+            let data: Option<String> = tx
+                .query_row("SELECT data FROM tombstones WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(failed)?;
+            if let Some(data) = data {
+                tombstones.insert(id.to_string(), decode(&data)?);
+            }
+        }
+        let state = State {
+            tasks: all_tasks(&tx)?,
+            tombstones,
+            horizon,
+        };
+        let mut inner = Inner::new(state, Journal::Batch(vec![]));
+        inner.seq = seq;
+
+        let result = change(&mut inner)?;
+        let Journal::Batch(records) = inner.journal else {
+            unreachable!("the journal was set above");
+        };
+        for record in records {
+            write_record(&tx, record)?;
+        }
+        tx.execute("UPDATE sync_state SET seq = ?1", [inner.seq])
+            .map_err(failed)?;
+        tx.commit().map_err(failed)?;
+        Ok(result)
+    }
+
+    fn read<T>(
+        &self,
+        query: impl FnOnce(&Transaction) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(failed)?;
+        query(&tx)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> io::Result<()> {
+    let tx = conn.transaction().map_err(io::Error::other)?;
+    let applied: usize = tx
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(io::Error::other)?;
+    if applied > MIGRATIONS.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the database has {} migration(s) applied, but this server only knows {}",
+                applied,
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        tx.execute_batch(migration).map_err(io::Error::other)?;
+        tx.pragma_update(None, "user_version", version + 1)
+            .map_err(io::Error::other)?;
+    }
+    tx.commit().map_err(io::Error::other)
+}
+
+fn failed(err: impl ToString) -> StoreError {
+    StoreError::Persistence(err.to_string())
+}
+
+fn decode<T: DeserializeOwned>(data: &str) -> Result<T, StoreError> {
+    serde_json::from_str(data).map_err(failed)
+}
+
+fn encode(value: &impl serde::Serialize) -> Result<String, StoreError> {
+    serde_json::to_string(value).map_err(failed)
+}
+
+fn sync_state(tx: &Transaction) -> Result<(u64, u64), StoreError> {
+    tx.query_row("SELECT seq, horizon FROM sync_state", [], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })
+    .map_err(failed)
+}
+
+/// Decodes the `data` column of every row `sql` returns.
+fn query<T: DeserializeOwned>(
+    tx: &Transaction,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<T>, StoreError> {
+    let mut statement = tx.prepare_cached(sql).map_err(failed)?;
+    let rows = statement
+        .query_map(params, |row| row.get::<_, String>(0))
+        .map_err(failed)?;
+    rows.map(|data| decode(&data.map_err(failed)?)).collect()
+}
+
+fn all_tasks(tx: &Transaction) -> Result<IndexMap<String, Task>, StoreError> {
+    let tasks: Vec<Task> = query(tx, "SELECT data FROM tasks ORDER BY position", [])?;
+    Ok(tasks
+        .into_iter()
+        .map(|task| (task.id.clone().expect("stored tasks have an id"), task))
+        .collect())
+}
+
+fn write_record(tx: &Transaction, record: Record) -> Result<(), StoreError> {
+    match record {
+        Record::Put { task } => {
+            tx.execute(
+                "INSERT INTO tasks (id, position, seq, data)
+                 VALUES (?1, (SELECT IFNULL(MAX(position), 0) + 1 FROM tasks), ?2, ?3)
+                 ON CONFLICT (id) DO UPDATE SET seq = excluded.seq, data = excluded.data",
+                params![task.id, task.versions.seq, encode(&task)?],
+            )
+            .map_err(failed)?;
+            // Only undoing a deletion brings a task back.
+            tx.execute("DELETE FROM tombstones WHERE id = ?1", [&task.id])
+                .map_err(failed)?;
+        }
+        Record::Delete { id, tombstone } => {
+            tx.execute("DELETE FROM tasks WHERE id = ?1", [&id])
+                .map_err(failed)?;
+            if let Some(tombstone) = tombstone {
+                tx.execute(
+                    "INSERT OR REPLACE INTO tombstones (id, seq, deleted_at, data)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        id,
+                        tombstone.seq,
+                        tombstone.deleted.at.timestamp_micros(),
+                        encode(&tombstone)?
+                    ],
+                )
+                .map_err(failed)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl TaskRepository for SqliteStore {
+    fn len(&self) -> Result<usize, StoreError> {
+        self.read(|tx| {
+            tx.query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get(0))
+                .map_err(failed)
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Task>, StoreError> {
+        self.read(|tx| {
+            let tasks = query(tx, "SELECT data FROM tasks WHERE id = ?1", [id])?;
+            Ok(tasks.into_iter().next())
+        })
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Task>, StoreError> {
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        self.read(|tx| {
+            query(
+                tx,
+                "SELECT data FROM tasks ORDER BY position LIMIT ?1 OFFSET ?2",
+                [limit, offset],
+            )
+        })
+    }
+
+    fn select(
+        &self,
+        filter: &dyn Fn(&Task) -> bool,
+        order: Option<fn(&Task, &Task) -> Ordering>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Task>, StoreError> {
+        let tasks = self.read(all_tasks)?;
+        Ok(store::select(tasks.values(), filter, order, offset, limit))
+    }
+
+    fn execute(&self, command: Command) -> Result<Vec<TaskChange>, StoreError> {
+        self.write(None, |inner| inner.execute(command, Utc::now()))
+    }
+
+    fn merge(&self, origin: &str, change: SyncChange) -> Result<Vec<TaskChange>, StoreError> {
+        let id = change.id.to_string();
+        self.write(Some(&id), |inner| inner.merge(origin, change, Utc::now()))
+    }
+
+    fn changes(&self, since: Option<u64>, also: &[String]) -> Result<Changes, StoreError> {
+        let also = encode(&also)?;
+        self.read(|tx| {
+            let (seq, horizon) = sync_state(tx)?;
+            let Some(since) = since.filter(|since| (horizon..=seq).contains(since)) else {
+                return Ok(Changes {
+                    token: seq,
+                    reset: true,
+                    tasks: all_tasks(tx)?.into_values().collect(),
+                    deleted: vec![],
+                });
+            };
+            Ok(Changes {
+                token: seq,
+                reset: false,
+                tasks: query(
+                    tx,
+                    "SELECT data FROM tasks
+                     WHERE seq > ?1 OR id IN (SELECT value FROM json_each(?2))
+                     ORDER BY position",
+                    params![since, also],
+                )?,
+                deleted: query::<Tombstone>(
+                    tx,
+                    "SELECT data FROM tombstones
+                     WHERE seq > ?1 OR id IN (SELECT value FROM json_each(?2))
+                     ORDER BY seq",
+                    params![since, also],
+                )?,
+            })
+        })
+    }
+
+    fn prune_tombstones(&self, cutoff: DateTime<Utc>) -> Result<(), StoreError> {
+        let cutoff = cutoff.timestamp_micros();
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(failed)?;
+        let latest: Option<u64> = tx
+            .query_row(
+                "SELECT MAX(seq) FROM tombstones WHERE deleted_at < ?1",
+                [cutoff],
+                |row| row.get(0),
+            )
+            .map_err(failed)?;
+        if let Some(latest) = latest {
+            tx.execute("UPDATE sync_state SET horizon = MAX(horizon, ?1)", [latest])
+                .map_err(failed)?;
+            tx.execute("DELETE FROM tombstones WHERE deleted_at < ?1", [cutoff])
+                .map_err(failed)?;
+        }
+        tx.commit().map_err(failed)
+    }
+
+    fn tree(&self, id: &str) -> Result<Option<TaskTree>, StoreError> {
+        Ok(graph::tree(&self.read(all_tasks)?, id))
+    }
+
+    fn order(&self) -> Result<Vec<Task>, StoreError> {
+        Ok(graph::topological_order(&self.read(all_tasks)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::conformance::task;
+    use crate::repository::conformance_suite;
+
+    fn open() -> ((), SqliteStore) {
+        ((), SqliteStore::open(":memory:").unwrap())
+    }
+
+    conformance_suite!(open);
+
+    #[test]
+    fn reopening_keeps_tasks_and_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        let path = path.to_str().unwrap();
+        let store = SqliteStore::open(path).unwrap();
+        let a = store.insert(task("a")).unwrap().id.unwrap();
+        store.insert(task("b")).unwrap();
+        store.remove(&a).unwrap();
+        let before = store.changes(Some(0), &[]).unwrap();
+        drop(store);
+
+        let store = SqliteStore::open(path).unwrap();
+        let after = store.changes(Some(0), &[]).unwrap();
+        assert_eq!(after.token, before.token);
+        assert_eq!(after.tasks, before.tasks);
+        assert_eq!(after.deleted, before.deleted);
+        // Titles are checked against what was stored before.
+        assert!(store.insert(task("b")).is_err());
+    }
+
+    #[test]
+    fn newer_schemas_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        let path = path.to_str().unwrap();
+        drop(SqliteStore::open(path).unwrap());
+        let conn = Connection::open(path).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        drop(conn);
+
+        let err = SqliteStore::open(path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
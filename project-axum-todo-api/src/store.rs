@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+
+use crate::graph::{self, TaskTree};
+use crate::history::{self, Command, TaskChange};
+use crate::model::Task;
+use crate::persist::{self, Fsync, Record, State, Wal};
+use crate::repository::TaskRepository;
+use crate::schedule;
+use crate::schema::{SyncChange, UpdateTaskSchema};
+use crate::sync::{self, Changes, Stamp, Tombstone};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    NotFound(String),
+    TitleTaken(String),
+    /// The task's schedule or links do not make sense.
+    Invalid(String),
+    /// Titles of the open tasks keeping this one from being completed.
+    Blocked(Vec<String>),
+    /// The task with this id was changed after the command being undone or
+    /// redone.
+    Conflict(String),
+    /// The backend failed to read or to write; nothing was changed.
+    Persistence(String),
+}
+
+/// Tasks indexed by id, kept in insertion order so pages can be sliced out
+/// directly. Titles of open tasks are indexed as well to keep them unique
+/// without a scan.
+///
+/// Every method takes the lock for its own duration only, so readers never
+/// wait on each other and nothing holds the lock across an `.await`. A store
+/// opened on a directory logs each change before making it; see [`persist`].
+#[derive(Debug, Default)]
+pub struct TaskStore {
+    inner: RwLock<Inner>,
+    /// Keeps an older snapshot from replacing a newer one.
+    compaction: Mutex<()>,
+}
+
+/// The tasks and the rules every change to them follows, whichever backend
+/// keeps them.
+#[derive(Debug, Default)]
+pub(crate) struct Inner {
+    pub(crate) tasks: IndexMap<String, Task>,
+    /// Title to id, for open tasks.
+    titles: HashMap<String, String>,
+    /// Deleted tasks, oldest first.
+    pub(crate) tombstones: IndexMap<String, Tombstone>,
+    /// The last sequence number handed out.
+    pub(crate) seq: u64,
+    /// Changes up to here may be missing tombstones.
+    pub(crate) horizon: u64,
+    pub(crate) journal: Journal,
+}
+
+/// Where changes are recorded before they are made.
+#[derive(Debug, Default)]
+pub(crate) enum Journal {
+    /// Nowhere; the tasks only live in memory.
+    #[default]
+    None,
+    Wal(Wal),
+    /// Kept for the caller to write out, in order.
+    Batch(Vec<Record>),
+}
+
+impl Inner {
+    pub(crate) fn new(state: State, journal: Journal) -> Inner {
+        let titles = state
+            .tasks
+            .iter()
+            .filter(|(_, task)| is_open(task))
+            .map(|(id, task)| (task.title.clone(), id.clone()))
+            .collect();
+        let seq = state
+            .tasks
+            .values()
+            .map(|task| task.versions.seq)
+            .chain(state.tombstones.values().map(|tombstone| tombstone.seq))
+            .fold(state.horizon, u64::max);
+        Inner {
+            tasks: state.tasks,
+            titles,
+            tombstones: state.tombstones,
+            seq,
+            horizon: state.horizon,
+            journal,
+        }
+    }
+
+    /// Called with the lock held, so the log is in the same order as the
+    /// changes. Returns what the records are about to change.
+    fn log(&mut self, records: &[Record]) -> Result<Vec<TaskChange>, StoreError> {
+        match &mut self.journal {
+            Journal::None => {}
+            Journal::Wal(wal) => wal
+                .append(records)
+                .map_err(|err| StoreError::Persistence(err.to_string()))?,
+            Journal::Batch(batch) => batch.extend_from_slice(records),
+        }
+        Ok(records
+            .iter()
+            .map(|record| match record {
+                Record::Put { task } => TaskChange {
+                    before: self.tasks.get(task.id.as_deref().unwrap()).cloned(),
+                    after: Some(task.clone()),
+                },
+                Record::Delete { id, .. } => TaskChange {
+                    before: self.tasks.get(id).cloned(),
+                    after: None,
+                },
+            })
+            .collect())
+    }
+
+    fn title_taken(&self, title: &str, id: &str) -> bool {
+        self.titles.get(title).is_some_and(|owner| owner != id)
+    }
+
+    /// Keeps the title index in step with replacing `old` by `new`.
+    fn index(&mut self, old: Option<&Task>, new: Option<&Task>) {
+        if let Some(old) = old.filter(|old| is_open(old)) {
+            if self.titles.get(&old.title) == old.id.as_ref() {
+                self.titles.remove(&old.title);
+            }
+        }
+        if let Some(new) = new.filter(|new| is_open(new)) {
+            let id = new.id.clone().expect("stored tasks have an id");
+            self.titles.insert(new.title.clone(), id);
+        }
+    }
+
+    /// Checks `task`, about to replace `old` or else be added, and stores it
+    /// with the next occurrence completing it may bring. The fields that
+    /// changed get `stamp`. The first change returned is the one to `task`.
+    pub(crate) fn commit(
+        &mut self,
+        old: Option<&Task>,
+        mut task: Task,
+        stamp: &Stamp,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let id = task.id.clone().expect("stored tasks have an id");
+        schedule::normalize(&mut task).map_err(StoreError::Invalid)?;
+        graph::check_links(&self.tasks, &mut task, old).map_err(StoreError::Invalid)?;
+        if is_open(&task) && self.title_taken(&task.title, &id) {
+            return Err(StoreError::TitleTaken(task.title));
+        }
+        let completing = old.is_some_and(|old| is_open(old) && !is_open(&task));
+        if completing {
+            let open_blockers: Vec<String> = task
+                .blockedBy
+                .iter()
+                .filter_map(|blocker| self.tasks.get(blocker))
+                .filter(|blocker| is_open(blocker))
+                .map(|blocker| blocker.title.clone())
+                .collect();
+            if !open_blockers.is_empty() {
+                return Err(StoreError::Blocked(open_blockers));
+            }
+        }
+
+        if old.is_some() {
+            task.updatedAt = Some(now);
+        }
+        let mut next = match completing {
+            true => schedule::next_occurrence(&task, now).map_err(StoreError::Invalid)?,
+            false => None,
+        };
+        if next.is_some() {
+            task.recurrence = None;
+        }
+        let mut seq = self.seq;
+        sync::stamp(old, &mut task, stamp);
+        seq += 1;
+        task.versions.seq = seq;
+        let mut records = vec![Record::Put { task: task.clone() }];
+        if let Some(next) = &mut next {
+            next.versions = Default::default();
+            sync::stamp(None, next, stamp);
+            seq += 1;
+            next.versions.seq = seq;
+            records.push(Record::Put { task: next.clone() });
+        }
+        let changes = self.log(&records)?;
+
+        self.seq = seq;
+        self.index(old, Some(&task));
+        // Only undoing a deletion brings a task back.
+        self.tombstones.shift_remove(&id);
+        self.tasks.insert(id, task);
+        if let Some(next) = next {
+            self.index(None, Some(&next));
+            self.tasks.insert(next.id.clone().unwrap(), next);
+        }
+        Ok(changes)
+    }
+
+    /// Applies the fields set in `changes` and bumps `updatedAt`. Completing a
+    /// recurring task hands its recurrence on to a new task for the next
+    /// occurrence.
+    pub(crate) fn update(
+        &mut self,
+        id: &str,
+        changes: UpdateTaskSchema,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let old = self
+            .tasks
+            .get(id)
+            .cloned()
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+
+        let mut task = old.clone();
+        if let Some(title) = changes.title {
+            task.title = title;
+        }
+        if let Some(content) = changes.content {
+            task.content = content;
+        }
+        if let Some(completed) = changes.completed {
+            task.completed = Some(completed);
+        }
+        if let Some(due_at) = changes.dueAt {
+            task.dueAt = due_at;
+        }
+        if let Some(priority) = changes.priority {
+            task.priority = Some(priority);
+        }
+        if let Some(recurrence) = changes.recurrence {
+            task.recurrence = recurrence;
+        }
+        if let Some(parent_id) = changes.parentId {
+            task.parentId = parent_id;
+        }
+        if let Some(blocked_by) = changes.blockedBy {
+            task.blockedBy = blocked_by;
+        }
+        self.commit(Some(&old), task, &Stamp::server(now), now)
+    }
+
+    /// Removes a task, leaving a tombstone. Subtasks move up to its parent,
+    /// and tasks it was blocking stop waiting for it. The last change
+    /// returned is the removal.
+    pub(crate) fn delete(
+        &mut self,
+        id: &str,
+        stamp: &Stamp,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let removed = self
+            .tasks
+            .get(id)
+            .cloned()
+            .ok_or_else(|| StoreError::NotFound(id.to_string()))?;
+
+        let mut seq = self.seq;
+        let linked: Vec<Task> = self
+            .tasks
+            .values()
+            .filter(|task| {
+                task.parentId.as_deref() == Some(id) || task.blockedBy.iter().any(|b| b == id)
+            })
+            .map(|old| {
+                let mut task = old.clone();
+                if task.parentId.as_deref() == Some(id) {
+                    task.parentId = removed.parentId.clone();
+                }
+                task.blockedBy.retain(|blocker| blocker != id);
+                task.updatedAt = Some(now);
+                sync::stamp(Some(old), &mut task, stamp);
+                seq += 1;
+                task.versions.seq = seq;
+                task
+            })
+            .collect();
+        seq += 1;
+        let tombstone = Tombstone {
+            id: id.to_string(),
+            seq,
+            deleted: stamp.clone(),
+        };
+        let mut records: Vec<Record> = linked
+            .iter()
+            .map(|task| Record::Put { task: task.clone() })
+            .collect();
+        records.push(Record::Delete {
+            id: id.to_string(),
+            tombstone: Some(tombstone.clone()),
+        });
+        let changes = self.log(&records)?;
+
+        self.seq = seq;
+        for task in linked {
+            self.tasks.insert(task.id.clone().unwrap(), task);
+        }
+        self.tasks.shift_remove(id);
+        self.index(Some(&removed), None);
+        self.tombstones.insert(id.to_string(), tombstone);
+        Ok(changes)
+    }
+
+    /// Runs `command` as the server, returning every task it touched.
+    pub(crate) fn execute(
+        &mut self,
+        command: Command,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let stamp = Stamp::server(now);
+        match command {
+            Command::Create(task) => self.commit(None, task, &stamp, now),
+            Command::Update { id, changes } => self.update(&id, changes, now),
+            Command::Delete { id } => self.delete(&id, &stamp, now),
+            Command::Revert(changes) => self.revert(&changes, &stamp, now),
+        }
+    }
+
+    /// Puts back every task in `changes` as it was before, undoing the
+    /// changes in reverse so each step is checked like the one it undoes.
+    /// The steps run on a copy first, so either all of them go through or
+    /// none does; undoing is rare enough to pay for the copy.
+    fn revert(
+        &mut self,
+        changes: &[TaskChange],
+        stamp: &Stamp,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        if let Some(change) = changes
+            .iter()
+            .find(|change| !history::unchanged(self.tasks.get(change.id()), change.after.as_ref()))
+        {
+            return Err(StoreError::Conflict(change.id().to_string()));
+        }
+
+        let mut scratch = Inner {
+            tasks: self.tasks.clone(),
+            titles: self.titles.clone(),
+            tombstones: self.tombstones.clone(),
+            seq: self.seq,
+            horizon: self.horizon,
+            journal: Journal::Batch(vec![]),
+        };
+        for change in changes.iter().rev() {
+            let id = change.id();
+            let current = scratch.tasks.get(id).cloned();
+            match (&change.before, current) {
+                (None, None) => {}
+                (None, Some(_)) => {
+                    scratch.delete(id, stamp, now)?;
+                }
+                (Some(before), current) => {
+                    let mut task = before.clone();
+                    if let Some(current) = &current {
+                        task.versions = current.versions.clone();
+                    }
+                    task.updatedAt = Some(now);
+                    scratch.commit(current.as_ref(), task, stamp, now)?;
+                }
+            }
+        }
+
+        let Journal::Batch(records) = std::mem::take(&mut scratch.journal) else {
+            unreachable!("the journal was set above");
+        };
+        let changes = self.log(&records)?;
+        scratch.journal = std::mem::take(&mut self.journal);
+        *self = scratch;
+        Ok(history::squash(changes))
+    }
+
+    /// See [`TaskRepository::merge`]. Only the tombstone of the changed task
+    /// needs to be loaded.
+    pub(crate) fn merge(
+        &mut self,
+        origin: &str,
+        change: SyncChange,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TaskChange>, StoreError> {
+        let stamp = Stamp {
+            at: change.changedAt.min(now),
+            origin: origin.to_string(),
+        };
+        let id = change.id.to_string();
+        if self.tombstones.contains_key(&id) {
+            return Ok(vec![]);
+        }
+        let old = self.tasks.get(&id).cloned();
+
+        if change.deleted {
+            return match old {
+                Some(old) if old.versions.older_than(&stamp) => self.delete(&id, &stamp, now),
+                _ => Ok(vec![]),
+            };
+        }
+        let mut task = match &old {
+            Some(old) => old.clone(),
+            None if !change.fields.contains_key(&sync::Field::Title) => {
+                return Err(StoreError::Invalid(format!(
+                    "Todo with ID: {} not found, and a new todo needs a title",
+                    id
+                )));
+            }
+            None => Task {
+                id: Some(id),
+                completed: Some(false),
+                priority: Some(Default::default()),
+                createdAt: Some(now),
+                updatedAt: Some(now),
+                ..Default::default()
+            },
+        };
+        if !sync::merge(&mut task, change.fields, &stamp).map_err(StoreError::Invalid)? {
+            return Ok(vec![]);
+        }
+        self.commit(old.as_ref(), task, &stamp, now)
+    }
+}
+
+/// Only open tasks need unique titles, so that the next occurrence of a
+/// recurring task can keep the title of the one just completed.
+fn is_open(task: &Task) -> bool {
+    task.completed != Some(true)
+}
+
+/// The page of `tasks` matching `filter`, in `order` or else as they come.
+pub(crate) fn select<'a>(
+    tasks: impl Iterator<Item = &'a Task>,
+    filter: &dyn Fn(&Task) -> bool,
+    order: Option<fn(&Task, &Task) -> Ordering>,
+    offset: usize,
+    limit: usize,
+) -> Vec<Task> {
+    let mut matching: Vec<&Task> = tasks.filter(|task| filter(task)).collect();
+    if let Some(order) = order {
+        matching.sort_by(|a, b| order(a, b));
+    }
+    matching
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+impl TaskStore {
+    /// A store that only lives in memory.
+    pub fn new() -> TaskStore {
+        TaskStore::default()
+    }
+
+    /// A store persisted in `dir`, holding whatever was saved there before.
+    pub fn open(dir: &Path, fsync: Fsync) -> io::Result<TaskStore> {
+        let (state, wal) = persist::load(dir, fsync)?;
+        Ok(TaskStore {
+            inner: RwLock::new(Inner::new(state, Journal::Wal(wal))),
+            compaction: Mutex::new(()),
+        })
+    }
+
+    // A panic while holding the lock cannot leave the maps half-updated
+    // (every method checks before it writes), so poisoning is ignored.
+    fn read(&self) -> RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl TaskRepository for TaskStore {
+    fn len(&self) -> Result<usize, StoreError> {
+        Ok(self.read().tasks.len())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Task>, StoreError> {
+        Ok(self.read().tasks.get(id).cloned())
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Task>, StoreError> {
+        let inner = self.read();
+        let end = offset.saturating_add(limit).min(inner.tasks.len());
+        Ok(match inner.tasks.get_range(offset..end) {
+            Some(page) => page.values().cloned().collect(),
+            None => vec![],
+        })
+    }
+
+    fn select(
+        &self,
+        filter: &dyn Fn(&Task) -> bool,
+        order: Option<fn(&Task, &Task) -> Ordering>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Task>, StoreError> {
+        Ok(select(
+            self.read().tasks.values(),
+            filter,
+            order,
+            offset,
+            limit,
+        ))
+    }
+
+    /// Removing a task shifts the ones after it to keep the order, which costs
+    /// O(n); lookups and pages stay O(1) in exchange.
+    fn execute(&self, command: Command) -> Result<Vec<TaskChange>, StoreError> {
+        self.write().execute(command, Utc::now())
+    }
+
+    fn merge(&self, origin: &str, change: SyncChange) -> Result<Vec<TaskChange>, StoreError> {
+        self.write().merge(origin, change, Utc::now())
+    }
+
+    fn changes(&self, since: Option<u64>, also: &[String]) -> Result<Changes, StoreError> {
+        let inner = self.read();
+        let Some(since) = since.filter(|since| (inner.horizon..=inner.seq).contains(since)) else {
+            return Ok(Changes {
+                token: inner.seq,
+                reset: true,
+                tasks: inner.tasks.values().cloned().collect(),
+                deleted: vec![],
+            });
+        };
+        let wanted = |id: &String, seq: u64| seq > since || also.contains(id);
+        Ok(Changes {
+            token: inner.seq,
+            reset: false,
+            tasks: inner
+                .tasks
+                .iter()
+                .filter(|(id, task)| wanted(id, task.versions.seq))
+                .map(|(_, task)| task.clone())
+                .collect(),
+            deleted: inner
+                .tombstones
+                .iter()
+                .filter(|(id, tombstone)| wanted(id, tombstone.seq))
+                .map(|(_, tombstone)| tombstone.clone())
+                .collect(),
+        })
+    }
+
+    fn prune_tombstones(&self, cutoff: DateTime<Utc>) -> Result<(), StoreError> {
+        let mut inner = self.write();
+        let mut horizon = inner.horizon;
+        inner.tombstones.retain(|_, tombstone| {
+            let expired = tombstone.deleted.at < cutoff;
+            if expired {
+                horizon = horizon.max(tombstone.seq);
+            }
+            !expired
+        });
+        inner.horizon = horizon;
+        Ok(())
+    }
+
+    fn tree(&self, id: &str) -> Result<Option<TaskTree>, StoreError> {
+        Ok(graph::tree(&self.read().tasks, id))
+    }
+
+    fn order(&self) -> Result<Vec<Task>, StoreError> {
+        Ok(graph::topological_order(&self.read().tasks))
+    }
+
+    /// Writes a snapshot and drops the log it makes redundant.
+    fn compact(&self) -> io::Result<bool> {
+        let _compaction = self
+            .compaction
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let (dir, generation, state) = {
+            let mut inner = self.write();
+            let Journal::Wal(wal) = &mut inner.journal else {
+                return Ok(false);
+            };
+            if wal.records() == 0 {
+                return Ok(false);
+            }
+            let generation = wal.rotate()?;
+            let dir = wal.dir().to_path_buf();
+            let state = State {
+                tasks: inner.tasks.clone(),
+                tombstones: inner.tombstones.clone(),
+                horizon: inner.horizon,
+            };
+            (dir, generation, state)
+        };
+        persist::write_snapshot(&dir, generation, state)?;
+        Ok(true)
+    }
+
+    /// Flushes logged changes to disk, for [`Fsync::Every`].
+    fn sync(&self) -> io::Result<()> {
+        let file = match &mut self.write().journal {
+            Journal::Wal(wal) => wal.take_unsynced()?,
+            _ => None,
+        };
+        match file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::conformance_suite;
+
+    mod memory {
+        use super::*;
+
+        fn open() -> ((), TaskStore) {
+            ((), TaskStore::new())
+        }
+
+        conformance_suite!(open);
+    }
+
+    mod wal {
+        use super::*;
+
+        fn open() -> (tempfile::TempDir, TaskStore) {
+            let dir = tempfile::tempdir().unwrap();
+            let store = TaskStore::open(dir.path(), Fsync::Never).unwrap();
+            (dir, store)
+        }
+
+        conformance_suite!(open);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::model::Task;
+
+// Clients that were offline send their edits as changes to single fields, and
+// every field of a task remembers the stamp of the change that last set it. An
+// incoming value only replaces a field when its stamp is later, so replicas
+// that see the same changes in any order end up with the same tasks.
+//
+// Every stored change also takes the next number of a store-wide sequence. A
+// sync token is the last number a client has seen; what changed since then is
+// every task and tombstone with a higher one.
+
+/// Origin of the changes made through the rest of the API.
+pub const SERVER: &str = "server";
+
+/// When a field was set, and by whom. Later stamps win; the same instant is
+/// settled by comparing origins, so the outcome never depends on arrival order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Stamp {
+    pub at: DateTime<Utc>,
+    pub origin: String,
+}
+
+impl Stamp {
+    pub fn server(at: DateTime<Utc>) -> Stamp {
+        Stamp {
+            at,
+            origin: SERVER.to_string(),
+        }
+    }
+}
+
+/// The fields of a task that clients can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Field {
+    Title,
+    Content,
+    Completed,
+    DueAt,
+    Priority,
+    Recurrence,
+    ParentId,
+    BlockedBy,
+}
+
+impl Field {
+    pub const ALL: [Field; 8] = [
+        Field::Title,
+        Field::Content,
+        Field::Completed,
+        Field::DueAt,
+        Field::Priority,
+        Field::Recurrence,
+        Field::ParentId,
+        Field::BlockedBy,
+    ];
+
+    fn get(self, task: &Task) -> Value {
+        match self {
+            Field::Title => json!(task.title),
+            Field::Content => json!(task.content),
+            Field::Completed => json!(task.completed),
+            Field::DueAt => json!(task.dueAt),
+            Field::Priority => json!(task.priority),
+            Field::Recurrence => json!(task.recurrence),
+            Field::ParentId => json!(task.parentId),
+            Field::BlockedBy => json!(task.blockedBy),
+        }
+    }
+
+    fn set(self, task: &mut Task, value: Value) -> serde_json::Result<()> {
+        match self {
+            Field::Title => task.title = serde_json::from_value(value)?,
+            Field::Content => task.content = serde_json::from_value(value)?,
+            Field::Completed => task.completed = serde_json::from_value(value)?,
+            Field::DueAt => task.dueAt = serde_json::from_value(value)?,
+            Field::Priority => task.priority = serde_json::from_value(value)?,
+            Field::Recurrence => task.recurrence = serde_json::from_value(value)?,
+            Field::ParentId => task.parentId = serde_json::from_value(value)?,
+            Field::BlockedBy => task.blockedBy = serde_json::from_value(value)?,
+        }
+        Ok(())
+    }
+}
+
+/// Where a task stands in the sync: the sequence number of its last change,
+/// and the stamp of each field. Fields set before syncing existed have none
+/// and lose to any change.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Versions {
+    pub seq: u64,
+    #[serde(default)]
+    pub fields: BTreeMap<Field, Stamp>,
+}
+
+impl Versions {
+    /// Whether a change stamped `stamp` came after every field was last set.
+    pub fn older_than(&self, stamp: &Stamp) -> bool {
+        self.fields.values().all(|field| field < stamp)
+    }
+}
+
+/// What is left of a deleted task, so that clients hear of the deletion and
+/// later edits of it are dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub id: String,
+    pub seq: u64,
+    pub deleted: Stamp,
+}
+
+/// Gives `stamp` to every field of `task` that differs from `old`, or to all
+/// of them for a new task.
+pub fn stamp(old: Option<&Task>, task: &mut Task, stamp: &Stamp) {
+    for field in Field::ALL {
+        if old.is_none_or(|old| field.get(old) != field.get(task)) {
+            task.versions.fields.insert(field, stamp.clone());
+        }
+    }
+}
+
+/// Sets the fields for which `stamp` is later than what `task` holds, and
+/// returns whether any was.
+pub fn merge(
+    task: &mut Task,
+    fields: BTreeMap<Field, Value>,
+    stamp: &Stamp,
+) -> Result<bool, String> {
+    let mut merged = false;
+    for (field, value) in fields {
+        if task
+            .versions
+            .fields
+            .get(&field)
+            .is_some_and(|current| current >= stamp)
+        {
+            continue;
+        }
+        field
+            .set(task, value)
+            .map_err(|err| format!("Invalid {}: {}", json!(field).as_str().unwrap(), err))?;
+        task.versions.fields.insert(field, stamp.clone());
+        merged = true;
+    }
+    Ok(merged)
+}
+
+/// What a client needs to catch up.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// To be sent back next time.
+    pub token: u64,
+    /// The token was too old or unknown, so `tasks` holds every task and
+    /// replaces whatever the client had.
+    pub reset: bool,
+    pub tasks: Vec<Task>,
+    pub deleted: Vec<Tombstone>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(origin: &str, seconds: i64) -> Stamp {
+        Stamp {
+            at: DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap(),
+            origin: origin.to_string(),
+        }
+    }
+
+    fn fields(changes: Value) -> BTreeMap<Field, Value> {
+        serde_json::from_value(changes).unwrap()
+    }
+
+    #[test]
+    fn later_changes_win_field_by_field() {
+        let mut task = Task::default();
+        stamp(None, &mut task, &at(SERVER, 0));
+
+        let phone = fields(json!({ "title": "phone", "completed": true }));
+        assert!(merge(&mut task, phone, &at("phone", 20)).unwrap());
+        // Older than the title from the phone, newer than the content.
+        let laptop = fields(json!({ "title": "laptop", "content": "notes" }));
+        assert!(merge(&mut task, laptop, &at("laptop", 10)).unwrap());
+
+        assert_eq!(task.title, "phone");
+        assert_eq!(task.content, "notes");
+        assert_eq!(task.completed, Some(true));
+        assert_eq!(task.versions.fields[&Field::Content], at("laptop", 10));
+        assert_eq!(task.versions.fields[&Field::Priority], at(SERVER, 0));
+    }
+
+    #[test]
+    fn ties_are_settled_the_same_in_any_order() {
+        let a = (fields(json!({ "title": "a" })), at("a", 5));
+        let b = (fields(json!({ "title": "b" })), at("b", 5));
+
+        for order in [[&a, &b], [&b, &a]] {
+            let mut task = Task::default();
+            for (changes, stamp) in order {
+                merge(&mut task, changes.clone(), stamp).unwrap();
+            }
+            assert_eq!(task.title, "b");
+        }
+
+        // Replaying a change is a no-op.
+        let mut task = Task::default();
+        assert!(merge(&mut task, b.0.clone(), &b.1).unwrap());
+        assert!(!merge(&mut task, b.0, &b.1).unwrap());
+    }
+
+    #[test]
+    fn values_are_checked() {
+        let mut task = Task::default();
+        let bad = fields(json!({ "completed": "yes" }));
+        assert!(merge(&mut task, bad, &at("phone", 0))
+            .unwrap_err()
+            .starts_with("Invalid completed"));
+    }
+
+    #[test]
+    fn only_changed_fields_are_stamped() {
+        let old = Task::default();
+        let mut task = old.clone();
+        task.title = "renamed".to_string();
+        stamp(Some(&old), &mut task, &at(SERVER, 1));
+        assert_eq!(
+            task.versions.fields.keys().collect::<Vec<_>>(),
+            [&Field::Title]
+        );
+        assert!(!task.versions.older_than(&at(SERVER, 1)));
+        assert!(task.versions.older_than(&at("phone", 2)));
+    }
+}